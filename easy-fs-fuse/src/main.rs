@@ -1,5 +1,5 @@
 use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+use easy_fs::{block_cache_sync_all, BlockDevice, EasyFileSystem};
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
@@ -84,6 +84,8 @@ fn easy_fs_pack() -> std::io::Result<()> {
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
+    // the block cache writes back lazily
+    block_cache_sync_all();
     // list apps
     for app in root_inode.ls() {
         println!("{}", app);
@@ -99,7 +101,7 @@ fn efs_test() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open("target/fs.img")?;
-        f.set_len(BLOCK_NUM * BLOCK_SZ).unwrap();
+        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64).unwrap();
         f
     })));
     EasyFileSystem::create(block_file.clone(), 4096, 1);
@@ -151,5 +153,67 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(1000 * BLOCK_SZ);
     random_str_test(2000 * BLOCK_SZ);
 
+    // hard links share the inode, which goes away with its last link
+    let ino = filea.inode_id();
+    root_inode.link("linka", &filea).unwrap();
+    let linka = root_inode.find("linka").unwrap();
+    assert_eq!(linka.inode_id(), ino);
+    assert_eq!(filea.nlink(), 2);
+    assert!(root_inode.link("fileb", &filea).is_none());
+    assert!(root_inode.unlink("filea").is_some());
+    assert!(root_inode.find("filea").is_none());
+    assert_eq!(linka.nlink(), 1);
+    let len = linka.read_at(0, &mut buffer);
+    assert!(len > 0);
+    assert!(root_inode.unlink("linka").is_some());
+    assert!(root_inode.unlink("linka").is_none());
+    assert_eq!(root_inode.ls(), vec!["fileb"]);
+    // an unlinked inode in use is freed with its last reference
+    assert_eq!(linka.nlink(), 0);
+    assert_eq!(linka.read_at(0, &mut buffer), len);
+    drop(filea);
+    drop(linka);
+    assert_eq!(root_inode.create("filec").unwrap().inode_id(), ino);
+
+    // directories and path walking
+    let dira = root_inode.mkdir("dira").unwrap();
+    assert!(dira.is_dir());
+    assert_eq!(dira.nlink(), 2);
+    assert_eq!(root_inode.nlink(), 3);
+    assert!(root_inode.mkdir("dira").is_none());
+    let dirb = dira.mkdir("dirb").unwrap();
+    dirb.create("filed").unwrap().write_at(0, greet_str.as_bytes());
+    let filed = root_inode.find("/dira/./dirb/../dirb/filed").unwrap();
+    let len = filed.read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    assert_eq!(dirb.find("../..").unwrap().inode_id(), 0);
+    assert!(root_inode.find("fileb/filed").is_none());
+    assert!(root_inode.rmdir("dira").is_none());
+    assert!(dira.unlink("dirb").is_none());
+    assert!(dirb.unlink("filed").is_some());
+    assert!(dira.rmdir("dirb").is_some());
+    assert!(root_inode.rmdir("dira").is_some());
+    // a removed directory still in use takes no new entries
+    assert_eq!(dira.nlink(), 0);
+    assert!(dira.create("filee").is_none());
+    assert!(dira.mkdir("dirc").is_none());
+    assert_eq!(root_inode.nlink(), 2);
+    assert_eq!(root_inode.ls(), vec!["fileb", "filec"]);
+
+    // the block cache writes back lazily, the data is on the disk after a sync
+    let sync_str = format!("Synced {}!", rand::random::<u64>());
+    let on_disk = || -> std::io::Result<bool> {
+        let mut image = Vec::new();
+        File::open("target/fs.img")?.read_to_end(&mut image)?;
+        Ok(image
+            .windows(sync_str.len())
+            .any(|window| window == sync_str.as_bytes()))
+    };
+    let filec = root_inode.find("filec").unwrap();
+    filec.write_at(0, sync_str.as_bytes());
+    assert!(!on_disk()?);
+    block_cache_sync_all();
+    assert!(on_disk()?);
+
     Ok(())
 }
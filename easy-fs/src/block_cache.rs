@@ -2,8 +2,9 @@ use super::{
     BLOCK_SZ,
     BlockDevice,
 };
-use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -76,54 +77,169 @@ impl Drop for BlockCache {
     }
 }
 
-/// Use a block cache of 16 blocks
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Number of blocks cached by default
+pub const BLOCK_CACHE_SIZE: usize = 64;
 
+/// A cached block, `referenced` is the reference bit of the CLOCK replacement
+struct CacheSlot {
+    block_id: usize,
+    cache: Arc<Mutex<BlockCache>>,
+    referenced: bool,
+}
+
+/// Block caches found by block id through a hash table, the one to be
+/// replaced is chosen by the CLOCK algorithm. Dirty blocks are written
+/// back when replaced or synced.
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    capacity: usize,
+    slots: Vec<CacheSlot>,
+    /// Indices into `slots`, the bucket of a block is `block_id % buckets.len()`
+    buckets: Vec<Vec<usize>>,
+    /// Clock hand, the next slot to look at for replacement
+    hand: usize,
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
-        Self { queue: VecDeque::new() }
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        let mut manager = Self {
+            capacity,
+            slots: Vec::new(),
+            buckets: Vec::new(),
+            hand: 0,
+        };
+        manager.rehash();
+        manager
+    }
+
+    /// Change the number of cached blocks, extra blocks are replaced lazily
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        self.rehash();
+    }
+
+    /// Rebuild the hash table with about one bucket per block
+    fn rehash(&mut self) {
+        self.buckets = vec![Vec::new(); self.capacity.next_power_of_two()];
+        for (idx, slot) in self.slots.iter().enumerate() {
+            let bucket = slot.block_id % self.buckets.len();
+            self.buckets[bucket].push(idx);
+        }
+    }
+
+    fn bucket_of(&mut self, block_id: usize) -> &mut Vec<usize> {
+        let bucket = block_id % self.buckets.len();
+        &mut self.buckets[bucket]
+    }
+
+    fn find(&self, block_id: usize) -> Option<usize> {
+        self.buckets[block_id % self.buckets.len()]
+            .iter()
+            .copied()
+            .find(|&idx| self.slots[idx].block_id == block_id)
+    }
+
+    /// Pick a block nobody is using with the CLOCK algorithm. A clean one is
+    /// dropped from the hash table and its slot returned, a dirty one is
+    /// returned to be written back first, without the manager locked.
+    fn evict(&mut self) -> Result<usize, Arc<Mutex<BlockCache>>> {
+        // one round clears the reference bits, the next one finds a victim
+        for _ in 0..2 * self.slots.len() {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let slot = &mut self.slots[idx];
+            if Arc::strong_count(&slot.cache) > 1 {
+                continue;
+            }
+            if slot.referenced {
+                slot.referenced = false;
+                continue;
+            }
+            if slot.cache.lock().modified {
+                return Err(Arc::clone(&slot.cache));
+            }
+            let block_id = slot.block_id;
+            self.bucket_of(block_id).retain(|&i| i != idx);
+            return Ok(idx);
+        }
+        panic!("Run out of BlockCache!");
     }
 
+    /// Drop the block in slot `idx`, which is out of the hash table,
+    /// moving the last slot into its place
+    fn remove_slot(&mut self, idx: usize) {
+        self.slots.swap_remove(idx);
+        if idx < self.slots.len() {
+            let moved = self.slots.len();
+            let block_id = self.slots[idx].block_id;
+            for i in self.bucket_of(block_id).iter_mut() {
+                if *i == moved {
+                    *i = idx;
+                }
+            }
+        }
+        if self.hand >= self.slots.len() {
+            self.hand = 0;
+        }
+    }
+
+    /// Get the block cache of `block_id`, loading it from the disk if it is
+    /// not cached. `Err` gives a dirty victim to write back before trying again.
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue
-            .iter()
-            .find(|pair| pair.0 == block_id) {
-                Arc::clone(&pair.1)
+    ) -> Result<Arc<Mutex<BlockCache>>, Arc<Mutex<BlockCache>>> {
+        if let Some(idx) = self.find(block_id) {
+            let slot = &mut self.slots[idx];
+            slot.referenced = true;
+            return Ok(Arc::clone(&slot.cache));
+        }
+        // substitute, the capacity may have been lowered
+        while self.slots.len() > self.capacity {
+            let idx = self.evict()?;
+            self.remove_slot(idx);
+        }
+        let idx = if self.slots.len() == self.capacity {
+            Some(self.evict()?)
         } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self.queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1) {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+            None
+        };
+        // load block into mem
+        let block_cache = Arc::new(Mutex::new(
+            BlockCache::new(block_id, Arc::clone(&block_device))
+        ));
+        let slot = CacheSlot {
+            block_id,
+            cache: Arc::clone(&block_cache),
+            referenced: true,
+        };
+        let idx = match idx {
+            Some(idx) => {
+                self.slots[idx] = slot;
+                idx
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(
-                BlockCache::new(block_id, Arc::clone(&block_device))
-            ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
-        }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.bucket_of(block_id).push(idx);
+        Ok(block_cache)
+    }
+
+    /// All the cached blocks, to be synced without the manager locked
+    fn caches(&self) -> Vec<Arc<Mutex<BlockCache>>> {
+        self.slots.iter().map(|slot| Arc::clone(&slot.cache)).collect()
     }
+
 }
 
 lazy_static! {
     /// The global block cache manager
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> = Mutex::new(
-        BlockCacheManager::new()
+        BlockCacheManager::new(BLOCK_CACHE_SIZE)
     );
 }
 
@@ -132,13 +248,28 @@ pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>
 ) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id, block_device)
+    loop {
+        let victim = match BLOCK_CACHE_MANAGER
+            .lock()
+            .get_block_cache(block_id, Arc::clone(&block_device))
+        {
+            Ok(block_cache) => return block_cache,
+            Err(victim) => victim,
+        };
+        // the disk is not written with the manager locked
+        victim.lock().sync();
+    }
 }
 
 /// Sync all block cache to block device
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    let caches = BLOCK_CACHE_MANAGER.lock().caches();
+    for cache in caches {
         cache.lock().sync();
     }
 }
+
+/// Set the number of blocks kept in the block cache
+pub fn set_block_cache_capacity(capacity: usize) {
+    BLOCK_CACHE_MANAGER.lock().set_capacity(capacity);
+}
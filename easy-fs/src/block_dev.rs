@@ -5,4 +5,6 @@ use core::any::Any;
 pub trait BlockDevice : Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Handle an interrupt of the device, nothing to do for devices without any
+    fn handle_irq(&self) {}
 }
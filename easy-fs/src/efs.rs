@@ -1,3 +1,4 @@
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use spin::Mutex;
 use super::{
@@ -6,7 +7,9 @@ use super::{
     SuperBlock,
     DiskInode,
     DiskInodeType,
+    DirEntry,
     Inode,
+    DIRENT_SZ,
     get_block_cache,
     block_cache_sync_all,
 };
@@ -19,6 +22,8 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    /// Number of in-memory `Inode`s of each inode id
+    open_inodes: BTreeMap<u32, usize>,
 }
 
 /// A data block of block size
@@ -50,6 +55,7 @@ impl EasyFileSystem {
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            open_inodes: BTreeMap::new(),
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -85,6 +91,20 @@ impl EasyFileSystem {
         .lock()
         .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
             disk_inode.initialize(DiskInodeType::Directory);
+            // both "." and ".." of the root refer to itself
+            disk_inode.nlink = 2;
+            let new_size = (2 * DIRENT_SZ) as u32;
+            let new_blocks = (0..disk_inode.blocks_num_needed(new_size))
+                .map(|_| efs.alloc_data())
+                .collect();
+            disk_inode.increase_size(new_size, new_blocks, &block_device);
+            for (i, name) in [".", ".."].iter().enumerate() {
+                disk_inode.write_at(
+                    i * DIRENT_SZ,
+                    DirEntry::new(name, 0).as_bytes(),
+                    &block_device,
+                );
+            }
         });
         block_cache_sync_all();
         Arc::new(Mutex::new(efs))
@@ -110,6 +130,7 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    open_inodes: BTreeMap::new(),
                 };
                 Arc::new(Mutex::new(efs))
             })
@@ -134,6 +155,32 @@ impl EasyFileSystem {
         let block_id = self.inode_area_start_block + inode_id / inodes_per_block;
         (block_id, (inode_id % inodes_per_block) as usize * inode_size)
     }
+    /// Get inode id by the position of its disk inode
+    pub fn get_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        (block_id - self.inode_area_start_block) * inodes_per_block
+            + (block_offset / inode_size) as u32
+    }
+    /// Count a new in-memory reference to an inode
+    pub(crate) fn open_inode(&mut self, inode_id: u32) {
+        *self.open_inodes.entry(inode_id).or_insert(0) += 1;
+    }
+    /// Drop an in-memory reference to an inode, returns whether it was the last one
+    pub(crate) fn close_inode(&mut self, inode_id: u32) -> bool {
+        let count = self.open_inodes.get_mut(&inode_id).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.open_inodes.remove(&inode_id);
+            true
+        } else {
+            false
+        }
+    }
+    /// Whether an inode is referenced in memory
+    pub(crate) fn is_inode_open(&self, inode_id: u32) -> bool {
+        self.open_inodes.contains_key(&inode_id)
+    }
     /// Get data block by id
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
@@ -142,6 +189,10 @@ impl EasyFileSystem {
     pub fn alloc_inode(&mut self) -> u32 {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
+    /// Deallocate an inode
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap.dealloc(&self.block_device, inode_id as usize)
+    }
     /// Allocate a data block
     pub fn alloc_data(&mut self) -> u32 {
         self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
@@ -16,4 +16,5 @@ pub use efs::EasyFileSystem;
 pub use vfs::Inode;
 use layout::*;
 use bitmap::Bitmap;
-use block_cache::{get_block_cache, block_cache_sync_all};
+pub use block_cache::{block_cache_sync_all, set_block_cache_capacity, BLOCK_CACHE_SIZE};
+use block_cache::get_block_cache;
@@ -5,8 +5,8 @@ use super::{
     DirEntry,
     EasyFileSystem,
     DIRENT_SZ,
+    NAME_LENGTH_LIMIT,
     get_block_cache,
-    block_cache_sync_all,
 };
 use alloc::sync::Arc;
 use alloc::string::String;
@@ -29,6 +29,19 @@ impl Inode {
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
+        let efs = Arc::clone(&fs);
+        let mut efs = efs.lock();
+        Self::open(block_id, block_offset, &mut efs, fs, block_device)
+    }
+    /// Create a vfs inode with the efs lock held
+    fn open(
+        block_id: u32,
+        block_offset: usize,
+        efs: &mut EasyFileSystem,
+        fs: Arc<Mutex<EasyFileSystem>>,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        efs.open_inode(efs.get_inode_id(block_id, block_offset));
         Self {
             block_id: block_id as usize,
             block_offset,
@@ -36,6 +49,25 @@ impl Inode {
             block_device,
         }
     }
+    /// Get the inode id of current inode
+    pub fn inode_id(&self) -> u32 {
+        self.fs.lock().get_inode_id(self.block_id as u32, self.block_offset)
+    }
+    /// Get the number of hard links to current inode
+    pub fn nlink(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+    /// Get the size of current inode in bytes
+    pub fn size(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size)
+    }
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
     /// Call a function over a disk inode to read it
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         get_block_cache(
@@ -75,21 +107,31 @@ impl Inode {
         }
         None
     }
-    /// Find inode under current inode by name
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode)
-            .map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
-        })
+    /// Find inode under current inode by a path relative to it,
+    /// components are separated by '/' and may contain "." or ".."
+    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        let mut block_id = self.block_id as u32;
+        let mut block_offset = self.block_offset;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let inode_id = get_block_cache(
+                block_id as usize,
+                Arc::clone(&self.block_device)
+            ).lock().read(block_offset, |disk_inode: &DiskInode| {
+                if !disk_inode.is_dir() {
+                    return None;
+                }
+                self.find_inode_id(name, disk_inode)
+            })?;
+            (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        }
+        Some(Arc::new(Self::open(
+            block_id,
+            block_offset,
+            &mut fs,
+            self.fs.clone(),
+            self.block_device.clone(),
+        )))
     }
     /// Increase the size of a disk inode
     fn increase_size(
@@ -108,17 +150,126 @@ impl Inode {
         }
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
-    /// Create inode under current inode by name
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
-        if self.modify_disk_inode(|root_inode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
+    /// Whether `name` can be used as a new directory entry
+    fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= NAME_LENGTH_LIMIT
+            && !name.contains('/')
+            && name != "."
+            && name != ".."
+    }
+    /// Append a directory entry to a directory disk inode
+    fn append_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        dir_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+        let new_size = (file_count + 1) * DIRENT_SZ;
+        // increase size
+        self.increase_size(new_size as u32, dir_inode, fs);
+        // write dirent
+        let dirent = DirEntry::new(name, inode_id);
+        dir_inode.write_at(
+            file_count * DIRENT_SZ,
+            dirent.as_bytes(),
+            &self.block_device,
+        );
+    }
+    /// Remove the directory entry `name` from a directory disk inode,
+    /// returns the inode id it referred to
+    fn remove_dirent(
+        &self,
+        name: &str,
+        dir_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) -> Option<u32> {
+        let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+        let mut dirents: Vec<DirEntry> = Vec::with_capacity(file_count);
+        let mut inode_id = None;
+        for i in 0..file_count {
+            let mut dirent = DirEntry::empty();
+            assert_eq!(
+                dir_inode.read_at(
+                    DIRENT_SZ * i,
+                    dirent.as_bytes_mut(),
+                    &self.block_device,
+                ),
+                DIRENT_SZ,
+            );
+            if inode_id.is_none() && dirent.name() == name {
+                inode_id = Some(dirent.inode_number());
+            } else {
+                dirents.push(dirent);
+            }
+        }
+        let inode_id = inode_id?;
+        // rebuild the dirent without the removed entry
+        for data_block in dir_inode.clear_size(&self.block_device) {
+            fs.dealloc_data(data_block);
+        }
+        self.increase_size((dirents.len() * DIRENT_SZ) as u32, dir_inode, fs);
+        for (i, dirent) in dirents.iter().enumerate() {
+            dir_inode.write_at(
+                i * DIRENT_SZ,
+                dirent.as_bytes(),
+                &self.block_device,
+            );
+        }
+        Some(inode_id)
+    }
+    /// Free an inode without links along with its data blocks
+    fn free_inode(&self, inode_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let data_blocks_dealloc = get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device)
+        ).lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+            disk_inode.clear_size(&self.block_device)
+        });
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.dealloc_data(data_block);
+        }
+        fs.dealloc_inode(inode_id);
+    }
+    /// Look up `name` under current directory and tell whether it is a directory
+    fn find_child(
+        &self,
+        name: &str,
+        fs: &MutexGuard<EasyFileSystem>,
+    ) -> Option<(u32, bool)> {
+        let inode_id = self.read_disk_inode(|root_inode| {
+            if !root_inode.is_dir() {
+                return None;
+            }
             self.find_inode_id(name, root_inode)
-        }).is_some() {
+        })?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let is_dir = get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device)
+        ).lock().read(block_offset, |disk_inode: &DiskInode| disk_inode.is_dir());
+        Some((inode_id, is_dir))
+    }
+    /// Create an inode of the given type under current inode by name
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if !Self::is_valid_name(name) {
             return None;
         }
+        let mut fs = self.fs.lock();
+        if self.read_disk_inode(|root_inode| {
+            // only a directory not removed yet may hold new entries
+            !root_inode.is_dir()
+                || root_inode.nlink == 0
+                // has the file been created?
+                || self.find_inode_id(name, root_inode).is_some()
+        }) {
+            return None;
+        }
+        let is_dir = type_ == DiskInodeType::Directory;
+        let parent_inode_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
         // create a new file
         // alloc a inode with an indirect block
         let new_inode_id = fs.alloc_inode();
@@ -129,35 +280,123 @@ impl Inode {
             new_inode_block_id as usize,
             Arc::clone(&self.block_device)
         ).lock().modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-            new_inode.initialize(DiskInodeType::File);
+            new_inode.initialize(type_);
+            if is_dir {
+                // linked from its parent and its own "."
+                new_inode.nlink = 2;
+                self.append_dirent(".", new_inode_id, new_inode, &mut fs);
+                self.append_dirent("..", parent_inode_id, new_inode, &mut fs);
+            }
         });
         self.modify_disk_inode(|root_inode| {
+            // the ".." of a new directory links back to its parent
+            if is_dir {
+                root_inode.nlink += 1;
+            }
             // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
+            self.append_dirent(name, new_inode_id, root_inode, &mut fs);
         });
 
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
         // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
+        Some(Arc::new(Self::open(
+            new_inode_block_id,
+            new_inode_block_offset,
+            &mut fs,
             self.fs.clone(),
             self.block_device.clone(),
         )))
         // release efs lock automatically by compiler
     }
-    /// List inodes under current inode
+    /// Create a regular file under current inode by name
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+    /// Create a directory under current inode by name
+    pub fn mkdir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+    /// Create a hard link `name` under current inode to the file `inode`
+    pub fn link(&self, name: &str, inode: &Inode) -> Option<()> {
+        if !Self::is_valid_name(name) || inode.is_dir() {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        if self.read_disk_inode(|root_inode| {
+            !root_inode.is_dir()
+                || root_inode.nlink == 0
+                || self.find_inode_id(name, root_inode).is_some()
+        }) {
+            return None;
+        }
+        let inode_id = fs.get_inode_id(inode.block_id as u32, inode.block_offset);
+        inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+        });
+        self.modify_disk_inode(|root_inode| {
+            self.append_dirent(name, inode_id, root_inode, &mut fs);
+        });
+        Some(())
+    }
+    /// Remove the file entry `name` under current inode,
+    /// the inode and its data are released along with its last link,
+    /// or with its last in-memory reference if it is still in use
+    pub fn unlink(&self, name: &str) -> Option<()> {
+        if !Self::is_valid_name(name) {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        match self.find_child(name, &fs) {
+            Some((_, false)) => {}
+            _ => return None,
+        }
+        let inode_id = self.modify_disk_inode(|root_inode| {
+            self.remove_dirent(name, root_inode, &mut fs)
+        })?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let nlink = get_block_cache(
+            block_id as usize,
+            Arc::clone(&self.block_device)
+        ).lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+            disk_inode.nlink -= 1;
+            disk_inode.nlink
+        });
+        if nlink == 0 && !fs.is_inode_open(inode_id) {
+            self.free_inode(inode_id, &mut fs);
+        }
+        Some(())
+    }
+    /// Remove the empty directory `name` under current inode,
+    /// it is released later if it is still in use
+    pub fn rmdir(&self, name: &str) -> Option<()> {
+        if !Self::is_valid_name(name) {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        let inode_id = match self.find_child(name, &fs) {
+            Some((inode_id, true)) => inode_id,
+            _ => return None,
+        };
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let dir_cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+        // only "." and ".." are left in an empty directory
+        if dir_cache.lock().read(block_offset, |disk_inode: &DiskInode| {
+            disk_inode.size as usize > 2 * DIRENT_SZ
+        }) {
+            return None;
+        }
+        self.modify_disk_inode(|root_inode| {
+            self.remove_dirent(name, root_inode, &mut fs);
+            root_inode.nlink -= 1;
+        });
+        dir_cache.lock().modify(block_offset, |disk_inode: &mut DiskInode| {
+            disk_inode.nlink = 0;
+        });
+        if !fs.is_inode_open(inode_id) {
+            self.free_inode(inode_id, &mut fs);
+        }
+        Some(())
+    }
+    /// List inodes under current inode, "." and ".." excluded
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
@@ -173,6 +412,9 @@ impl Inode {
                     ),
                     DIRENT_SZ,
                 );
+                if dirent.name() == "." || dirent.name() == ".." {
+                    continue;
+                }
                 v.push(String::from(dirent.name()));
             }
             v
@@ -192,7 +434,6 @@ impl Inode {
             self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
             disk_inode.write_at(offset, buf, &self.block_device)
         });
-        block_cache_sync_all();
         size
     }
     /// Clear the data in current inode
@@ -206,6 +447,16 @@ impl Inode {
                 fs.dealloc_data(data_block);
             }
         });
-        block_cache_sync_all();
+    }
+}
+
+impl Drop for Inode {
+    /// An inode unlinked while in use is freed along with its last reference
+    fn drop(&mut self) {
+        let mut fs = self.fs.lock();
+        let inode_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
+        if fs.close_inode(inode_id) && self.read_disk_inode(|disk_inode| disk_inode.nlink == 0) {
+            self.free_inode(inode_id, &mut fs);
+        }
     }
 }
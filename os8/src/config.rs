@@ -1,14 +1,82 @@
 //! Constants used in rCore
 
+/// Size of the user stack of a thread unless it asks for another one, and the
+/// initial size of the main stack
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// Thread-local storage of each thread, right above its user stack
+pub const USER_TLS_SIZE: usize = 4096;
+/// The user stacks are allocated from a region of this size above the heap
+pub const USER_STACK_REGION_SIZE: usize = 0x1000_0000;
+/// The main stack at the top of the stack region grows on demand up to this size
+pub const USER_MAIN_STACK_LIMIT: usize = 0x80_0000;
+/// The user heap grows from the program break up to this size, the user
+/// stacks are placed above it
+pub const USER_HEAP_LIMIT: usize = 0x400_0000;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 20;
+/// Stack of each hart from boot on, the idle control flow keeps running on it
+pub const BOOT_STACK_SIZE: usize = 4096 * 16;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
 pub const MEMORY_END: usize = 0x88000000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const MAX_SYSCALL_NUM: usize = 500;
+/// Number of harts the kernel runs on at most, `entry.asm` has a boot stack for each
+pub const MAX_HARTS: usize = 4;
+
+/// User mappings stay below the end of the lower half of the Sv39 address space
+pub const USER_SPACE_END: usize = 1 << 38;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const CLOCK_FREQ: usize = 12500000;
-pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0c00_0000, 0x21_0000), // PLIC
+    (0x1000_0000, 0x1000),    // serial port
+    (0x1000_1000, 0x1000),    // virtio block device
+    (0x1000_2000, 0x1000),    // virtio swap device
+];
+
+/// The scheduling policies to choose from
+#[allow(unused)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SchedPolicy {
+    /// First come first served, tasks run until they yield or block
+    Fifo,
+    /// Round robin with a time slice of `RR_TIME_SLICE` ticks
+    RoundRobin,
+    /// Stride scheduling, tasks get cpu time proportional to their priority
+    Stride,
+    /// Multi-level feedback queue
+    Mlfq,
+}
+
+/// The scheduling policy of the task manager
+pub const SCHED_POLICY: SchedPolicy = SchedPolicy::Stride;
+/// Time slice of round robin scheduling in timer ticks
+pub const RR_TIME_SLICE: usize = 1;
+/// The stride of a task is `BIG_STRIDE / priority`
+pub const BIG_STRIDE: u64 = 0x10_0000;
+/// Priority of a task that has not set it
+pub const DEFAULT_PRIORITY: usize = 16;
+/// Number of queues of the multi-level feedback queue scheduler
+pub const MLFQ_LEVELS: usize = 4;
+/// Interval in timer ticks to lift all tasks back to the top queue
+pub const MLFQ_BOOST_TICKS: usize = 100;
+
+/// Number of disk blocks kept in the block cache
+pub const BLOCK_CACHE_SIZE: usize = 256;
+/// Interval in ms to write dirty cached blocks back to the disk
+pub const BLOCK_FLUSH_INTERVAL_MS: usize = 1000;
+
+/// Number of pages the swap device holds, the Makefile creates it as large
+pub const SWAP_PAGES: usize = 0x1_0000;
+
+/// Number of received bytes the serial port keeps until the TTY takes them
+pub const SERIAL_RX_BUFFER_SIZE: usize = 256;
+/// Number of input bytes the TTY keeps until they are read
+pub const TTY_BUFFER_SIZE: usize = 4096;
+
+/// Number of messages a mailbox holds at most
+pub const MAIL_CAPACITY: usize = 16;
+/// Length in bytes of a message at most
+pub const MAIL_MAX_LEN: usize = 256;
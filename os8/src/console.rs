@@ -1,10 +1,14 @@
 //! SBI console driver, for text output
 
 use crate::sbi::console_putchar;
+use crate::sync::SpinLock;
 use core::fmt::{self, Write};
 
 struct Stdout;
 
+/// Harts print one at a time so that their lines do not interleave
+static STDOUT: SpinLock<Stdout> = SpinLock::new(Stdout);
+
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.chars() {
@@ -15,7 +19,7 @@ impl Write for Stdout {
 }
 
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    STDOUT.lock().write_fmt(args).unwrap();
 }
 
 #[macro_export]
@@ -61,7 +65,8 @@ pub fn print_colorized(
     foreground_color: impl Into<u8>,
     background_color: impl Into<u8>,
 ) {
-    Stdout
+    STDOUT
+        .lock()
         .write_fmt(colorize!(args, foreground_color, background_color))
         .unwrap();
 }
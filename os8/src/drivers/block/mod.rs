@@ -5,8 +5,20 @@ use alloc::sync::Arc;
 use easy_fs::BlockDevice;
 type BlockDeviceImpl = virtio_blk::VirtIOBlock;
 
+/// The virtio device slot of the disk holding the file system
+const VIRTIO0: usize = 0x10001000;
+/// The virtio device slot of the swap disk
+const VIRTIO1: usize = 0x10002000;
+
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new(VIRTIO0, false));
+    /// The disk user pages are swapped out to, if QEMU is given a second one.
+    /// Pages are swapped with an address space locked, so it is polled.
+    pub static ref SWAP_DEVICE: Option<Arc<dyn BlockDevice>> = if BlockDeviceImpl::probe(VIRTIO1) {
+        Some(Arc::new(BlockDeviceImpl::new(VIRTIO1, true)))
+    } else {
+        None
+    };
 }
 
 #[allow(unused)]
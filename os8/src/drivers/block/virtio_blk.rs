@@ -1,5 +1,4 @@
-
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+use virtio_drivers::{BlkResp, Hal, RespStatus, VirtIOBlk, VirtIOHeader};
 use crate::mm::{
     PhysAddr,
     VirtAddr,
@@ -12,73 +11,159 @@ use crate::mm::{
     kernel_token,
 };
 use super::BlockDevice;
-use crate::sync::UPSafeCell;
+use crate::lang_items::panicking;
+use crate::sync::{SpinLock, SpinLockGuard};
+use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::ptr::read_volatile;
 use lazy_static::*;
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+/// Magic value of the first register of a virtio device, "virt" in little endian
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+/// Device ID of a virtio block device
+const VIRTIO_ID_BLOCK: u32 = 2;
+
+/// A virtio block device, requests are submitted without waiting and
+/// the device interrupts when they are done
+pub struct VirtIOBlock {
+    inner: SpinLock<VirtIOBlockInner>,
+    /// Requests are always waited for by polling, for callers holding spinlocks
+    polled: bool,
+}
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static>>);
+struct VirtIOBlockInner {
+    virtio_blk: VirtIOBlk<'static, VirtioHal>,
+    /// Tasks sleeping until the request of a token is done
+    waiters: BTreeMap<u16, Arc<TaskControlBlock>>,
+    /// Requests done with nobody sleeping for them, their submitters poll
+    completed: BTreeSet<u16>,
+}
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe { 
-        UPSafeCell::new(Vec::new())
-    };
+    static ref QUEUE_FRAMES: SpinLock<Vec<FrameTracker>> = SpinLock::new(Vec::new());
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0.exclusive_access()
-        .read_block(block_id, buf)
-        .expect("Error when reading VirtIOBlk");
+        let mut resp = BlkResp::default();
+        let mut inner = self.inner.lock();
+        let token = unsafe { inner.virtio_blk.read_block_nb(block_id, buf, &mut resp) }
+            .expect("Error when reading VirtIOBlk");
+        self.wait_for(inner, token);
+        assert_eq!(
+            resp.status(),
+            RespStatus::Ok,
+            "Error when reading VirtIOBlk"
+        );
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0.exclusive_access()
-        .write_block(block_id, buf)
-        .expect("Error when writing VirtIOBlk");
+        let mut resp = BlkResp::default();
+        let mut inner = self.inner.lock();
+        let token = unsafe { inner.virtio_blk.write_block_nb(block_id, buf, &mut resp) }
+            .expect("Error when writing VirtIOBlk");
+        self.wait_for(inner, token);
+        assert_eq!(
+            resp.status(),
+            RespStatus::Ok,
+            "Error when writing VirtIOBlk"
+        );
+    }
+    fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        inner.virtio_blk.ack_interrupt();
+        while let Ok(token) = inner.virtio_blk.pop_used() {
+            match inner.waiters.remove(&token) {
+                Some(task) => add_task(task),
+                None => {
+                    inner.completed.insert(token);
+                }
+            }
+        }
     }
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
+    /// Whether there is a virtio block device at `base`, an empty slot has device ID 0
+    pub fn probe(base: usize) -> bool {
         unsafe {
-            Self(UPSafeCell::new(VirtIOBlk::new(
-                &mut *(VIRTIO0 as *mut VirtIOHeader)
-            ).unwrap()))
+            read_volatile(base as *const u32) == VIRTIO_MAGIC
+                && read_volatile((base + 8) as *const u32) == VIRTIO_ID_BLOCK
         }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
-    let mut ppn_base = PhysPageNum(0);
-    for i in 0..pages {
-        let frame = frame_alloc().unwrap();
-        if i == 0 { ppn_base = frame.ppn; }
-        assert_eq!(frame.ppn.0, ppn_base.0 + i);
-        QUEUE_FRAMES.exclusive_access().push(frame);
+    pub fn new(base: usize, polled: bool) -> Self {
+        let virtio_blk =
+            unsafe { VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).unwrap() };
+        Self {
+            inner: SpinLock::new(VirtIOBlockInner {
+                virtio_blk,
+                waiters: BTreeMap::new(),
+                completed: BTreeSet::new(),
+            }),
+            polled,
+        }
     }
-    ppn_base.into()
-}
 
-#[no_mangle]
-pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
-    let mut ppn_base: PhysPageNum = pa.into();
-    for _ in 0..pages {
-        frame_dealloc(ppn_base);
-        ppn_base.step();
+    /// Wait until the request of `token` submitted with `inner` held is done.
+    /// The current task sleeps until the interrupt, without a task to put to
+    /// sleep (during boot), on a polled device or when the kernel is going
+    /// down after a panic we poll the device.
+    fn wait_for(&self, mut inner: SpinLockGuard<VirtIOBlockInner>, token: u16) {
+        if let Some(task) = current_task().filter(|_| !self.polled && !panicking()) {
+            inner.waiters.insert(token, task);
+            drop(inner);
+            block_current_and_run_next();
+            return;
+        }
+        drop(inner);
+        loop {
+            self.handle_irq();
+            if self.inner.lock().completed.remove(&token) {
+                return;
+            }
+            spin_loop();
+        }
     }
-    0
 }
 
-#[no_mangle]
-pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
-    VirtAddr(paddr.0)
-}
+/// Memory of the virtio driver, the kernel space maps the physical memory identically
+pub struct VirtioHal;
+
+impl Hal for VirtioHal {
+    fn dma_alloc(pages: usize) -> usize {
+        let mut ppn_base = PhysPageNum(0);
+        for i in 0..pages {
+            let frame = frame_alloc().unwrap();
+            if i == 0 {
+                ppn_base = frame.ppn;
+            }
+            assert_eq!(frame.ppn.0, ppn_base.0 + i);
+            QUEUE_FRAMES.lock().push(frame);
+        }
+        let pa: PhysAddr = ppn_base.into();
+        pa.0
+    }
+
+    fn dma_dealloc(pa: usize, pages: usize) -> i32 {
+        let mut ppn_base: PhysPageNum = PhysAddr::from(pa).into();
+        for _ in 0..pages {
+            frame_dealloc(ppn_base);
+            ppn_base.step();
+        }
+        0
+    }
 
-#[no_mangle]
-pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
-    PageTable::from_token(kernel_token()).translate_va(vaddr).unwrap()
+    fn phys_to_virt(addr: usize) -> usize {
+        addr
+    }
+
+    fn virt_to_phys(vaddr: usize) -> usize {
+        PageTable::from_token(kernel_token())
+            .translate_va(VirtAddr::from(vaddr))
+            .unwrap()
+            .0
+    }
 }
@@ -1,3 +1,61 @@
 mod block;
+mod plic;
+mod serial;
+mod tty;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
+pub use tty::{TtyMode, TTY};
+
+use crate::task::hart_id;
+use riscv::register::sip;
+
+/// Interrupt source of the virtio block device
+const VIRTIO0_IRQ: usize = 1;
+/// Interrupt source of the serial port
+const UART0_IRQ: usize = 10;
+
+/// Set up the devices and take their interrupts on the boot hart
+pub fn init() {
+    for source in [VIRTIO0_IRQ, UART0_IRQ] {
+        plic::set_priority(source, 1);
+    }
+    serial::init();
+    // the swap device takes its queues while memory is still free
+    lazy_static::initialize(&SWAP_DEVICE);
+    init_hart();
+}
+
+/// Route the interrupts of the devices to the current hart
+pub fn init_hart() {
+    let hart_id = hart_id();
+    for source in [VIRTIO0_IRQ, UART0_IRQ] {
+        plic::enable(hart_id, source);
+    }
+    plic::set_threshold(hart_id, 0);
+}
+
+/// Handle an external interrupt, a device interrupt is routed to all
+/// harts but only the one claiming it first handles it
+pub fn irq_handler() {
+    let hart_id = hart_id();
+    let source = plic::claim(hart_id);
+    match source {
+        0 => return,
+        VIRTIO0_IRQ => BLOCK_DEVICE.handle_irq(),
+        UART0_IRQ => {
+            serial::handle_irq();
+            TTY.handle_input();
+        }
+        // a source nobody enabled, completed so that it does not stick
+        _ => warn!("Spurious IRQ {} on hart {}", source, hart_id),
+    }
+    plic::complete(hart_id, source);
+}
+
+/// Handle a pending external interrupt, for an idle hart which never
+/// leaves the kernel to take it
+pub fn poll_irq() {
+    if sip::read().sext() {
+        irq_handler();
+    }
+}
@@ -0,0 +1,61 @@
+//! RISC-V Platform-Level Interrupt Controller of the QEMU virt machine
+//!
+//! Every source has a priority, every context (a privilege mode of a hart)
+//! enables the sources it takes and claims the interrupts routed to it.
+
+use core::ptr::{read_volatile, write_volatile};
+
+const PLIC_BASE: usize = 0x0c00_0000;
+const PRIORITY_BASE: usize = PLIC_BASE;
+const ENABLE_BASE: usize = PLIC_BASE + 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = PLIC_BASE + 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// The supervisor context of `hart_id`, context `2 * hart_id` is its machine mode
+fn s_context(hart_id: usize) -> usize {
+    2 * hart_id + 1
+}
+
+/// Set the priority of interrupt `source`, 0 never interrupts
+pub fn set_priority(source: usize, priority: u32) {
+    unsafe {
+        write_volatile((PRIORITY_BASE + 4 * source) as *mut u32, priority);
+    }
+}
+
+/// Route interrupt `source` to the supervisor mode of `hart_id`
+pub fn enable(hart_id: usize, source: usize) {
+    let reg = (ENABLE_BASE + s_context(hart_id) * ENABLE_STRIDE + 4 * (source / 32)) as *mut u32;
+    unsafe {
+        write_volatile(reg, read_volatile(reg) | 1 << (source % 32));
+    }
+}
+
+/// Interrupts with a priority above `threshold` reach the supervisor mode of `hart_id`
+pub fn set_threshold(hart_id: usize, threshold: u32) {
+    unsafe {
+        write_volatile(
+            (CONTEXT_BASE + s_context(hart_id) * CONTEXT_STRIDE) as *mut u32,
+            threshold,
+        );
+    }
+}
+
+/// Take the pending interrupt of the highest priority, 0 if another hart took it first
+pub fn claim(hart_id: usize) -> usize {
+    unsafe {
+        read_volatile((CONTEXT_BASE + s_context(hart_id) * CONTEXT_STRIDE + 4) as *const u32)
+            as usize
+    }
+}
+
+/// Tell that the interrupt `source` claimed by `hart_id` has been handled
+pub fn complete(hart_id: usize, source: usize) {
+    unsafe {
+        write_volatile(
+            (CONTEXT_BASE + s_context(hart_id) * CONTEXT_STRIDE + 4) as *mut u32,
+            source as u32,
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! NS16550A UART of the QEMU virt machine
+//!
+//! Output still goes through the SBI console, which writes to the same UART.
+//! Received bytes raise an interrupt and are kept in a ring buffer until the
+//! TTY takes them.
+
+use crate::config::SERIAL_RX_BUFFER_SIZE;
+use crate::sync::SpinLock;
+use core::ptr::{read_volatile, write_volatile};
+
+const UART_BASE: usize = 0x1000_0000;
+/// Receiver buffer register, read only
+const RBR: usize = 0;
+/// Interrupt enable register
+const IER: usize = 1;
+/// FIFO control register, write only
+const FCR: usize = 2;
+/// Modem control register
+const MCR: usize = 4;
+/// Line status register
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+/// Gates the interrupt line of the UART on real hardware
+const MCR_OUT2: u8 = 1 << 3;
+const LSR_DATA_READY: u8 = 1 << 0;
+
+fn read_reg(reg: usize) -> u8 {
+    unsafe { read_volatile((UART_BASE + reg) as *const u8) }
+}
+
+fn write_reg(reg: usize, value: u8) {
+    unsafe { write_volatile((UART_BASE + reg) as *mut u8, value) }
+}
+
+/// Bytes received and not taken yet, the oldest ones are kept when it is full
+struct RxRing {
+    buffer: [u8; SERIAL_RX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RxRing {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; SERIAL_RX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < SERIAL_RX_BUFFER_SIZE {
+            self.buffer[(self.head + self.len) % SERIAL_RX_BUFFER_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buffer[self.head];
+        self.head = (self.head + 1) % SERIAL_RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+static RX_RING: SpinLock<RxRing> = SpinLock::new(RxRing::new());
+
+/// Turn on the FIFOs and the receive interrupt
+pub fn init() {
+    write_reg(FCR, FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX);
+    write_reg(MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+    write_reg(IER, IER_RX_AVAILABLE);
+}
+
+/// Move the received bytes into the ring buffer, reading them clears the interrupt
+pub fn handle_irq() {
+    let mut rx_ring = RX_RING.lock();
+    while read_reg(LSR) & LSR_DATA_READY != 0 {
+        rx_ring.push(read_reg(RBR));
+    }
+}
+
+/// Take the oldest received byte
+pub fn getchar() -> Option<u8> {
+    RX_RING.lock().pop()
+}
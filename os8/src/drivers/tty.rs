@@ -0,0 +1,264 @@
+//! The console terminal and its line discipline
+//!
+//! Input from the serial port is edited here before the readers of stdin get
+//! it. In canonical mode a read waits for a whole line, which can be edited
+//! with backspace and ^U until enter is pressed. ^C sends SIGINT to the
+//! foreground process and ^D ends the input.
+//!
+//! The terminal starts out raw and without echo, as the SBI console was, for
+//! the shells which edit and echo their input themselves; a program wanting
+//! lines switches to canonical mode.
+
+use super::serial;
+use crate::config::TTY_BUFFER_SIZE;
+use crate::mm::UserBuffer;
+use crate::sync::SpinLock;
+use crate::task::{
+    add_task, current_process, current_task, pid2process, raise_signal, SignalFlags,
+    TaskControlBlock,
+};
+use crate::timer::block_current_until;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BS: u8 = 0x08;
+const LF: u8 = b'\n';
+const CR: u8 = b'\r';
+const CTRL_U: u8 = 0x15;
+const DEL: u8 = 0x7f;
+
+bitflags! {
+    /// Local modes of the terminal, as `c_lflag` of Linux termios
+    pub struct TtyMode: u32 {
+        /// ^C sends SIGINT to the foreground process
+        const ISIG = 0o1;
+        /// Input is read line by line, after editing
+        const ICANON = 0o2;
+        /// Input is echoed back
+        const ECHO = 0o10;
+    }
+}
+
+pub struct Tty {
+    inner: SpinLock<TtyInner>,
+}
+
+struct TtyInner {
+    mode: TtyMode,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// Input ready to be read, whole lines in canonical mode
+    ready: VecDeque<u8>,
+    /// ^D on an empty line, the next read returns 0
+    eof: bool,
+    /// Tasks sleeping until there is input
+    readers: VecDeque<Arc<TaskControlBlock>>,
+    /// Pid of the process getting the signals of the terminal
+    foreground: Option<usize>,
+}
+
+lazy_static! {
+    pub static ref TTY: Tty = Tty {
+        inner: SpinLock::new(TtyInner {
+            mode: TtyMode::ISIG,
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            eof: false,
+            readers: VecDeque::new(),
+            foreground: None,
+        }),
+    };
+}
+
+/// Erase the last `count` characters on the screen
+fn echo_erase(count: usize) {
+    for _ in 0..count {
+        print!("{} {}", BS as char, BS as char);
+    }
+}
+
+impl TtyInner {
+    fn wake_readers(&mut self) {
+        while let Some(task) = self.readers.pop_front() {
+            add_task(task);
+        }
+    }
+
+    /// Put a received byte through the line discipline, returns true for a
+    /// ^C interrupting the foreground process
+    fn receive(&mut self, byte: u8) -> bool {
+        let echo = self.mode.contains(TtyMode::ECHO);
+        let byte = if byte == CR { LF } else { byte };
+        if self.mode.contains(TtyMode::ISIG) && byte == CTRL_C {
+            if echo {
+                println!("^C");
+            }
+            self.line.clear();
+            return true;
+        }
+        if !self.mode.contains(TtyMode::ICANON) {
+            if self.ready.len() < TTY_BUFFER_SIZE {
+                self.ready.push_back(byte);
+                if echo {
+                    print!("{}", byte as char);
+                }
+            }
+            self.wake_readers();
+            return false;
+        }
+        match byte {
+            BS | DEL => {
+                if self.line.pop().is_some() && echo {
+                    echo_erase(1);
+                }
+            }
+            CTRL_U => {
+                if echo {
+                    echo_erase(self.line.len());
+                }
+                self.line.clear();
+            }
+            CTRL_D => {
+                if self.line.is_empty() {
+                    self.eof = true;
+                } else {
+                    self.ready.extend(self.line.drain(..));
+                }
+                self.wake_readers();
+            }
+            LF => {
+                self.ready.extend(self.line.drain(..));
+                self.ready.push_back(LF);
+                if echo {
+                    print!("{}", LF as char);
+                }
+                self.wake_readers();
+            }
+            _ => {
+                // the last place of a full buffer is kept for the newline
+                if self.ready.len() + self.line.len() + 1 < TTY_BUFFER_SIZE {
+                    self.line.push(byte);
+                    if echo {
+                        print!("{}", byte as char);
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Take `task` off the readers, false if it has been woken up already
+fn remove_reader(task: &Arc<TaskControlBlock>) -> bool {
+    let mut inner = TTY.inner.lock();
+    let readers = &mut inner.readers;
+    match readers.iter().position(|reader| Arc::ptr_eq(reader, task)) {
+        Some(index) => {
+            readers.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether a SIGINT not blocked is pending for the current process
+fn sigint_pending() -> bool {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.signals.contains(SignalFlags::SIGINT)
+        && !process_inner.signal_mask.contains(SignalFlags::SIGINT)
+}
+
+impl Tty {
+    /// Take the input from the serial port, called on its interrupts
+    pub fn handle_input(&self) {
+        let mut inner = self.inner.lock();
+        let mut interrupted = false;
+        while let Some(byte) = serial::getchar() {
+            interrupted |= inner.receive(byte);
+        }
+        if !interrupted {
+            return;
+        }
+        let foreground = inner.foreground;
+        drop(inner);
+        // raised with the terminal unlocked, as the signal may take a reader
+        // off the readers
+        if let Some(process) = foreground.and_then(pid2process) {
+            raise_signal(&process, SignalFlags::SIGINT);
+        }
+        // the reads of the foreground process are cut short
+        self.inner.lock().wake_readers();
+    }
+
+    /// Read into `buf` once there is input, at most one line in canonical mode.
+    /// Returns 0 at the end of input, or if SIGINT comes first.
+    pub fn read(&self, buf: UserBuffer) -> usize {
+        loop {
+            let mut inner = self.inner.lock();
+            if !inner.ready.is_empty() {
+                let canonical = inner.mode.contains(TtyMode::ICANON);
+                let mut input = Vec::new();
+                while input.len() < buf.len() {
+                    let byte = match inner.ready.pop_front() {
+                        Some(byte) => byte,
+                        None => break,
+                    };
+                    input.push(byte);
+                    if canonical && byte == LF {
+                        break;
+                    }
+                }
+                // user memory is written with the terminal unlocked
+                drop(inner);
+                for (ptr, byte) in buf.into_iter().zip(input.iter()) {
+                    unsafe {
+                        ptr.write_volatile(*byte);
+                    }
+                }
+                return input.len();
+            }
+            if inner.eof {
+                inner.eof = false;
+                return 0;
+            }
+            let task = current_task().unwrap();
+            inner.readers.push_back(Arc::clone(&task));
+            drop(inner);
+            // a SIGINT raised after this check wakes up the reader just queued
+            if sigint_pending() && remove_reader(&task) {
+                return 0;
+            }
+            if !block_current_until(None, remove_reader) {
+                return 0;
+            }
+        }
+    }
+
+    pub fn mode(&self) -> TtyMode {
+        self.inner.lock().mode
+    }
+
+    /// Switch to `mode`, a line being edited is ready at once out of canonical mode
+    pub fn set_mode(&self, mode: TtyMode) {
+        let mut inner = self.inner.lock();
+        if !mode.contains(TtyMode::ICANON) {
+            let line: Vec<u8> = inner.line.drain(..).collect();
+            inner.ready.extend(line);
+            inner.wake_readers();
+        }
+        inner.mode = mode;
+    }
+
+    pub fn foreground(&self) -> Option<usize> {
+        self.inner.lock().foreground
+    }
+
+    pub fn set_foreground(&self, pid: Option<usize>) {
+        self.inner.lock().foreground = pid;
+    }
+}
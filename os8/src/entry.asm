@@ -1,12 +1,25 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0: hartid, keep it in tp for the kernel
+    mv tp, a0
+    # harts beyond MAX_HARTS have no stack, park them
+    li t0, {MAX_HARTS}
+    bgeu a0, t0, park
+    # sp = boot_stack + (hartid + 1) * BOOT_STACK_SIZE
+    la sp, boot_stack
+    li t0, {BOOT_STACK_SIZE}
+    addi t1, a0, 1
+    mul t0, t0, t1
+    add sp, sp, t0
     call rust_main
+park:
+    wfi
+    j park
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    .space {BOOT_STACK_SIZE} * {MAX_HARTS}
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
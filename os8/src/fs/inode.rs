@@ -1,14 +1,20 @@
 use easy_fs::{
+    block_cache_sync_all,
+    set_block_cache_capacity,
     EasyFileSystem,
     Inode,
 };
+use crate::config::{BLOCK_CACHE_SIZE, BLOCK_FLUSH_INTERVAL_MS};
 use crate::drivers::BLOCK_DEVICE;
-use crate::sync::UPSafeCell;
+use crate::timer::get_time_ms;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::sync::{SleepLock, SleepLockGuard, SpinLock};
 use alloc::sync::Arc;
 use lazy_static::*;
 use bitflags::*;
+use alloc::string::String;
 use alloc::vec::Vec;
-use super::File;
+use super::{File, SeekFrom, Stat, StatMode};
 use crate::mm::UserBuffer;
 
 /// A wrapper around a filesystem inode
@@ -16,58 +22,121 @@ use crate::mm::UserBuffer;
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<OSInodeInner>,
+    /// Every write goes to the end of the file
+    append: bool,
+    /// Only used with `FS_LOCK` held
+    inode: Arc<Inode>,
+    inner: SpinLock<OSInodeInner>,
 }
 
-/// The OS inode inner in 'UPSafeCell'
+/// The OS inode inner in 'SpinLock', which is never held while waiting for
+/// the disk
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
 }
 
 impl OSInode {
     /// Construct an OS inode from a inode
-    pub fn new(
-        readable: bool,
-        writable: bool,
-        inode: Arc<Inode>,
-    ) -> Self {
+    pub fn new(readable: bool, writable: bool, append: bool, inode: Arc<Inode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner {
-                offset: 0,
-                inode,
-            })},
+            append,
+            inode,
+            inner: SpinLock::new(OSInodeInner { offset: 0 }),
         }
     }
     /// Read all data inside a inode into vector
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = lock_fs();
+        let mut offset = self.inner.lock().offset;
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = self.inode.read_at(offset, &mut buffer);
             if len == 0 {
                 break;
             }
-            inner.offset += len;
+            offset += len;
             v.extend_from_slice(&buffer[..len]);
         }
+        self.inner.lock().offset = offset;
         v
     }
 }
 
+impl Drop for OSInode {
+    /// A file may be closed anywhere, even with spinlocks held,
+    /// so its inode is dropped by the next filesystem operation
+    fn drop(&mut self) {
+        CLOSED_INODES.lock().push(Arc::clone(&self.inode));
+    }
+}
+
 lazy_static! {
     /// The root of all inodes, or '/' in short
     pub static ref ROOT_INODE: Arc<Inode> = {
+        set_block_cache_capacity(BLOCK_CACHE_SIZE);
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
+    /// Serializes all filesystem operations, every use of easy-fs takes it.
+    /// The task doing one may sleep on the disk holding the spin locks inside
+    /// easy-fs, which are thus never contended; who cannot sleep only tries it.
+    static ref FS_LOCK: SleepLock<()> = SleepLock::new(());
+    /// Inodes of closed files waiting to be dropped with `FS_LOCK` held
+    static ref CLOSED_INODES: SpinLock<Vec<Arc<Inode>>> = SpinLock::new(Vec::new());
+}
+
+/// Take `FS_LOCK` and drop the inodes of the files closed since the last time,
+/// dropping the last reference to an unlinked inode frees it on the disk
+fn lock_fs() -> SleepLockGuard<'static, ()> {
+    let fs = FS_LOCK.lock();
+    let closed = core::mem::take(&mut *CLOSED_INODES.lock());
+    drop(closed);
+    fs
+}
+
+/// Write all dirty blocks in the block cache back to the disk
+pub fn sync_all() {
+    let _fs = lock_fs();
+    block_cache_sync_all();
+}
+
+/// Time in ms of the last periodic flush
+static LAST_FLUSH_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// Write the dirty blocks back if the last flush is `BLOCK_FLUSH_INTERVAL_MS` ago,
+/// called on timer interrupts
+pub fn flush_dirty_blocks() {
+    let now = get_time_ms();
+    let last = LAST_FLUSH_MS.load(Ordering::Relaxed);
+    // only one hart does the flush
+    if now.saturating_sub(last) >= BLOCK_FLUSH_INTERVAL_MS
+        && LAST_FLUSH_MS
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        // skip this round if the filesystem is busy
+        try_sync_all();
+    }
+}
+
+/// Write all dirty blocks back unless a filesystem operation is going on,
+/// for who cannot sleep waiting for it. Returns whether they are written.
+pub fn try_sync_all() -> bool {
+    match FS_LOCK.try_lock() {
+        Some(_fs) => {
+            block_cache_sync_all();
+            true
+        }
+        None => false,
+    }
 }
 
 /// List all files in the filesystems
 pub fn list_apps() {
+    let _fs = lock_fs();
     println!("/**** APPS ****");
     for app in ROOT_INODE.ls() {
         println!("{}", app);
@@ -83,6 +152,7 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const APPEND = 1 << 11;
     }
 }
 
@@ -91,7 +161,7 @@ impl OpenFlags {
     /// does not check validity for simplicity
     /// returns (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        if self.difference(Self::APPEND).is_empty() {
             (true, false)
         } else if self.contains(Self::WRONLY) {
             (false, true)
@@ -101,41 +171,126 @@ impl OpenFlags {
     }
 }
 
+/// Find the inode of `path`, relative paths start from `cwd`
+fn find_inode(cwd: &str, path: &str) -> Option<Arc<Inode>> {
+    if path.starts_with('/') {
+        ROOT_INODE.find(path)
+    } else {
+        ROOT_INODE.find(cwd)?.find(path)
+    }
+}
+
+/// Split `path` into the inode of its parent directory and its last component
+fn find_parent<'a>(cwd: &str, path: &'a str) -> Option<(Arc<Inode>, &'a str)> {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(pos) => Some((find_inode(cwd, &path[..=pos])?, &path[pos + 1..])),
+        None => Some((find_inode(cwd, "")?, path)),
+    }
+}
+
 /// Open a file by path
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+pub fn open_file(cwd: &str, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(
-                readable,
-                writable,
-                inode,
-            )))
-        } else {
+    let _fs = lock_fs();
+    let inode = if flags.contains(OpenFlags::CREATE) {
+        let (parent, name) = find_parent(cwd, path)?;
+        match parent.find(name) {
+            Some(inode) => inode,
             // create file
-            ROOT_INODE.create(name)
-                .map(|inode| {
-                    Arc::new(OSInode::new(
-                        readable,
-                        writable,
-                        inode,
-                    ))
-                })
+            None => parent.create(name)?,
         }
     } else {
-        ROOT_INODE.find(name)
-            .map(|inode| {
-                if flags.contains(OpenFlags::TRUNC) {
-                    inode.clear();
-                }
-                Arc::new(OSInode::new(
-                    readable,
-                    writable,
-                    inode
-                ))
-            })
+        find_inode(cwd, path)?
+    };
+    let truncate = flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+    if inode.is_dir() {
+        // directories are read-only through files
+        if writable || truncate {
+            return None;
+        }
+    } else if truncate {
+        // clear size
+        inode.clear();
+    }
+    Some(Arc::new(OSInode::new(
+        readable,
+        writable,
+        flags.contains(OpenFlags::APPEND),
+        inode,
+    )))
+}
+
+/// Create a hard link `new_path` to the file `old_path`
+pub fn link_file(cwd: &str, old_path: &str, new_path: &str) -> Option<()> {
+    let _fs = lock_fs();
+    let inode = find_inode(cwd, old_path)?;
+    let (parent, name) = find_parent(cwd, new_path)?;
+    parent.link(name, &inode)
+}
+
+/// Remove a link to a file, the file is deleted with its last link
+pub fn unlink_file(cwd: &str, path: &str) -> Option<()> {
+    let _fs = lock_fs();
+    let (parent, name) = find_parent(cwd, path)?;
+    parent.unlink(name)
+}
+
+/// Create a directory by path
+pub fn make_dir(cwd: &str, path: &str) -> Option<()> {
+    let _fs = lock_fs();
+    let (parent, name) = find_parent(cwd, path)?;
+    parent.mkdir(name).map(|_| ())
+}
+
+/// Remove an empty directory by path
+pub fn remove_dir(cwd: &str, path: &str) -> Option<()> {
+    let _fs = lock_fs();
+    let (parent, name) = find_parent(cwd, path)?;
+    parent.rmdir(name)
+}
+
+/// Resolve the directory `path` into a canonical absolute path,
+/// returns `None` if it is not an existing directory
+pub fn resolve_dir(cwd: &str, path: &str) -> Option<String> {
+    let is_dir = {
+        let _fs = lock_fs();
+        find_inode(cwd, path)?.is_dir()
+    };
+    if !is_dir {
+        return None;
+    }
+    let mut components: Vec<&str> = Vec::new();
+    if !path.starts_with('/') {
+        components.extend(cwd.split('/').filter(|name| !name.is_empty()));
+    }
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(name),
+        }
+    }
+    let mut dir = String::new();
+    for name in components {
+        dir.push('/');
+        dir.push_str(name);
+    }
+    if dir.is_empty() {
+        dir.push('/');
+    }
+    Some(dir)
+}
+
+/// `base + delta`, `None` if it is negative or overflows
+fn offset_by(base: usize, delta: isize) -> Option<usize> {
+    let offset = (base as isize).checked_add(delta)?;
+    if offset < 0 {
+        None
+    } else {
+        Some(offset as usize)
     }
 }
 
@@ -143,27 +298,91 @@ impl File for OSInode {
     fn readable(&self) -> bool { self.readable }
     fn writable(&self) -> bool { self.writable }
     fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = lock_fs();
+        let mut offset = self.inner.lock().offset;
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
+            let read_size = self.inode.read_at(offset, *slice);
             if read_size == 0 {
                 break;
             }
-            inner.offset += read_size;
+            offset += read_size;
             total_read_size += read_size;
         }
+        self.inner.lock().offset = offset;
         total_read_size
     }
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = lock_fs();
+        let mut offset = if self.append {
+            self.inode.size() as usize
+        } else {
+            self.inner.lock().offset
+        };
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
+            let write_size = self.inode.write_at(offset, *slice);
             assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
+            offset += write_size;
             total_write_size += write_size;
         }
+        self.inner.lock().offset = offset;
         total_write_size
     }
+    fn stat(&self) -> Option<Stat> {
+        let _fs = lock_fs();
+        let mode = if self.inode.is_dir() {
+            StatMode::DIR
+        } else {
+            StatMode::FILE
+        };
+        Some(Stat::new(
+            self.inode.inode_id() as u64,
+            mode,
+            self.inode.nlink(),
+        ))
+    }
+    fn seekable(&self) -> bool {
+        true
+    }
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        let _fs = lock_fs();
+        let offset = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(delta) => offset_by(self.inner.lock().offset, delta)?,
+            SeekFrom::End(delta) => offset_by(self.inode.size() as usize, delta)?,
+        };
+        // seeking past the end is fine, a write there fills the hole with zeros
+        self.inner.lock().offset = offset;
+        Some(offset)
+    }
+    fn read_at(&self, mut offset: usize, mut buf: UserBuffer) -> usize {
+        let _fs = lock_fs();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = self.inode.read_at(offset, *slice);
+            if read_size == 0 {
+                break;
+            }
+            offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size
+    }
+    fn write_at(&self, mut offset: usize, buf: UserBuffer) -> usize {
+        let _fs = lock_fs();
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = self.inode.write_at(offset, *slice);
+            assert_eq!(write_size, slice.len());
+            offset += write_size;
+            total_write_size += write_size;
+        }
+        total_write_size
+    }
+    fn sync(&self) {
+        // the block cache does not know which file a block belongs to, so
+        // everything dirty goes, the whole filesystem is synced
+        sync_all();
+    }
 }
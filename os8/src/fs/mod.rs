@@ -10,6 +10,43 @@ pub trait File : Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Get the stat of the underlying inode, if there is one
+    fn stat(&self) -> Option<Stat> {
+        None
+    }
+    /// Whether the file is the console terminal
+    fn is_tty(&self) -> bool {
+        false
+    }
+    /// Whether the file has an offset to move, pipes and stdio do not
+    fn seekable(&self) -> bool {
+        false
+    }
+    /// Move the offset to `pos` and return it, `None` if it would be negative
+    fn seek(&self, _pos: SeekFrom) -> Option<usize> {
+        None
+    }
+    /// Read from `offset` without moving the offset of the file
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> usize {
+        0
+    }
+    /// Write at `offset` without moving the offset of the file
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> usize {
+        0
+    }
+    /// Write the data of the file back to the disk, if it has any there
+    fn sync(&self) {}
+}
+
+/// Where to move the offset of a file to
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    /// `SEEK_SET`, from the start of the file
+    Start(usize),
+    /// `SEEK_CUR`, from the current offset
+    Current(isize),
+    /// `SEEK_END`, from the end of the file
+    End(isize),
 }
 
 /// The stat of a inode
@@ -28,6 +65,19 @@ pub struct Stat {
     pad: [u64; 7],
 }
 
+impl Stat {
+    /// Create the stat of an inode on the (only) disk
+    pub fn new(ino: u64, mode: StatMode, nlink: u32) -> Self {
+        Self {
+            dev: 0,
+            ino,
+            mode,
+            nlink,
+            pad: [0; 7],
+        }
+    }
+}
+
 bitflags! {
     /// The mode of a inode
     /// whether a directory or a file
@@ -41,5 +91,8 @@ bitflags! {
 }    
 
 pub use stdio::{Stdin, Stdout};
-pub use inode::{OSInode, open_file, OpenFlags, list_apps};
+pub use inode::{
+    OSInode, open_file, link_file, unlink_file, make_dir, remove_dir, resolve_dir,
+    OpenFlags, list_apps, sync_all, try_sync_all, flush_dirty_blocks,
+};
 pub use pipe::{Pipe, make_pipe};
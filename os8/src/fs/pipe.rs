@@ -1,20 +1,20 @@
 use super::File;
 use alloc::sync::{Arc, Weak};
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use crate::mm::UserBuffer;
 
-use crate::task::suspend_current_and_run_next;
+use crate::task::{fatal_signal_pending, suspend_current_and_run_next};
 
 /// One end of a pipe
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinLock<PipeRingBuffer>>,
 }
 
 impl Pipe {
     /// Create the read end of a pipe from a ring buffer
-    pub fn read_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn read_end_with_buffer(buffer: Arc<SpinLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: true,
             writable: false,
@@ -22,7 +22,7 @@ impl Pipe {
         }
     }
     /// Create the write end of a pipe with a ring buffer
-    pub fn write_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn write_end_with_buffer(buffer: Arc<SpinLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: false,
             writable: true,
@@ -111,16 +111,14 @@ impl PipeRingBuffer {
 /// Crate a pipe
 /// return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe {
-        UPSafeCell::new(PipeRingBuffer::new())
-    });
+    let buffer = Arc::new(SpinLock::new(PipeRingBuffer::new()));
     let read_end = Arc::new(
         Pipe::read_end_with_buffer(buffer.clone())
     );
     let write_end = Arc::new(
         Pipe::write_end_with_buffer(buffer.clone())
     );
-    buffer.exclusive_access().set_write_end(&write_end);
+    buffer.lock().set_write_end(&write_end);
     (read_end, write_end)
 }
 
@@ -132,10 +130,11 @@ impl File for Pipe {
         let mut buf_iter = buf.into_iter();
         let mut read_size = 0usize;
         loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+            let mut ring_buffer = self.buffer.lock();
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
-                if ring_buffer.all_write_ends_closed() {
+                // a process being killed stops waiting for the writers
+                if ring_buffer.all_write_ends_closed() || fatal_signal_pending() {
                     return read_size;
                 }
                 drop(ring_buffer);
@@ -158,10 +157,13 @@ impl File for Pipe {
         let mut buf_iter = buf.into_iter();
         let mut write_size = 0usize;
         loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+            let mut ring_buffer = self.buffer.lock();
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
                 drop(ring_buffer);
+                if fatal_signal_pending() {
+                    return write_size;
+                }
                 suspend_current_and_run_next();
                 continue;
             }
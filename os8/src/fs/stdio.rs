@@ -1,7 +1,6 @@
 use super::File;
+use crate::drivers::TTY;
 use crate::mm::{UserBuffer};
-use crate::sbi::console_getchar;
-use crate::task::suspend_current_and_run_next;
 
 /// The standard input
 pub struct Stdin;
@@ -11,26 +10,15 @@ pub struct Stdout;
 impl File for Stdin {
     fn readable(&self) -> bool { true }
     fn writable(&self) -> bool { false }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
-        }
-        let ch = c as u8;
-        unsafe { user_buf.buffers[0].as_mut_ptr().write_volatile(ch); }
-        1
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        TTY.read(user_buf)
     }
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
 }
 
 impl File for Stdout {
@@ -45,4 +33,7 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
 }
@@ -1,9 +1,19 @@
 //! The panic handler
 
 use crate::console::ANSICON;
+use crate::fs::try_sync_all;
 use crate::sbi::shutdown;
 
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the first panic, from then on the kernel is going down
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the kernel is going down, nothing may sleep any more
+pub fn panicking() -> bool {
+    PANICKING.load(Ordering::Relaxed)
+}
 
 #[panic_handler]
 /// panic handler
@@ -25,5 +35,10 @@ fn panic(info: &PanicInfo) -> ! {
             info.message().unwrap()
         );
     }
+    // write back the block cache before shutting down, unless it is the
+    // write back which panics
+    if !PANICKING.swap(true, Ordering::Relaxed) && !try_sync_all() {
+        println!("[kernel] Filesystem busy, dirty blocks are lost");
+    }
     shutdown()
 }
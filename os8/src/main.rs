@@ -14,11 +14,16 @@
 //!
 //! We then call [`task::run_first_task()`] and for the first time go to
 //! userspace.
+//!
+//! The first hart to arrive boots the kernel and then starts the others
+//! through the SBI HSM extension, each of them running its own scheduling
+//! loop once it has switched to the kernel space.
 
 #![no_std]
 #![no_main]
 #![feature(panic_info_message)]
 #![feature(alloc_error_handler)]
+#![feature(asm_const)]
 
 #[macro_use]
 extern crate bitflags;
@@ -42,7 +47,20 @@ mod trap;
 mod drivers;
 mod fs;
 
-core::arch::global_asm!(include_str!("entry.asm"));
+use config::{BOOT_STACK_SIZE, MAX_HARTS};
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+core::arch::global_asm!(
+    include_str!("entry.asm"),
+    MAX_HARTS = const MAX_HARTS,
+    BOOT_STACK_SIZE = const BOOT_STACK_SIZE,
+);
+
+/// Id of the hart booting the kernel, it is not in .bss which gets cleared
+static BOOT_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Set once the boot hart has initialized the kernel
+static KERNEL_READY: AtomicBool = AtomicBool::new(false);
 
 /// clear BSS segment
 fn clear_bss() {
@@ -56,19 +74,52 @@ fn clear_bss() {
     }
 }
 
+/// Start all the other harts at `_start`, those that do not exist or
+/// are already running just fail to start
+fn start_other_harts(boot_hart: usize) {
+    extern "C" {
+        fn _start();
+    }
+    for hart_id in (0..MAX_HARTS).filter(|&hart_id| hart_id != boot_hart) {
+        sbi::hart_start(hart_id, _start as usize, 0);
+    }
+}
+
 #[no_mangle]
 /// the rust entry-point of os
-pub fn rust_main() -> ! {
-    clear_bss();
-    logging::init();
-    println!("[kernel] Hello, world!");
-    mm::init();
-    mm::remap_test();
-    trap::init();
-    trap::enable_timer_interrupt();
-    timer::set_next_trigger();
-    fs::list_apps();
-    task::add_initproc();
+pub fn rust_main(hart_id: usize) -> ! {
+    if BOOT_HART
+        .compare_exchange(usize::MAX, hart_id, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+    {
+        clear_bss();
+        logging::init();
+        println!("[kernel] Hello, world!");
+        mm::init();
+        mm::remap_test();
+        trap::init();
+        trap::enable_timer_interrupt();
+        trap::enable_software_interrupt();
+        trap::enable_external_interrupt();
+        drivers::init();
+        timer::set_next_trigger();
+        fs::list_apps();
+        task::add_initproc();
+        KERNEL_READY.store(true, Ordering::Release);
+        start_other_harts(hart_id);
+    } else {
+        while !KERNEL_READY.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        mm::init_other_hart();
+        trap::init();
+        trap::enable_timer_interrupt();
+        trap::enable_software_interrupt();
+        trap::enable_external_interrupt();
+        drivers::init_hart();
+        timer::set_next_trigger();
+        println!("[kernel] hart {} started", hart_id);
+    }
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
@@ -1,15 +1,16 @@
 //! Implementation of [`FrameAllocator`] which
 //! controls all the frames in the operating system.
 
+use super::swap::swap_out_any;
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
 
-/// manage a frame which has the same lifecycle as the tracker
-#[derive(Clone)]
+/// manage a frame which has the same lifecycle as the tracker,
+/// frames shared between address spaces are counted through `Arc<FrameTracker>`
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
 }
@@ -90,31 +91,41 @@ type FrameAllocatorImpl = StackFrameAllocator;
 
 lazy_static! {
     /// frame allocator instance through lazy_static!
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+        SpinLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
+    FRAME_ALLOCATOR.lock().init(
         PhysAddr::from(ekernel as usize).ceil(),
         PhysAddr::from(MEMORY_END).floor(),
     );
 }
 
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+/// allocate a frame, user pages of other processes are swapped out when
+/// memory is full
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    loop {
+        if let Some(frame) = try_frame_alloc() {
+            return Some(frame);
+        }
+        if !swap_out_any() {
+            return None;
+        }
+    }
+}
+
+/// allocate a free frame without swapping anything out
+pub fn try_frame_alloc() -> Option<FrameTracker> {
+    FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
 }
 
 /// deallocate a frame
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    FRAME_ALLOCATOR.lock().dealloc(ppn);
 }
 
 #[allow(unused)]
@@ -1,11 +1,13 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, FrameTracker};
+use super::stack_allocator::StackAllocator;
+use super::{frame_alloc, try_frame_alloc, FrameTracker};
+use super::{swap_alloc, SwapOut, SwapSlot};
+use super::{tlb_shootdown, StepByOne, VPNRange};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
-use crate::sync::UPSafeCell;
+use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_HEAP_LIMIT, USER_SPACE_END};
+use crate::sync::SpinLock;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -27,19 +29,27 @@ extern "C" {
 
 lazy_static! {
     /// a memory set instance through lazy_static! managing kernel space
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
-        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> =
+        Arc::new(SpinLock::new(MemorySet::new_kernel()));
 }
 
 /// Get the token of the kernel memory space
 pub fn kernel_token() -> usize {
-    KERNEL_SPACE.exclusive_access().token()
+    KERNEL_SPACE.lock().token()
 }
 
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// The last user page the clock hand of swapping has visited
+    clock_hand: VirtPageNum,
+    /// Start of the heap area, right after the program loaded
+    heap_bottom: usize,
+    /// The program break, the end of the heap
+    brk: usize,
+    /// The user stacks of the threads, above the heap
+    stacks: StackAllocator,
 }
 
 impl MemorySet {
@@ -47,6 +57,10 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            clock_hand: VirtPageNum(0),
+            heap_bottom: 0,
+            brk: 0,
+            stacks: StackAllocator::new(0),
         }
     }
     pub fn token(&self) -> usize {
@@ -64,17 +78,302 @@ impl MemorySet {
             None,
         );
     }
+    /// Map `[start_va, end_va)` lazily with `permission`, frames are allocated
+    /// on first access in [`MemorySet::handle_page_fault`].
+    /// Returns false if the range overlaps an existing area.
+    pub fn mmap(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) -> bool {
+        let map_area = MapArea::new(start_va, end_va, MapType::Framed, permission);
+        let (start_vpn, end_vpn) = (map_area.vpn_range.get_start(), map_area.vpn_range.get_end());
+        if end_vpn > VirtAddr::from(USER_SPACE_END).floor()
+            || self.areas.iter().any(|area| area.overlaps(start_vpn, end_vpn))
+        {
+            return false;
+        }
+        self.areas.push(map_area);
+        true
+    }
+    /// Unmap the user pages in `[start_va, end_va)`, areas only partially covered
+    /// are split. Returns false if any page in the range is not in a user area.
+    pub fn munmap(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        let mut vpn = start_vpn;
+        while vpn < end_vpn {
+            if !self.areas.iter().any(|area| {
+                area.map_perm.contains(MapPermission::U) && area.contains(vpn)
+            }) {
+                return false;
+            }
+            vpn.step();
+        }
+        self.unmap_range(start_vpn, end_vpn);
+        true
+    }
+    /// Unmap whatever user pages there are in `[start_vpn, end_vpn)`, areas only
+    /// partially covered are split
+    fn unmap_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let mut unmapped_frames = Vec::new();
+        for mut area in core::mem::take(&mut self.areas) {
+            if !area.map_perm.contains(MapPermission::U) || !area.overlaps(start_vpn, end_vpn) {
+                self.areas.push(area);
+                continue;
+            }
+            if area.vpn_range.get_start() < start_vpn {
+                let rest = area.split_off(start_vpn);
+                self.areas.push(area);
+                area = rest;
+            }
+            if end_vpn < area.vpn_range.get_end() {
+                let rest = area.split_off(end_vpn);
+                self.areas.push(rest);
+            }
+            // keep the frames until no hart can reach them through its TLB
+            unmapped_frames.extend(area.data_frames.values().cloned());
+            area.unmap(&mut self.page_table);
+        }
+        tlb_shootdown(self.token());
+        drop(unmapped_frames);
+    }
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `new_brk`, the heap area grows or shrinks with it.
+    /// Returns false if the heap would go below its bottom, beyond `USER_HEAP_LIMIT`
+    /// or into another area.
+    ///
+    /// The heap may have been split or cut short by `munmap`, pages unmapped
+    /// below the old break stay unmapped.
+    pub fn set_brk(&mut self, new_brk: usize) -> bool {
+        if new_brk < self.heap_bottom || new_brk > self.heap_bottom + USER_HEAP_LIMIT {
+            return false;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        let heap_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        if new_end > old_end {
+            if self
+                .areas
+                .iter()
+                .any(|area| area.overlaps(old_end, new_end))
+            {
+                return false;
+            }
+            // the new pages are mapped lazily, into what is left of the heap
+            // right below them or into a new area if that is gone
+            match self.areas.iter_mut().find(|area| {
+                area.vpn_range.get_start() >= heap_start
+                    && area.vpn_range.get_end() == old_end
+                    && area.map_type == MapType::Framed
+                    && area.map_perm == heap_perm
+            }) {
+                Some(area) => {
+                    area.vpn_range = VPNRange::new(area.vpn_range.get_start(), new_end);
+                }
+                None => self.areas.push(MapArea::new(
+                    old_end.into(),
+                    new_end.into(),
+                    MapType::Framed,
+                    heap_perm,
+                )),
+            }
+        } else if new_end < old_end {
+            self.unmap_range(new_end, old_end);
+        }
+        self.brk = new_brk;
+        true
+    }
+    /// Bottom of a new user stack of `size` bytes, with a guard page below
+    /// and room for the TLS area above. None if the stack region is full.
+    pub fn alloc_ustack(&mut self, size: usize) -> Option<usize> {
+        self.stacks.alloc(size)
+    }
+    /// Give back the user stack at `bottom`, its areas are removed by the caller
+    pub fn dealloc_ustack(&mut self, bottom: usize) {
+        self.stacks.dealloc(bottom);
+    }
+    /// Whether `va` is in the guard page below a user stack
+    pub fn is_stack_guard(&self, va: VirtAddr) -> bool {
+        self.stacks.is_guard(va.into())
+    }
+    /// Extend the main stack down to `vpn` if it lies in the room left for it
+    fn grow_main_stack(&mut self, vpn: VirtPageNum) {
+        let top = VirtAddr::from(self.stacks.main_top()).floor();
+        if vpn < VirtAddr::from(self.stacks.main_limit()).floor() || vpn >= top {
+            return;
+        }
+        let idx = match self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_end() == top)
+        {
+            Some(idx) => idx,
+            None => return,
+        };
+        let old_start = self.areas[idx].vpn_range.get_start();
+        if vpn >= old_start || self.areas.iter().any(|area| area.overlaps(vpn, old_start)) {
+            return;
+        }
+        // the new pages are mapped lazily
+        self.areas[idx].vpn_range = VPNRange::new(vpn, top);
+    }
+    /// Resolve a fault at `va` needing `access`: a lazily allocated page is mapped,
+    /// a swapped out page is read back, a write to a copy-on-write page gets its
+    /// own frame and the main stack grows down to it. Returns false if the fault
+    /// cannot be resolved and is a real one.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: MapPermission) -> bool {
+        let vpn = va.floor();
+        self.grow_main_stack(vpn);
+        let idx = match self.areas.iter().position(|area| area.contains(vpn)) {
+            Some(idx)
+                if self.areas[idx].map_type == MapType::Framed
+                    && self.areas[idx].map_perm.contains(access | MapPermission::U) =>
+            {
+                idx
+            }
+            _ => return false,
+        };
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                let area = &mut self.areas[idx];
+                // a writable area maps a page read-only only if it is shared
+                if access.contains(MapPermission::W) && !pte.writable() {
+                    area.copy_on_write(&mut self.page_table, vpn);
+                    // other threads may still read the shared frame
+                    tlb_shootdown(self.token());
+                    true
+                } else {
+                    // another hart has just resolved it, our TLB was stale
+                    (access.contains(MapPermission::R) && pte.readable())
+                        || (access.contains(MapPermission::W) && pte.writable())
+                        || (access.contains(MapPermission::X) && pte.executable())
+                }
+            }
+            _ => {
+                let frame = self.alloc_frame();
+                self.areas[idx].map_frame(&mut self.page_table, vpn, frame);
+                true
+            }
+        }
+    }
+    /// Set the D bit of a mapped page, for pages the kernel writes to
+    pub fn set_dirty(&mut self, vpn: VirtPageNum) {
+        self.page_table.set_dirty(vpn);
+    }
+    /// The frame of a page in memory
+    pub fn frame(&self, vpn: VirtPageNum) -> Option<Arc<FrameTracker>> {
+        self.areas
+            .iter()
+            .find(|area| area.contains(vpn))
+            .and_then(|area| area.data_frames.get(&vpn))
+            .map(Arc::clone)
+    }
+    /// A frame for a user page, when memory is full the pages of this space
+    /// are swapped out first and those of the other spaces next. The pages of
+    /// this space are written out with it locked, by the caller faulting on it.
+    fn alloc_frame(&mut self) -> FrameTracker {
+        loop {
+            if let Some(frame) = try_frame_alloc() {
+                return frame;
+            }
+            if self.swap_out().is_none() {
+                return frame_alloc().unwrap();
+            }
+        }
+    }
+    /// Swap out a user page picked by the clock algorithm: the hand sweeps the
+    /// pages in memory in the order of vpn and passes over those accessed since
+    /// its last visit, clearing their A bit. Returns None if no page can go,
+    /// the page is written out when the returned [`SwapOut`] is dropped.
+    pub fn swap_out(&mut self) -> Option<SwapOut> {
+        let resident: usize = self
+            .areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .map(|area| area.data_frames.len())
+            .sum();
+        // the second round finds the A bits cleared in the first one
+        for _ in 0..2 * resident {
+            let (idx, vpn) = self.next_resident(self.clock_hand)?;
+            self.clock_hand = vpn;
+            // frames shared copy-on-write or pinned by the kernel stay
+            if Arc::strong_count(&self.areas[idx].data_frames[&vpn]) > 1 {
+                continue;
+            }
+            if self.page_table.translate(vpn).unwrap().accessed() {
+                self.page_table.clear_accessed(vpn);
+                continue;
+            }
+            return self.swap_out_page(idx, vpn);
+        }
+        None
+    }
+    /// The first user page in memory after `vpn` and the index of its area,
+    /// the search wraps around to the lowest one
+    fn next_resident(&self, vpn: VirtPageNum) -> Option<(usize, VirtPageNum)> {
+        let first_from = |start: VirtPageNum| {
+            self.areas
+                .iter()
+                .enumerate()
+                .filter(|(_, area)| area.map_perm.contains(MapPermission::U))
+                .filter_map(|(idx, area)| {
+                    area.data_frames
+                        .range(start..)
+                        .next()
+                        .map(|(vpn, _)| (idx, *vpn))
+                })
+                .min_by_key(|(_, vpn)| *vpn)
+        };
+        first_from(VirtPageNum(vpn.0 + 1)).or_else(|| first_from(VirtPageNum(0)))
+    }
+    /// Unmap a page to be written out to its slot, a clean page whose slot
+    /// still holds it is just dropped. Returns None if the swap device is full.
+    fn swap_out_page(&mut self, idx: usize, vpn: VirtPageNum) -> Option<SwapOut> {
+        let token = self.token();
+        let area = &mut self.areas[idx];
+        let slot = match area.swapped.get(&vpn) {
+            Some(slot) => Arc::clone(slot),
+            None => Arc::new(swap_alloc()?),
+        };
+        let pte = self.page_table.take(vpn);
+        // no hart may write the page any more while it is written out
+        tlb_shootdown(token);
+        let frame = area.data_frames.remove(&vpn).unwrap();
+        let page = if pte.dirty() || !area.swapped.contains_key(&vpn) {
+            SwapOut::new(frame, Some(Arc::clone(&slot)))
+        } else {
+            SwapOut::new(frame, None)
+        };
+        area.swapped.insert(vpn, slot);
+        Some(page)
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
-        if let Some((idx, area)) = self
+        if let Some(idx) = self
             .areas
-            .iter_mut()
-            .enumerate()
-            .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
         {
-            area.unmap(&mut self.page_table);
-            self.areas.remove(idx);
+            self.remove_area(idx);
         }
     }
+    /// Remove the area containing `vpn`, for areas like the main stack whose
+    /// start moves as they grow
+    pub fn remove_area_containing(&mut self, vpn: VirtPageNum) {
+        if let Some(idx) = self.areas.iter().position(|area| area.contains(vpn)) {
+            self.remove_area(idx);
+        }
+    }
+    fn remove_area(&mut self, idx: usize) {
+        let mut area = self.areas.remove(idx);
+        let unmapped_frames: Vec<_> = area.data_frames.values().cloned().collect();
+        area.unmap(&mut self.page_table);
+        tlb_shootdown(self.token());
+        drop(unmapped_frames);
+    }
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
@@ -204,34 +503,64 @@ impl MemorySet {
                 );
             }
         }
+        // the heap starts empty right after the program
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.brk = memory_set.heap_bottom;
+        memory_set.areas.push(MapArea::new(
+            max_end_va,
+            max_end_va,
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        ));
         // We don't map user stack and trapframe here since they will be later
         // allocated through TaskControlBlock::new()
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_top: usize = max_end_va.into();
-        user_stack_top += PAGE_SIZE;
+        let stack_base = memory_set.heap_bottom + USER_HEAP_LIMIT + PAGE_SIZE;
+        memory_set.stacks = StackAllocator::new(stack_base);
+        let user_stack_top = memory_set.stacks.main_top();
         (
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
         )
     }
-    /// Copy an identical user_space
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// Copy an identical user_space, user pages are shared copy-on-write
+    /// between the two spaces and mapped read-only in both of them
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        memory_set.stacks = user_space.stacks.clone();
         // map trampoline
         memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
+        // share data sections/user_stack, copy trap_context
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+            let mut new_area = MapArea::from_another(area);
+            if area.map_perm.contains(MapPermission::U) {
+                let pte_flags = PTEFlags::from_bits((area.map_perm - MapPermission::W).bits).unwrap();
+                for (vpn, frame) in area.data_frames.iter() {
+                    user_space.page_table.remap(*vpn, frame.ppn, pte_flags);
+                    memory_set.page_table.map(*vpn, frame.ppn, pte_flags);
+                    new_area.data_frames.insert(*vpn, Arc::clone(frame));
+                }
+                // slots of pages swapped out are shared too, the clean copies
+                // of pages in memory stay with the parent
+                for (vpn, slot) in area.swapped.iter() {
+                    if !area.data_frames.contains_key(vpn) {
+                        new_area.swapped.insert(*vpn, Arc::clone(slot));
+                    }
+                }
+            } else {
+                // copy data of the mapped pages, lazily mapped pages stay lazy
+                for (vpn, frame) in area.data_frames.iter() {
+                    new_area.map_one(&mut memory_set.page_table, *vpn);
+                    new_area.data_frames[vpn]
+                        .ppn
+                        .get_bytes_array()
+                        .copy_from_slice(frame.ppn.get_bytes_array());
+                }
             }
+            memory_set.areas.push(new_area);
         }
         memory_set
     }
@@ -250,10 +579,14 @@ impl MemorySet {
         self.areas.clear();
     }
     pub fn kernel_copy() -> Self {
-        let areas = KERNEL_SPACE.exclusive_access().areas.clone();
+        let areas = KERNEL_SPACE.lock().areas.clone();
         Self {
             page_table: PageTable::from_token(kernel_token()),
             areas: areas,
+            clock_hand: VirtPageNum(0),
+            heap_bottom: 0,
+            brk: 0,
+            stacks: StackAllocator::new(0),
         }
     }
 }
@@ -262,7 +595,10 @@ impl MemorySet {
 #[derive(Clone)]
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// Slots of the pages swapped out, a page back in memory keeps its slot
+    /// as long as it is clean
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
     map_type: MapType,
     map_perm: MapPermission,
 }
@@ -279,6 +615,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type,
             map_perm,
         }
@@ -287,6 +624,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
@@ -300,13 +638,46 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
+    /// Map `vpn` to `frame`, a page swapped out is read back into it first
+    pub fn map_frame(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, frame: FrameTracker) {
+        if let Some(slot) = self.swapped.get(&vpn) {
+            slot.read(frame.ppn);
+            // a slot shared with another space is left to it
+            if Arc::strong_count(slot) > 1 {
+                self.swapped.remove(&vpn);
+            }
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.map(vpn, frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, Arc::new(frame));
+    }
 
+    /// Whether `vpn` is inside this area
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+    /// Whether this area overlaps `[start_vpn, end_vpn)`
+    pub fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() < end_vpn && start_vpn < self.vpn_range.get_end()
+    }
+    /// Split the area at `at`, self keeps `[start, at)` and `[at, end)` is returned
+    pub fn split_off(&mut self, at: VirtPageNum) -> Self {
+        let end = self.vpn_range.get_end();
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        Self {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            swapped: self.swapped.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+        }
+    }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         #[allow(clippy::single_match)]
         match self.map_type {
@@ -317,14 +688,41 @@ impl MapArea {
         }
         page_table.unmap(vpn);
     }
+    /// Give a shared page its own frame and make it writable again
+    pub fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let frame = self.data_frames.get_mut(&vpn).unwrap();
+        // the last one sharing the frame takes it over
+        if Arc::strong_count(frame) > 1 {
+            let new_frame = frame_alloc().unwrap();
+            new_frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            *frame = Arc::new(new_frame);
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.remap(vpn, frame.ppn, pte_flags);
+    }
     pub fn map(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.unmap_one(page_table, vpn);
+        match self.map_type {
+            MapType::Identical => {
+                for vpn in self.vpn_range {
+                    self.unmap_one(page_table, vpn);
+                }
+            }
+            // only the pages touched so far have been mapped
+            MapType::Framed => {
+                let vpns: Vec<VirtPageNum> = self.data_frames.keys().copied().collect();
+                for vpn in vpns {
+                    self.unmap_one(page_table, vpn);
+                }
+                self.swapped.clear();
+            }
         }
     }
     /// data: start-aligned but maybe with shorter length
@@ -370,7 +768,7 @@ bitflags! {
 
 #[allow(unused)]
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let mut kernel_space = KERNEL_SPACE.lock();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
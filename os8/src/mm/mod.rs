@@ -12,18 +12,33 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod stack_allocator;
+mod swap;
+mod tlb;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 pub use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+pub use frame_allocator::{frame_alloc, frame_dealloc, try_frame_alloc, FrameTracker};
 pub use memory_set::{remap_test, kernel_token};
 pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, translated_refmut, translated_ref, translated_str, PageTableEntry};
+pub use page_table::{
+    copy_from_user, copy_to_memory_set, copy_to_user, translated_byte_buffer,
+    translated_ref, translated_refmut, translated_str,
+    try_copy_from_user, try_copy_to_user, try_translated_ref, try_translated_refmut, unpin_user_pages,
+    PageTableEntry,
+};
 pub use page_table::{PTEFlags, PageTable, UserBuffer};
+pub use swap::{swap_alloc, SwapOut, SwapSlot};
+pub use tlb::{enter_user, leave_user, tlb_shootdown};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    KERNEL_SPACE.lock().activate();
+}
+
+/// switch a hart started after the boot hart to the kernel space
+pub fn init_other_hart() {
+    KERNEL_SPACE.lock().activate();
 }
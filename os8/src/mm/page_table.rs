@@ -1,10 +1,14 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use super::{MapPermission, MemorySet};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, USER_SPACE_END};
+use crate::task::{current_process, current_task};
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 bitflags! {
     /// page table entry flags
@@ -54,6 +58,12 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
 }
 
 /// page table structure
@@ -126,6 +136,37 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
     }
+    /// Point a mapped vpn to `ppn` with new flags
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+    /// The entry of a mapped vpn, the hardware may set its A/D bits on any hart
+    /// at the same time, so it is only changed atomically
+    fn atomic_pte(&self, vpn: VirtPageNum) -> &AtomicUsize {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid", vpn);
+        unsafe { &*(pte as *const PageTableEntry as *const AtomicUsize) }
+    }
+    /// Clear the A bit of a mapped vpn, it is set again on the next access
+    /// missing the TLB, which is flushed on every trap
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) {
+        self.atomic_pte(vpn)
+            .fetch_and(!(PTEFlags::A.bits as usize), Ordering::SeqCst);
+    }
+    /// Set the D bit of a mapped vpn, for pages the kernel writes to
+    pub fn set_dirty(&mut self, vpn: VirtPageNum) {
+        self.atomic_pte(vpn)
+            .fetch_or(PTEFlags::D.bits as usize, Ordering::SeqCst);
+    }
+    /// Unmap a vpn and return its last entry, without losing the A/D bits
+    /// the hardware sets in the meantime
+    pub fn take(&mut self, vpn: VirtPageNum) -> PageTableEntry {
+        PageTableEntry {
+            bits: self.atomic_pte(vpn).swap(0, Ordering::SeqCst),
+        }
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).copied()
     }
@@ -144,16 +185,121 @@ impl PageTable {
     }
 }
 
-/// translate a pointer to a mutable u8 Vec through page table
+/// Translate a user page through page table, a page of the current process
+/// that is still lazily mapped, copy-on-write or swapped out gets resolved here
+/// as if the user touched it, `write` tells whether the kernel is going to write it.
+/// Resolving a page locks the inner of the current process, so none of the
+/// translators below may be called with it held, see [`copy_to_memory_set`].
+fn translate_user_page(
+    page_table: &PageTable,
+    token: usize,
+    vpn: VirtPageNum,
+    write: bool,
+) -> PhysPageNum {
+    try_translate_user_page(page_table, token, vpn, write)
+        .unwrap_or_else(|| panic!("vpn {:?} is invalid", vpn))
+}
+
+/// Like [`translate_user_page`], but `None` if the user may not access `vpn`
+fn try_translate_user_page(
+    page_table: &PageTable,
+    token: usize,
+    vpn: VirtPageNum,
+    write: bool,
+) -> Option<PhysPageNum> {
+    if vpn.0 >= USER_SPACE_END >> PAGE_SIZE_BITS {
+        return None;
+    }
+    match page_table.translate(vpn) {
+        Some(pte) if is_user_page(&pte) && (!write || pte.dirty()) => return Some(pte.ppn()),
+        _ => {}
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    assert_eq!(inner.get_user_token(), token, "vpn {:?} is invalid", vpn);
+    resolve_user_page(&mut inner.memory_set, vpn, write)
+}
+
+/// Whether an entry maps a page the user may access
+fn is_user_page(pte: &PageTableEntry) -> bool {
+    pte.is_valid() && pte.flags().contains(PTEFlags::U)
+}
+
+/// Resolve the page faults an access of the user to `vpn` would take,
+/// `None` if the user may not access it
+fn resolve_user_page(
+    memory_set: &mut MemorySet,
+    vpn: VirtPageNum,
+    write: bool,
+) -> Option<PhysPageNum> {
+    // buffers of read-only areas may still be handed over for writing
+    if !(write && memory_set.handle_page_fault(vpn.into(), MapPermission::W)) {
+        memory_set.handle_page_fault(vpn.into(), MapPermission::empty());
+    }
+    match memory_set.translate(vpn) {
+        Some(pte) if is_user_page(&pte) => {
+            // the kernel writes through the physical address, which sets no D bit
+            if write {
+                memory_set.set_dirty(vpn);
+            }
+            Some(pte.ppn())
+        }
+        _ => None,
+    }
+}
+
+/// Translate a user page and keep it from being swapped out until the syscall
+/// returns, as the kernel keeps using it after later page faults
+fn pin_user_page(token: usize, vpn: VirtPageNum, write: bool) -> PhysPageNum {
+    try_pin_user_page(token, vpn, write).unwrap_or_else(|| panic!("vpn {:?} is invalid", vpn))
+}
+
+/// Like [`pin_user_page`], but `None` if the user may not access `vpn`
+fn try_pin_user_page(token: usize, vpn: VirtPageNum, write: bool) -> Option<PhysPageNum> {
+    if vpn.0 >= USER_SPACE_END >> PAGE_SIZE_BITS {
+        return None;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    assert_eq!(inner.get_user_token(), token, "vpn {:?} is invalid", vpn);
+    let ppn = resolve_user_page(&mut inner.memory_set, vpn, write)?;
+    let frame = inner.memory_set.frame(vpn);
+    drop(inner);
+    if let Some(frame) = frame {
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .pinned_frames
+            .push(frame);
+    }
+    Some(ppn)
+}
+
+/// Let the pages pinned by the syscall of the current thread be swapped out again
+pub fn unpin_user_pages() {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .pinned_frames
+        .clear();
+}
+
+/// Translate a user virtual address through page table
+fn translate_user_va(page_table: &PageTable, token: usize, va: VirtAddr, write: bool) -> PhysAddr {
+    let aligned_pa: PhysAddr = translate_user_page(page_table, token, va.floor(), write).into();
+    (aligned_pa.0 + va.page_offset()).into()
+}
+
+/// translate a pointer to a mutable u8 Vec through page table, the pages are
+/// pinned in memory until the syscall returns
 pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
     let end = start + len;
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = pin_user_page(token, vpn, true);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -172,10 +318,7 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let ch: u8 = *(translate_user_va(&page_table, token, VirtAddr::from(va), false).get_mut());
         if ch == 0 {
             break;
         } else {
@@ -186,20 +329,117 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
     string
 }
 
+/// Translate a pointer to a `T` in user space, the page is pinned in memory
+/// until the syscall returns
 pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table.translate_va(VirtAddr::from(ptr as usize)).unwrap().get_mut()
+    let va = VirtAddr::from(ptr as usize);
+    let aligned_pa: PhysAddr = pin_user_page(token, va.floor(), false).into();
+    PhysAddr::from(aligned_pa.0 + va.page_offset()).get_ref()
 }
 
+/// Like [`translated_ref`], for a `T` the kernel writes to
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    //println!("into translated_refmut!");
+    let va = VirtAddr::from(ptr as usize);
+    let aligned_pa: PhysAddr = pin_user_page(token, va.floor(), true).into();
+    PhysAddr::from(aligned_pa.0 + va.page_offset()).get_mut()
+}
+
+/// Like [`translated_ref`], but `None` instead of panicking if the user may
+/// not read `ptr`
+pub fn try_translated_ref<T>(token: usize, ptr: *const T) -> Option<&'static T> {
+    let va = VirtAddr::from(ptr as usize);
+    let aligned_pa: PhysAddr = try_pin_user_page(token, va.floor(), false)?.into();
+    Some(PhysAddr::from(aligned_pa.0 + va.page_offset()).get_ref())
+}
+
+/// Like [`translated_refmut`], but `None` instead of panicking if the user may
+/// not write `ptr`
+pub fn try_translated_refmut<T>(token: usize, ptr: *mut T) -> Option<&'static mut T> {
+    let va = VirtAddr::from(ptr as usize);
+    let aligned_pa: PhysAddr = try_pin_user_page(token, va.floor(), true)?.into();
+    Some(PhysAddr::from(aligned_pa.0 + va.page_offset()).get_mut())
+}
+
+/// Copy `src` into user space at `dst` through page table, which may cross pages.
+/// A page is done before the next one is translated, which may swap it out.
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) {
     let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    //println!("translated_refmut: before translate_va");
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+    let mut offset = 0;
+    while offset < src.len() {
+        let va = VirtAddr::from(dst as usize + offset);
+        let start = va.page_offset();
+        let len = (PAGE_SIZE - start).min(src.len() - offset);
+        let ppn = translate_user_page(&page_table, token, va.floor(), true);
+        ppn.get_bytes_array()[start..start + len].copy_from_slice(&src[offset..offset + len]);
+        offset += len;
+    }
+}
+
+/// Like [`copy_to_user`], but returns false instead of panicking if the
+/// user may not write some byte of `dst`, what fits before is copied
+pub fn try_copy_to_user(token: usize, dst: *mut u8, src: &[u8]) -> bool {
+    let page_table = PageTable::from_token(token);
+    let mut offset = 0;
+    while offset < src.len() {
+        let va = match (dst as usize).checked_add(offset) {
+            Some(va) => VirtAddr::from(va),
+            None => return false,
+        };
+        let start = va.page_offset();
+        let len = (PAGE_SIZE - start).min(src.len() - offset);
+        let ppn = match try_translate_user_page(&page_table, token, va.floor(), true) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        ppn.get_bytes_array()[start..start + len].copy_from_slice(&src[offset..offset + len]);
+        offset += len;
+    }
+    true
+}
+
+/// Copy `src` into the user space of `memory_set` at `dst`, for a caller
+/// holding the inner of the process owning it or building a new process
+pub fn copy_to_memory_set(memory_set: &mut MemorySet, dst: usize, src: &[u8]) {
+    let mut offset = 0;
+    while offset < src.len() {
+        let va = VirtAddr::from(dst + offset);
+        let start = va.page_offset();
+        let len = (PAGE_SIZE - start).min(src.len() - offset);
+        let ppn = resolve_user_page(memory_set, va.floor(), true).unwrap();
+        ppn.get_bytes_array()[start..start + len].copy_from_slice(&src[offset..offset + len]);
+        offset += len;
+    }
+}
+
+/// Copy user space at `src` into `dst` through page table, which may cross pages
+pub fn copy_from_user(token: usize, src: *const u8, dst: &mut [u8]) {
+    assert!(
+        try_copy_from_user(token, src, dst),
+        "{:?} is invalid",
+        VirtAddr::from(src as usize)
+    );
+}
+
+/// Like [`copy_from_user`], but returns false instead of panicking if the
+/// user may not read some byte of `src`
+pub fn try_copy_from_user(token: usize, src: *const u8, dst: &mut [u8]) -> bool {
+    let page_table = PageTable::from_token(token);
+    let mut offset = 0;
+    while offset < dst.len() {
+        let va = match (src as usize).checked_add(offset) {
+            Some(va) => VirtAddr::from(va),
+            None => return false,
+        };
+        let start = va.page_offset();
+        let len = (PAGE_SIZE - start).min(dst.len() - offset);
+        let ppn = match try_translate_user_page(&page_table, token, va.floor(), false) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        dst[offset..offset + len].copy_from_slice(&ppn.get_bytes_array()[start..start + len]);
+        offset += len;
+    }
+    true
 }
 
 /// An abstraction over a buffer passed from user space to kernel space
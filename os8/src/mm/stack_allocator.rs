@@ -0,0 +1,89 @@
+//! Allocation of the user stacks of the threads of a process
+//!
+//! The stacks are placed in a region above the heap. The main stack sits at
+//! the top of the region and grows down on demand, the stacks of the other
+//! threads are allocated below it with the sizes they ask for. Every stack has
+//! its TLS area right above it and an unmapped guard page right below it, so
+//! an overflow faults instead of running into its neighbour.
+
+use crate::config::{PAGE_SIZE, USER_MAIN_STACK_LIMIT, USER_STACK_REGION_SIZE, USER_TLS_SIZE};
+use alloc::collections::BTreeMap;
+use core::ops::Bound::{Excluded, Unbounded};
+
+#[derive(Clone)]
+pub struct StackAllocator {
+    /// Top of the main stack
+    main_top: usize,
+    /// Free ranges below the main stack, from their start to their end
+    free: BTreeMap<usize, usize>,
+    /// Stacks given out, from their bottom to their size
+    used: BTreeMap<usize, usize>,
+}
+
+impl StackAllocator {
+    /// Stacks in the region starting at `base`
+    pub fn new(base: usize) -> Self {
+        let main_top = base + USER_STACK_REGION_SIZE - USER_TLS_SIZE;
+        let mut free = BTreeMap::new();
+        // the main stack and its guard page take the top of the region
+        free.insert(base, main_top - USER_MAIN_STACK_LIMIT - PAGE_SIZE);
+        Self {
+            main_top,
+            free,
+            used: BTreeMap::new(),
+        }
+    }
+    pub fn main_top(&self) -> usize {
+        self.main_top
+    }
+    /// The lowest address the main stack may grow down to
+    pub fn main_limit(&self) -> usize {
+        self.main_top - USER_MAIN_STACK_LIMIT
+    }
+    /// Bottom of a new stack of `size` bytes, a multiple of the page size,
+    /// None if there is no room left
+    pub fn alloc(&mut self, size: usize) -> Option<usize> {
+        // with its guard page and TLS area
+        let block = size.checked_add(PAGE_SIZE + USER_TLS_SIZE)?;
+        let (start, end) = self
+            .free
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .find(|(start, end)| end - start >= block)?;
+        self.free.remove(&start);
+        if end - start > block {
+            self.free.insert(start + block, end);
+        }
+        let bottom = start + PAGE_SIZE;
+        self.used.insert(bottom, size);
+        Some(bottom)
+    }
+    /// Give back the stack at `bottom` together with its guard page and TLS area
+    pub fn dealloc(&mut self, bottom: usize) {
+        let size = self.used.remove(&bottom).unwrap();
+        let mut start = bottom - PAGE_SIZE;
+        let mut end = bottom + size + USER_TLS_SIZE;
+        // merge with the free ranges around
+        if let Some(next_end) = self.free.remove(&end) {
+            end = next_end;
+        }
+        if let Some((&prev_start, &prev_end)) = self.free.range(..start).next_back() {
+            if prev_end == start {
+                self.free.remove(&prev_start);
+                start = prev_start;
+            }
+        }
+        self.free.insert(start, end);
+    }
+    /// Whether `va` is in the guard page of a stack
+    pub fn is_guard(&self, va: usize) -> bool {
+        let main_limit = self.main_limit();
+        if (main_limit - PAGE_SIZE..main_limit).contains(&va) {
+            return true;
+        }
+        self.used
+            .range((Excluded(va), Unbounded))
+            .next()
+            .map_or(false, |(&bottom, _)| bottom - PAGE_SIZE <= va)
+    }
+}
@@ -0,0 +1,135 @@
+//! Swapping user pages out to the swap device
+//!
+//! When memory is full, user pages are written to a second virtio disk with
+//! no file system on it and read back on the page fault of their next access.
+//! Every page swapped out takes a slot of `PAGE_SIZE` bytes on the disk, slots
+//! are shared between address spaces like frames through `Arc<SwapSlot>`.
+
+use super::{FrameTracker, PhysPageNum};
+use crate::config::{PAGE_SIZE, SWAP_PAGES};
+use crate::drivers::SWAP_DEVICE;
+use crate::sync::SpinLock;
+use crate::task::all_processes;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+use easy_fs::BLOCK_SZ;
+use lazy_static::*;
+
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SZ;
+
+/// a slot on the swap device, freed when the tracker is dropped
+pub struct SwapSlot {
+    id: usize,
+    /// A page unmapped already is still being written into the slot
+    writing: AtomicBool,
+}
+
+impl SwapSlot {
+    /// Write the page of `ppn` into the slot
+    fn write(&self, ppn: PhysPageNum) {
+        let device = SWAP_DEVICE.as_ref().unwrap();
+        for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SZ).enumerate() {
+            device.write_block(self.id * BLOCKS_PER_SLOT + i, block);
+        }
+        self.writing.store(false, Ordering::Release);
+    }
+    /// Read the slot into the page of `ppn`, once the page written into it is there
+    pub fn read(&self, ppn: PhysPageNum) {
+        while self.writing.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        let device = SWAP_DEVICE.as_ref().unwrap();
+        for (i, block) in ppn.get_bytes_array().chunks_mut(BLOCK_SZ).enumerate() {
+            device.read_block(self.id * BLOCKS_PER_SLOT + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_ALLOCATOR.lock().dealloc(self.id);
+    }
+}
+
+/// A page taken out of its address space, written into its slot when dropped,
+/// which is done with the address space unlocked. Its frame is freed then.
+pub struct SwapOut {
+    frame: Arc<FrameTracker>,
+    /// None for a clean page its slot still holds
+    slot: Option<Arc<SwapSlot>>,
+}
+
+impl SwapOut {
+    pub fn new(frame: Arc<FrameTracker>, slot: Option<Arc<SwapSlot>>) -> Self {
+        // a page fault on the page waits for it to be in the slot
+        if let Some(slot) = &slot {
+            slot.writing.store(true, Ordering::Relaxed);
+        }
+        Self { frame, slot }
+    }
+}
+
+impl Drop for SwapOut {
+    fn drop(&mut self) {
+        if let Some(slot) = &self.slot {
+            slot.write(self.frame.ppn);
+        }
+    }
+}
+
+/// slots never used are taken from `current` on, freed ones are recycled first
+struct SwapAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(id) = self.recycled.pop() {
+            Some(id)
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, id: usize) {
+        if id >= self.current || self.recycled.contains(&id) {
+            panic!("Swap slot {} has not been allocated!", id);
+        }
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    static ref SWAP_ALLOCATOR: SpinLock<SwapAllocator> = SpinLock::new(SwapAllocator {
+        current: 0,
+        end: if SWAP_DEVICE.is_some() { SWAP_PAGES } else { 0 },
+        recycled: Vec::new(),
+    });
+}
+
+/// allocate a slot, None if the swap device is full or missing
+pub fn swap_alloc() -> Option<SwapSlot> {
+    SWAP_ALLOCATOR.lock().alloc().map(|id| SwapSlot {
+        id,
+        writing: AtomicBool::new(false),
+    })
+}
+
+/// Swap out a page of any process whose address space no hart is changing,
+/// for a frame wanted by the kernel. Returns false if nothing could be freed.
+pub fn swap_out_any() -> bool {
+    all_processes().iter().any(|process| {
+        let page = match process.try_inner_exclusive_access() {
+            Some(mut inner) => inner.memory_set.swap_out(),
+            None => None,
+        };
+        // written out here, with the process unlocked
+        page.is_some()
+    })
+}
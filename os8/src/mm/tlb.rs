@@ -0,0 +1,67 @@
+//! TLB shootdown between harts
+//!
+//! `sfence.vma` only flushes the TLB of the hart executing it. Every trap
+//! switches `satp` and flushes the TLB in the trampoline, so after a page
+//! table has changed, only the harts running user code in that address space
+//! may still use its old translations. Interrupting them with an IPI is
+//! enough: the trap itself flushes their TLB.
+
+use crate::config::MAX_HARTS;
+use crate::sbi::send_ipi;
+use crate::task::hart_id;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IN_KERNEL: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_FLUSH: AtomicBool = AtomicBool::new(false);
+
+/// Token of the address space each hart runs user code in, 0 in the kernel
+static USER_TOKEN: [AtomicUsize; MAX_HARTS] = [IN_KERNEL; MAX_HARTS];
+/// Set for a hart asked to flush its TLB, cleared when it traps
+static FLUSH_PENDING: [AtomicBool; MAX_HARTS] = [NO_FLUSH; MAX_HARTS];
+
+/// The current hart is about to run user code in the address space of `token`,
+/// `__restore` flushes its TLB after this
+pub fn enter_user(token: usize) {
+    USER_TOKEN[hart_id()].store(token, Ordering::SeqCst);
+}
+
+/// The current hart has trapped into the kernel and `__alltraps` has flushed its TLB
+pub fn leave_user() {
+    let hart_id = hart_id();
+    USER_TOKEN[hart_id].store(0, Ordering::SeqCst);
+    FLUSH_PENDING[hart_id].store(false, Ordering::SeqCst);
+}
+
+/// Make sure that no hart uses the old translations of the address space of
+/// `token` once its page table has changed. The frames unmapped from it must
+/// not be freed before this returns.
+pub fn tlb_shootdown(token: usize) {
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    // the page table has to be written before we look at the other harts
+    fence(Ordering::SeqCst);
+    let current = hart_id();
+    let mut hart_mask = 0;
+    for hart in (0..MAX_HARTS).filter(|&hart| hart != current) {
+        if USER_TOKEN[hart].load(Ordering::SeqCst) == token {
+            FLUSH_PENDING[hart].store(true, Ordering::SeqCst);
+            hart_mask |= 1 << hart;
+        }
+    }
+    if hart_mask == 0 {
+        return;
+    }
+    send_ipi(hart_mask);
+    for hart in (0..MAX_HARTS).filter(|&hart| hart_mask & (1 << hart) != 0) {
+        // a hart that has left the space flushes its TLB before it comes back
+        while FLUSH_PENDING[hart].load(Ordering::SeqCst)
+            && USER_TOKEN[hart].load(Ordering::SeqCst) == token
+        {
+            spin_loop();
+        }
+    }
+}
@@ -7,6 +7,11 @@ const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
 const SBI_SHUTDOWN: usize = 8;
 
+const SBI_EXT_HSM: usize = 0x48534d;
+const SBI_HSM_HART_START: usize = 0;
+const SBI_EXT_IPI: usize = 0x735049;
+const SBI_IPI_SEND_IPI: usize = 0;
+
 #[inline(always)]
 /// general sbi call
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -24,6 +29,24 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+#[inline(always)]
+/// sbi call of an extension with the calling convention of SBI v0.2,
+/// returns the error code and the value
+fn sbi_call_ext(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
 /// use sbi call to set timer
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
@@ -39,6 +62,18 @@ pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
 
+/// use sbi call to start hart `hart_id` at the physical address `start_addr`
+/// with `opaque` in a1, returns whether the hart is starting
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> bool {
+    let (error, _) = sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_START, hart_id, start_addr, opaque);
+    error == 0
+}
+
+/// use sbi call to send a supervisor software interrupt to the harts in `hart_mask`
+pub fn send_ipi(hart_mask: usize) {
+    sbi_call_ext(SBI_EXT_IPI, SBI_IPI_SEND_IPI, hart_mask, 0, 0);
+}
+
 /// use sbi call to shutdown the kernel
 pub fn shutdown() -> ! {
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
@@ -0,0 +1,54 @@
+use crate::sync::SpinLock;
+use crate::task::{add_task, current_task, TaskControlBlock};
+use crate::timer::block_current_until;
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// A barrier releasing `count` threads together once they have all arrived,
+/// it can be used again right away for the next round
+pub struct Barrier {
+    inner: SpinLock<BarrierInner>,
+}
+
+struct BarrierInner {
+    count: usize,
+    wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Barrier {
+    pub fn new(count: usize) -> Self {
+        Self {
+            inner: SpinLock::new(BarrierInner {
+                count,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Wait for the other threads of this round, returns true for the one
+    /// arriving last, which wakes the others up. Returns None if a signal
+    /// going to kill the process comes first.
+    pub fn wait(self: Arc<Self>) -> Option<bool> {
+        let mut inner = self.inner.lock();
+        if inner.wait_queue.len() + 1 == inner.count {
+            for task in inner.wait_queue.drain(..) {
+                add_task(task);
+            }
+            return Some(true);
+        }
+        inner.wait_queue.push_back(current_task().unwrap());
+        drop(inner);
+        // a thread leaving early is no longer counted as arrived
+        let released = block_current_until(None, move |task| {
+            let mut inner = self.inner.lock();
+            let queue = &mut inner.wait_queue;
+            match queue.iter().position(|waiter| Arc::ptr_eq(waiter, task)) {
+                Some(index) => {
+                    queue.remove(index);
+                    true
+                }
+                None => false,
+            }
+        });
+        released.then_some(false)
+    }
+}
@@ -1,9 +1,10 @@
-use crate::sync::{Mutex, UPSafeCell};
-use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
+use crate::sync::{Mutex, MutexError, SpinLock};
+use crate::task::{add_task, current_task, TaskControlBlock};
+use crate::timer::block_current_until;
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPSafeCell<CondvarInner>,
+    pub inner: SpinLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -13,27 +14,85 @@ pub struct CondvarInner {
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(CondvarInner {
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(CondvarInner {
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
     pub fn signal(&self) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         if let Some(task) = inner.wait_queue.pop_front() {
             add_task(task);
         }
     }
 
-    pub fn wait(&self, mutex: Arc<dyn Mutex>) {
-        mutex.unlock();
-        let mut inner = self.inner.exclusive_access();
-        inner.wait_queue.push_back(current_task().unwrap());
-        drop(inner);
-        block_current_and_run_next();
-        mutex.lock();
+    pub fn broadcast(&self) {
+        let mut inner = self.inner.lock();
+        for task in inner.wait_queue.drain(..) {
+            add_task(task);
+        }
+    }
+
+    /// Wait for a signal with `mutex` unlocked, which the current thread must
+    /// hold. Returns the error of locking it again, or `MutexError::Interrupted`
+    /// if a signal going to kill the process comes first.
+    pub fn wait(self: Arc<Self>, mutex: Arc<dyn Mutex>) -> Result<(), MutexError> {
+        let signalled = self.sleep(&mutex, None)?;
+        mutex.lock()?;
+        if signalled {
+            Ok(())
+        } else {
+            Err(MutexError::Interrupted)
+        }
+    }
+
+    /// Like `wait`, but stop waiting once `expire_ms` has passed. The mutex is
+    /// locked again either way, its error comes before `MutexError::TimedOut`.
+    pub fn wait_until(
+        self: Arc<Self>,
+        mutex: Arc<dyn Mutex>,
+        expire_ms: usize,
+    ) -> Result<(), MutexError> {
+        let signalled = self.sleep(&mutex, Some(expire_ms))?;
+        mutex.lock()?;
+        if signalled {
+            Ok(())
+        } else {
+            Err(MutexError::TimedOut)
+        }
+    }
+
+    /// Sleep with `mutex` unlocked until signalled, returns false if the
+    /// wait has been cut short and the error of unlocking `mutex` if that fails
+    fn sleep(
+        self: Arc<Self>,
+        mutex: &Arc<dyn Mutex>,
+        expire_ms: Option<usize>,
+    ) -> Result<bool, MutexError> {
+        // queue up before unlocking, a signal from another hart in between
+        // would be lost otherwise
+        let task = current_task().unwrap();
+        self.inner.lock().wait_queue.push_back(Arc::clone(&task));
+        if let Err(err) = mutex.unlock() {
+            self.remove_waiter(&task);
+            return Err(err);
+        }
+        Ok(block_current_until(expire_ms, move |task| {
+            self.remove_waiter(task)
+        }))
+    }
+
+    /// Take `task` off the wait queue, false if it is not there any more
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = self.inner.lock();
+        let queue = &mut inner.wait_queue;
+        match queue.iter().position(|waiter| Arc::ptr_eq(waiter, task)) {
+            Some(index) => {
+                queue.remove(index);
+                true
+            }
+            None => false,
+        }
     }
 }
@@ -0,0 +1,183 @@
+//! Deadlock detection over the mutexes and semaphores of a process
+//!
+//! A [`ResourceTracker`] keeps the available, allocation and need matrices
+//! of the banker's algorithm. The syscall layer records every step with the
+//! id of the resource and the tid of the calling thread: a request before
+//! blocking, an acquire once the lock or the semaphore is really taken, a
+//! release before handing it back.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A resource of a process that threads may wait for
+#[derive(Copy, Clone, Debug)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// The matrices of one kind of resources, indexed by `[tid][id]`
+struct ResourceTable {
+    available: Vec<usize>,
+    allocation: Vec<Vec<usize>>,
+    need: Vec<Vec<usize>>,
+}
+
+impl ResourceTable {
+    fn new() -> Self {
+        Self {
+            available: Vec::new(),
+            allocation: Vec::new(),
+            need: Vec::new(),
+        }
+    }
+
+    /// Set up resource `id` with `count` units, none of them allocated
+    fn add(&mut self, id: usize, count: usize) {
+        if id >= self.available.len() {
+            self.available.resize(id + 1, 0);
+        }
+        self.available[id] = count;
+        for row in self.allocation.iter_mut().chain(self.need.iter_mut()) {
+            if id < row.len() {
+                row[id] = 0;
+            }
+        }
+    }
+
+    /// Make sure there are cells for thread `tid` and resource `id`
+    fn reserve(&mut self, tid: usize, id: usize) {
+        let columns = self.available.len().max(id + 1);
+        if tid >= self.allocation.len() {
+            self.allocation.resize(tid + 1, Vec::new());
+            self.need.resize(tid + 1, Vec::new());
+        }
+        for row in [&mut self.allocation[tid], &mut self.need[tid]] {
+            if row.len() < columns {
+                row.resize(columns, 0);
+            }
+        }
+    }
+
+    fn allocation(&self, tid: usize, id: usize) -> usize {
+        self.allocation
+            .get(tid)
+            .and_then(|row| row.get(id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn need(&self, tid: usize, id: usize) -> usize {
+        self.need
+            .get(tid)
+            .and_then(|row| row.get(id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn clear_thread(&mut self, tid: usize) {
+        if let Some(row) = self.allocation.get_mut(tid) {
+            row.fill(0);
+        }
+        if let Some(row) = self.need.get_mut(tid) {
+            row.fill(0);
+        }
+    }
+}
+
+/// Resource accounting of a process for deadlock detection
+pub struct ResourceTracker {
+    mutexes: ResourceTable,
+    semaphores: ResourceTable,
+}
+
+impl ResourceTracker {
+    pub fn new() -> Self {
+        Self {
+            mutexes: ResourceTable::new(),
+            semaphores: ResourceTable::new(),
+        }
+    }
+
+    fn table(&mut self, res: Resource) -> (&mut ResourceTable, usize) {
+        match res {
+            Resource::Mutex(id) => (&mut self.mutexes, id),
+            Resource::Semaphore(id) => (&mut self.semaphores, id),
+        }
+    }
+
+    /// A new resource with `count` units, it may reuse the id of a dropped one
+    pub fn add(&mut self, res: Resource, count: usize) {
+        let (table, id) = self.table(res);
+        table.add(id, count);
+    }
+
+    /// Thread `tid` is about to wait for a unit of `res`
+    pub fn request(&mut self, tid: usize, res: Resource) {
+        let (table, id) = self.table(res);
+        table.reserve(tid, id);
+        table.need[tid][id] += 1;
+    }
+
+    /// Thread `tid` gives up its request of `res`
+    pub fn cancel(&mut self, tid: usize, res: Resource) {
+        let (table, id) = self.table(res);
+        table.reserve(tid, id);
+        table.need[tid][id] = table.need[tid][id].saturating_sub(1);
+    }
+
+    /// The requested unit of `res` has been taken by thread `tid`,
+    /// whether at once or handed over by a releasing thread
+    pub fn acquire(&mut self, tid: usize, res: Resource) {
+        let (table, id) = self.table(res);
+        table.reserve(tid, id);
+        table.need[tid][id] = table.need[tid][id].saturating_sub(1);
+        table.available[id] = table.available[id].saturating_sub(1);
+        table.allocation[tid][id] += 1;
+    }
+
+    /// Thread `tid` puts back a unit of `res`, a semaphore may be
+    /// signalled by a thread that never took it
+    pub fn release(&mut self, tid: usize, res: Resource) {
+        let (table, id) = self.table(res);
+        table.reserve(tid, id);
+        table.allocation[tid][id] = table.allocation[tid][id].saturating_sub(1);
+        table.available[id] += 1;
+    }
+
+    /// Forget about an exited thread so that its tid starts clean when recycled,
+    /// what it still holds is lost as it is for the real resources
+    pub fn clear_thread(&mut self, tid: usize) {
+        self.mutexes.clear_thread(tid);
+        self.semaphores.clear_thread(tid);
+    }
+
+    /// Whether all threads can still finish in some order, i.e. there is no deadlock
+    pub fn is_safe(&self) -> bool {
+        let threads = self.mutexes.need.len().max(self.semaphores.need.len());
+        let mut mutex_work = self.mutexes.available.clone();
+        let mut semaphore_work = self.semaphores.available.clone();
+        let mut finish = vec![false; threads];
+        loop {
+            let runnable = (0..threads).find(|&tid| {
+                !finish[tid]
+                    && (0..mutex_work.len()).all(|id| self.mutexes.need(tid, id) <= mutex_work[id])
+                    && (0..semaphore_work.len())
+                        .all(|id| self.semaphores.need(tid, id) <= semaphore_work[id])
+            });
+            match runnable {
+                Some(tid) => {
+                    // it can run to the end and give back all it holds
+                    finish[tid] = true;
+                    for (id, work) in mutex_work.iter_mut().enumerate() {
+                        *work += self.mutexes.allocation(tid, id);
+                    }
+                    for (id, work) in semaphore_work.iter_mut().enumerate() {
+                        *work += self.semaphores.allocation(tid, id);
+                    }
+                }
+                None => return finish.iter().all(|&finished| finished),
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Futexes, wait queues the kernel keeps for words in user memory
+//!
+//! User space takes a lock with atomic instructions on a word and only asks
+//! the kernel to sleep on the word, or to wake its sleepers, on contention.
+//! The queues are keyed by the physical address of the word, so threads of a
+//! process and processes sharing the page all find the same queue.
+
+use crate::sync::SpinLock;
+use crate::task::{add_task, current_task, fatal_signal_pending, TaskControlBlock};
+use crate::timer::block_current_until;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    static ref FUTEX_QUEUES: SpinLock<BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// Why [`futex_wait`] returns without being woken up
+pub enum FutexError {
+    /// The word no longer holds the value the caller saw
+    ValueChanged,
+    /// The deadline has passed
+    TimedOut,
+    /// A signal is going to kill the process
+    Interrupted,
+}
+
+fn futex_key(word: &AtomicU32) -> usize {
+    word as *const AtomicU32 as usize
+}
+
+/// Sleep on `word` if it still holds `val`, until [`futex_wake`], the
+/// deadline `expire_ms` or a signal going to kill the process. The page of
+/// `word` has to stay resident meanwhile.
+pub fn futex_wait(word: &AtomicU32, val: u32, expire_ms: Option<usize>) -> Result<(), FutexError> {
+    let key = futex_key(word);
+    let mut queues = FUTEX_QUEUES.lock();
+    // wakers change the word before taking the lock, so no wakeup is lost
+    // between this check and going to sleep
+    if word.load(Ordering::SeqCst) != val {
+        return Err(FutexError::ValueChanged);
+    }
+    queues
+        .entry(key)
+        .or_default()
+        .push_back(current_task().unwrap());
+    drop(queues);
+    if !block_current_until(expire_ms, move |task| remove_waiter(key, task)) {
+        if fatal_signal_pending() {
+            return Err(FutexError::Interrupted);
+        }
+        return Err(FutexError::TimedOut);
+    }
+    Ok(())
+}
+
+/// Take `task` off the queue of `key`, false if it is not there any more
+fn remove_waiter(key: usize, task: &Arc<TaskControlBlock>) -> bool {
+    let mut queues = FUTEX_QUEUES.lock();
+    let queue = match queues.get_mut(&key) {
+        Some(queue) => queue,
+        None => return false,
+    };
+    match queue.iter().position(|waiter| Arc::ptr_eq(waiter, task)) {
+        Some(index) => {
+            queue.remove(index);
+            if queue.is_empty() {
+                queues.remove(&key);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Wake at most `count` threads sleeping on `word`, returns how many were woken
+pub fn futex_wake(word: &AtomicU32, count: usize) -> usize {
+    let key = futex_key(word);
+    let mut queues = FUTEX_QUEUES.lock();
+    let queue = match queues.get_mut(&key) {
+        Some(queue) => queue,
+        None => return 0,
+    };
+    let mut woken = 0;
+    while woken < count {
+        match queue.pop_front() {
+            Some(task) => add_task(task),
+            None => break,
+        }
+        woken += 1;
+    }
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    woken
+}
@@ -1,11 +1,42 @@
 //! Synchronization and interior mutability primitives
+//!
+//! The kernel data shared between harts is guarded by [`SpinLock`]s, which
+//! are never held across a task switch, and the file system by the only
+//! [`SleepLock`], `FS_LOCK`, which is taken with no spin lock held. The spin
+//! locks inside easy-fs are only taken under `FS_LOCK`, they are the only
+//! ones held while waiting for the disk. Locks taken together are always
+//! nested in these orders:
+//!
+//! - `PI_LOCK`, then the inner lock of a blocking mutex, then the inner lock
+//!   of a thread
+//! - `TIMERS`, then the cancel of a wait, then the wait queue it takes the
+//!   thread off: the inner lock of a mutex, semaphore, condvar, rwlock or
+//!   barrier, `FUTEX_QUEUES` or the TTY
+//! - a wait queue, then `TASK_MANAGER` to wake a thread up, then the inner
+//!   lock of a thread
+//! - the inner lock of a process, then the inner locks of its threads
+//! - the inner lock of a process, then `FRAME_ALLOCATOR`. Swapping out for a
+//!   frame only tries the locks of processes, one held already is skipped.
+//! - `STDOUT` comes last, anything may print
 
+mod barrier;
 mod condvar;
+mod deadlock;
+mod futex;
 mod mutex;
+mod rwlock;
 mod semaphore;
+mod sleep;
+mod spin;
 mod up;
 
+pub use barrier::Barrier;
 pub use condvar::Condvar;
-pub use mutex::{Mutex, MutexBlocking, MutexSpin};
-pub use semaphore::Semaphore;
+pub use deadlock::{Resource, ResourceTracker};
+pub use futex::{futex_wait, futex_wake, FutexError};
+pub use mutex::{CheckedMutex, Mutex, MutexBlocking, MutexError, MutexFlags, MutexKind, MutexSpin};
+pub use rwlock::RwLock;
+pub use semaphore::{Semaphore, SemaphoreError};
+pub use sleep::{SleepLock, SleepLockGuard};
+pub use spin::{SpinLock, SpinLockGuard};
 pub use up::UPSafeCell;
@@ -1,112 +1,503 @@
-use super::UPSafeCell;
-use crate::syscall::process;
+use super::SpinLock;
+use crate::task::TaskControlBlock;
 use crate::task::{add_task, current_task};
-use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
-use crate::task::{current_process, TaskControlBlock};
-use alloc::{collections::VecDeque, sync::Arc};
+use crate::task::{fatal_signal_pending, suspend_current_and_run_next};
+use crate::timer::{block_current_until, get_time_ms};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use bitflags::*;
+use core::cmp::Reverse;
+use lazy_static::*;
+
+bitflags! {
+    /// The kind of mutex made by `sys_mutex_create`
+    pub struct MutexFlags: u32 {
+        /// Waiters sleep instead of yielding
+        const BLOCKING = 1 << 0;
+        /// A relock by the owner fails, only the owner may unlock
+        const ERRORCHECK = 1 << 1;
+        /// The owner may lock it again, only the owner may unlock
+        const RECURSIVE = 1 << 2;
+        /// Unlocked for the next locker when the owner exits holding it
+        const ROBUST = 1 << 3;
+    }
+}
+
+/// Why a mutex operation fails, all but `OwnerDead` leave the mutex as it was
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MutexError {
+    /// The mutex is held by another thread
+    Busy,
+    /// The deadline has passed before the mutex became free
+    TimedOut,
+    /// The current thread does not hold the mutex
+    NotOwner,
+    /// The current thread holds the mutex already
+    Deadlock,
+    /// The mutex has been taken, but its last owner exited holding it
+    OwnerDead,
+    /// A signal is going to kill the process before the mutex became free
+    Interrupted,
+}
 
 pub trait Mutex: Sync + Send {
-    fn lock(&self);
-    fn unlock(&self);
+    fn lock(self: Arc<Self>) -> Result<(), MutexError>;
+    /// Take the lock only if it is free
+    fn try_lock(&self) -> Result<(), MutexError>;
+    /// Like `lock`, but give up once `expire_ms` has passed
+    fn lock_until(self: Arc<Self>, expire_ms: usize) -> Result<(), MutexError>;
+    fn unlock(&self) -> Result<(), MutexError>;
+    /// Whether some thread holds the mutex, as far as the mutex knows
+    fn is_locked(&self) -> bool {
+        true
+    }
+    /// How many times the current thread holds the mutex, None if the mutex
+    /// does not keep track of its owner
+    fn depth(&self) -> Option<usize> {
+        None
+    }
+    /// `task` is exiting, a robust mutex it holds is given to the next locker.
+    /// Returns whether the mutex has been unlocked.
+    fn owner_exited(&self, _task: &Arc<TaskControlBlock>) -> bool {
+        false
+    }
 }
 
 pub struct MutexSpin {
-    locked: UPSafeCell<bool>,
+    locked: SpinLock<bool>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: unsafe { UPSafeCell::new(false) },
+            locked: SpinLock::new(false),
         }
     }
 }
 
 impl Mutex for MutexSpin {
-    fn lock(&self) {
+    fn lock(self: Arc<Self>) -> Result<(), MutexError> {
         loop {
-            let mut locked = self.locked.exclusive_access();
+            let mut locked = self.locked.lock();
             if *locked {
                 drop(locked);
+                if fatal_signal_pending() {
+                    return Err(MutexError::Interrupted);
+                }
                 suspend_current_and_run_next();
                 continue;
             } else {
                 *locked = true;
-                return;
+                return Ok(());
             }
         }
     }
 
-    fn unlock(&self) {
-        let mut locked = self.locked.exclusive_access();
-        *locked = false;
+    fn try_lock(&self) -> Result<(), MutexError> {
+        let mut locked = self.locked.lock();
+        if core::mem::replace(&mut *locked, true) {
+            return Err(MutexError::Busy);
+        }
+        Ok(())
+    }
+
+    fn lock_until(self: Arc<Self>, expire_ms: usize) -> Result<(), MutexError> {
+        loop {
+            if self.try_lock().is_ok() {
+                return Ok(());
+            }
+            if get_time_ms() >= expire_ms {
+                return Err(MutexError::TimedOut);
+            }
+            if fatal_signal_pending() {
+                return Err(MutexError::Interrupted);
+            }
+            suspend_current_and_run_next();
+        }
+    }
+
+    fn unlock(&self) -> Result<(), MutexError> {
+        let mut locked = self.locked.lock();
+        if !core::mem::replace(&mut *locked, false) {
+            return Err(MutexError::NotOwner);
+        }
+        Ok(())
+    }
+
+    fn is_locked(&self) -> bool {
+        *self.locked.lock()
     }
 }
 
+lazy_static! {
+    /// Taken around every change of owners and waiters of blocking mutexes,
+    /// as priority inheritance follows a chain of owners across several of them
+    static ref PI_LOCK: SpinLock<()> = SpinLock::new(());
+}
+
+/// A mutex whose waiters sleep, it is handed over to the waiter of the highest
+/// priority and its owner runs with the priority of that waiter meanwhile
 pub struct MutexBlocking {
-    inner: UPSafeCell<MutexBlockingInner>,
+    inner: SpinLock<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
-    locked: bool,
+    owner: Option<Arc<TaskControlBlock>>,
     wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
 impl MutexBlocking {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(MutexBlockingInner {
-                    locked: false,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(MutexBlockingInner {
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
-}
 
-impl Mutex for MutexBlocking {
-    fn lock(&self) {
-        let mut mutex_inner = self.inner.exclusive_access();
-        if mutex_inner.locked {
-            mutex_inner.wait_queue.push_back(current_task().unwrap());
-            drop(mutex_inner);
-            block_current_and_run_next();
-        } else {
-            if let Some(task) = current_task() {
-                let task_inner = task.inner_exclusive_access();
-                if let Some(res) = task_inner.res.as_ref() {
-                    let tid = res.tid;
-                    let process = current_process();
-                    let mut process_inner = process.inner_exclusive_access();
-                    let mutex_id = process_inner.mutex_id;
-                    process_inner.mutex_need_vector[tid][mutex_id] -= 1;
-                    process_inner.mutex_available_vector[mutex_id] -= 1;
-                    process_inner.mutex_allocation_vector[tid][mutex_id] += 1;
+    /// Take the lock if it is free, else queue up `task`, lend its priority
+    /// to the owner and return false. `PI_LOCK` must be held.
+    fn acquire_or_wait(self: &Arc<Self>, task: &Arc<TaskControlBlock>) -> bool {
+        let mut mutex_inner = self.inner.lock();
+        let owner = match &mutex_inner.owner {
+            Some(owner) => Arc::clone(owner),
+            None => {
+                mutex_inner.owner = Some(Arc::clone(task));
+                return true;
+            }
+        };
+        mutex_inner.wait_queue.push_back(Arc::clone(task));
+        drop(mutex_inner);
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.blocked_on = Some(Arc::clone(self));
+        let priority = task_inner.priority;
+        drop(task_inner);
+        lend_priority(owner, Arc::as_ptr(self) as usize, priority);
+        false
+    }
+
+    /// Wait for the lock to be handed over by `unlock` until `expire_ms`, or
+    /// until a signal is going to kill the process, and say which of them
+    /// ended the wait if the lock has not been taken.
+    fn wait_for_handover(
+        self: Arc<Self>,
+        task: &Arc<TaskControlBlock>,
+        expire_ms: Option<usize>,
+    ) -> Result<(), MutexError> {
+        // the lock is handed over to a waiter taken off the queue by unlock,
+        // so a waiter still queued on timeout has not got it
+        let acquired = block_current_until(expire_ms, move |task| {
+            let mut mutex_inner = self.inner.lock();
+            let queue = &mut mutex_inner.wait_queue;
+            match queue.iter().position(|waiter| Arc::ptr_eq(waiter, task)) {
+                Some(index) => {
+                    queue.remove(index);
+                    true
                 }
+                None => false,
             }
-            mutex_inner.locked = true;
-        }
-    }
-
-    fn unlock(&self) {
-        let mut mutex_inner = self.inner.exclusive_access();
-        assert!(mutex_inner.locked);
-        if let Some(task) = current_task() {
-            let task_inner = task.inner_exclusive_access();
-            if let Some(res) = task_inner.res.as_ref() {
-                let tid = res.tid;
-                let process = current_process();
-                let mut process_inner = process.inner_exclusive_access();
-                let mutex_id = process_inner.mutex_id;
-                process_inner.mutex_available_vector[mutex_id] += 1;
-                process_inner.mutex_allocation_vector[tid][mutex_id] -= 1;
+        });
+        if !acquired {
+            // the priority lent to the owner goes with the waiter
+            let pi_lock = PI_LOCK.lock();
+            let mutex = task.inner_exclusive_access().blocked_on.take().unwrap();
+            restore_priority(mutex);
+            drop(pi_lock);
+            if fatal_signal_pending() {
+                return Err(MutexError::Interrupted);
             }
+            return Err(MutexError::TimedOut);
+        }
+        Ok(())
+    }
+}
+
+/// Lend `priority` to `owner` for the mutex at `key`, and on along the chain
+/// of mutexes each owner waits for. `PI_LOCK` must be held.
+fn lend_priority(mut owner: Arc<TaskControlBlock>, mut key: usize, priority: usize) {
+    loop {
+        let mut owner_inner = owner.inner_exclusive_access();
+        let lent = owner_inner.donations.entry(key).or_insert(0);
+        if *lent >= priority {
+            return;
+        }
+        *lent = priority;
+        if owner_inner.priority >= priority {
+            return;
+        }
+        owner_inner.update_priority();
+        let mutex = match owner_inner.blocked_on.clone() {
+            Some(mutex) => mutex,
+            None => return,
+        };
+        drop(owner_inner);
+        owner = match &mutex.inner.lock().owner {
+            Some(owner) => Arc::clone(owner),
+            None => return,
+        };
+        key = Arc::as_ptr(&mutex) as usize;
+    }
+}
+
+/// A waiter has left `mutex`, lend its owner only the priorities of the
+/// waiters left and on along the chain of mutexes each owner waits for.
+/// `PI_LOCK` must be held.
+fn restore_priority(mut mutex: Arc<MutexBlocking>) {
+    loop {
+        let mutex_inner = mutex.inner.lock();
+        let owner = match &mutex_inner.owner {
+            Some(owner) => Arc::clone(owner),
+            None => return,
+        };
+        let lent = mutex_inner
+            .wait_queue
+            .iter()
+            .map(|task| task.inner_exclusive_access().priority)
+            .max();
+        drop(mutex_inner);
+        let key = Arc::as_ptr(&mutex) as usize;
+        let mut owner_inner = owner.inner_exclusive_access();
+        let priority = owner_inner.priority;
+        match lent {
+            Some(lent) => owner_inner.donations.insert(key, lent),
+            None => owner_inner.donations.remove(&key),
+        };
+        owner_inner.update_priority();
+        if owner_inner.priority == priority {
+            return;
+        }
+        mutex = match owner_inner.blocked_on.clone() {
+            Some(mutex) => mutex,
+            None => return,
+        };
+    }
+}
+
+impl Mutex for MutexBlocking {
+    fn lock(self: Arc<Self>) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        let pi_lock = PI_LOCK.lock();
+        let acquired = self.acquire_or_wait(&task);
+        drop(pi_lock);
+        if !acquired {
+            self.wait_for_handover(&task, None)?;
+        }
+        Ok(())
+    }
+
+    fn try_lock(&self) -> Result<(), MutexError> {
+        let mut mutex_inner = self.inner.lock();
+        if mutex_inner.owner.is_some() {
+            return Err(MutexError::Busy);
+        }
+        mutex_inner.owner = Some(current_task().unwrap());
+        Ok(())
+    }
+
+    fn lock_until(self: Arc<Self>, expire_ms: usize) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        let pi_lock = PI_LOCK.lock();
+        let acquired = self.acquire_or_wait(&task);
+        drop(pi_lock);
+        if !acquired {
+            self.wait_for_handover(&task, Some(expire_ms))?;
         }
-        if let Some(waking_task) = mutex_inner.wait_queue.pop_front() {
+        Ok(())
+    }
+
+    fn unlock(&self) -> Result<(), MutexError> {
+        let key = self as *const Self as usize;
+        let _pi_lock = PI_LOCK.lock();
+        let mut mutex_inner = self.inner.lock();
+        let owner = match mutex_inner.owner.take() {
+            Some(owner) => owner,
+            None => return Err(MutexError::NotOwner),
+        };
+        let mut owner_inner = owner.inner_exclusive_access();
+        owner_inner.donations.remove(&key);
+        owner_inner.update_priority();
+        drop(owner_inner);
+        // the first of the waiters of the highest priority
+        let next = mutex_inner
+            .wait_queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, task)| (task.inner_exclusive_access().priority, Reverse(*i)))
+            .map(|(i, _)| i);
+        if let Some(i) = next {
+            let waking_task = mutex_inner.wait_queue.remove(i).unwrap();
+            let lent = mutex_inner
+                .wait_queue
+                .iter()
+                .map(|task| task.inner_exclusive_access().priority)
+                .max();
+            let mut task_inner = waking_task.inner_exclusive_access();
+            task_inner.blocked_on = None;
+            if let Some(priority) = lent {
+                task_inner.donations.insert(key, priority);
+                task_inner.update_priority();
+            }
+            drop(task_inner);
+            mutex_inner.owner = Some(Arc::clone(&waking_task));
             add_task(waking_task);
+        }
+        Ok(())
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.lock().owner.is_some()
+    }
+}
+
+/// What a [`CheckedMutex`] does when its owner locks it again
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MutexKind {
+    /// Wait for itself forever like a plain mutex
+    Normal,
+    /// Fail with `MutexError::Deadlock`
+    ErrorCheck,
+    /// Count the locks, the mutex is released by as many unlocks
+    Recursive,
+}
+
+/// A mutex knowing the thread holding it, on top of a [`MutexSpin`] or a
+/// [`MutexBlocking`]. Only the owner may unlock it, and a robust one is
+/// unlocked for the next locker when its owner exits.
+pub struct CheckedMutex {
+    raw: Arc<dyn Mutex>,
+    kind: MutexKind,
+    robust: bool,
+    state: SpinLock<CheckedMutexState>,
+}
+
+pub struct CheckedMutexState {
+    /// The owner, let go of once it exits so that it may go away
+    owner: Option<Arc<TaskControlBlock>>,
+    /// Number of times the owner has locked the mutex
+    count: usize,
+    /// Whether the last owner exited holding the mutex
+    owner_died: bool,
+}
+
+impl CheckedMutexState {
+    fn is_owner(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.owner
+            .as_ref()
+            .map_or(false, |owner| Arc::ptr_eq(owner, task))
+    }
+}
+
+impl CheckedMutex {
+    pub fn new(raw: Arc<dyn Mutex>, kind: MutexKind, robust: bool) -> Self {
+        Self {
+            raw,
+            kind,
+            robust,
+            state: SpinLock::new(CheckedMutexState {
+                owner: None,
+                count: 0,
+                owner_died: false,
+            }),
+        }
+    }
+
+    /// Lock again if the current thread is the owner, None if it is not
+    fn relock(&self, task: &Arc<TaskControlBlock>) -> Option<Result<(), MutexError>> {
+        let mut state = self.state.lock();
+        if !state.is_owner(task) {
+            return None;
+        }
+        match self.kind {
+            MutexKind::Normal => None,
+            MutexKind::ErrorCheck => Some(Err(MutexError::Deadlock)),
+            MutexKind::Recursive => {
+                state.count += 1;
+                Some(Ok(()))
+            }
+        }
+    }
+
+    /// Record the current thread as the owner once it has taken the raw mutex
+    fn acquired(&self, task: &Arc<TaskControlBlock>) -> Result<(), MutexError> {
+        let mut state = self.state.lock();
+        state.owner = Some(Arc::clone(task));
+        state.count = 1;
+        if core::mem::take(&mut state.owner_died) {
+            Err(MutexError::OwnerDead)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Mutex for CheckedMutex {
+    fn lock(self: Arc<Self>) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        if let Some(result) = self.relock(&task) {
+            return result;
+        }
+        Arc::clone(&self.raw).lock()?;
+        self.acquired(&task)
+    }
+
+    fn try_lock(&self) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        if let Some(result) = self.relock(&task) {
+            return result;
+        }
+        self.raw.try_lock()?;
+        self.acquired(&task)
+    }
+
+    fn lock_until(self: Arc<Self>, expire_ms: usize) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        if let Some(result) = self.relock(&task) {
+            return result;
+        }
+        Arc::clone(&self.raw).lock_until(expire_ms)?;
+        self.acquired(&task)
+    }
+
+    fn unlock(&self) -> Result<(), MutexError> {
+        let task = current_task().unwrap();
+        let mut state = self.state.lock();
+        if !state.is_owner(&task) {
+            return Err(MutexError::NotOwner);
+        }
+        state.count -= 1;
+        if state.count > 0 {
+            return Ok(());
+        }
+        state.owner = None;
+        drop(state);
+        self.raw.unlock()
+    }
+
+    fn depth(&self) -> Option<usize> {
+        let task = current_task().unwrap();
+        let state = self.state.lock();
+        Some(if state.is_owner(&task) {
+            state.count
         } else {
-            mutex_inner.locked = false;
+            0
+        })
+    }
+
+    fn owner_exited(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut state = self.state.lock();
+        if !state.is_owner(task) {
+            return false;
+        }
+        state.owner = None;
+        state.count = 0;
+        // any other mutex stays locked for good
+        if !self.robust {
+            return false;
         }
+        state.owner_died = true;
+        drop(state);
+        self.raw.unlock().unwrap();
+        true
     }
 }
@@ -0,0 +1,142 @@
+use crate::sync::SpinLock;
+use crate::task::{add_task, current_task, TaskControlBlock};
+use crate::timer::block_current_until;
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// A blocking reader-writer lock preferring writers: once a writer waits,
+/// new readers queue up behind it even though the lock is only read.
+pub struct RwLock {
+    inner: SpinLock<RwLockInner>,
+}
+
+struct RwLockInner {
+    /// Number of threads holding the lock for reading
+    readers: usize,
+    /// Tid of the thread holding the lock for writing
+    writer: Option<usize>,
+    read_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// The waiting writers with their tids
+    write_queue: VecDeque<(usize, Arc<TaskControlBlock>)>,
+}
+
+impl RwLock {
+    pub fn new() -> Self {
+        Self {
+            inner: SpinLock::new(RwLockInner {
+                readers: 0,
+                writer: None,
+                read_queue: VecDeque::new(),
+                write_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Take the lock for reading, returns false if a signal going to kill
+    /// the process comes first
+    pub fn read(self: Arc<Self>) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.writer.is_some() || !inner.write_queue.is_empty() {
+            // woken up as a reader already counted by the unlocking writer
+            inner.read_queue.push_back(current_task().unwrap());
+            drop(inner);
+            return block_current_until(None, move |task| {
+                remove_waiter(&mut self.inner.lock().read_queue, task)
+            });
+        }
+        inner.readers += 1;
+        true
+    }
+
+    /// Take the lock for writing, returns false if a signal going to kill
+    /// the process comes first
+    pub fn write(self: Arc<Self>) -> bool {
+        let tid = current_tid();
+        let mut inner = self.inner.lock();
+        if inner.writer.is_some() || inner.readers > 0 {
+            // woken up as the writer by the unlocking thread
+            inner.write_queue.push_back((tid, current_task().unwrap()));
+            drop(inner);
+            return block_current_until(None, move |task| {
+                let mut inner = self.inner.lock();
+                let queue = &mut inner.write_queue;
+                match queue
+                    .iter()
+                    .position(|(_, waiter)| Arc::ptr_eq(waiter, task))
+                {
+                    Some(index) => {
+                        queue.remove(index);
+                    }
+                    None => return false,
+                }
+                // the readers queued up behind the last waiting writer go on
+                if inner.writer.is_none() && inner.write_queue.is_empty() {
+                    inner.wake_readers();
+                }
+                true
+            });
+        }
+        inner.writer = Some(tid);
+        true
+    }
+
+    /// Release the lock held for either reading or writing, returns false if
+    /// the current thread does not hold it
+    pub fn unlock(&self) -> bool {
+        let tid = current_tid();
+        let mut inner = self.inner.lock();
+        match inner.writer {
+            Some(writer) if writer == tid => inner.writer = None,
+            // nobody reads while a writer holds the lock
+            Some(_) => return false,
+            None if inner.readers == 0 => return false,
+            None => {
+                inner.readers -= 1;
+                if inner.readers > 0 {
+                    return true;
+                }
+            }
+        }
+        if let Some((tid, task)) = inner.write_queue.pop_front() {
+            inner.writer = Some(tid);
+            add_task(task);
+        } else {
+            inner.wake_readers();
+        }
+        true
+    }
+}
+
+impl RwLockInner {
+    /// Let all the queued readers in
+    fn wake_readers(&mut self) {
+        self.readers += self.read_queue.len();
+        for task in self.read_queue.drain(..) {
+            add_task(task);
+        }
+    }
+}
+
+/// Tid of the current thread
+fn current_tid() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid
+}
+
+/// Take `task` off `queue`, false if it is not there any more
+fn remove_waiter(
+    queue: &mut VecDeque<Arc<TaskControlBlock>>,
+    task: &Arc<TaskControlBlock>,
+) -> bool {
+    match queue.iter().position(|waiter| Arc::ptr_eq(waiter, task)) {
+        Some(index) => {
+            queue.remove(index);
+            true
+        }
+        None => false,
+    }
+}
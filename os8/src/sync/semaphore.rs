@@ -1,9 +1,10 @@
-use crate::sync::UPSafeCell;
-use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock, current_process};
+use crate::sync::SpinLock;
+use crate::task::{add_task, current_task, fatal_signal_pending, TaskControlBlock};
+use crate::timer::block_current_until;
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Semaphore {
-    pub inner: UPSafeCell<SemaphoreInner>,
+    pub inner: SpinLock<SemaphoreInner>,
 }
 
 pub struct SemaphoreInner {
@@ -11,62 +12,83 @@ pub struct SemaphoreInner {
     pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
+/// Why [`Semaphore::down_until`] returns without taking a unit
+pub enum SemaphoreError {
+    /// The deadline has passed
+    TimedOut,
+    /// A signal is going to kill the process
+    Interrupted,
+}
+
 impl Semaphore {
     pub fn new(res_count: usize) -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(SemaphoreInner {
-                    count: res_count as isize,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(SemaphoreInner {
+                count: res_count as isize,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
     pub fn up(&self) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.count += 1;
 
         if inner.count <= 0 {
             if let Some(task) = inner.wait_queue.pop_front() {
                 add_task(task);
             }
-        } else {
-            if let Some(task) = current_task() {
-                let task_inner = task.inner_exclusive_access();
-                if let Some(res) = task_inner.res.as_ref() {
-                    let tid = res.tid;
-                    let process = current_process();
-                    let mut process_inner = process.inner_exclusive_access();
-                    let sem_id = process_inner.semaphore_id;
-                    process_inner.semaphore_available_vector[sem_id] += 1;
-                    process_inner.semaphore_allocation_vector[tid][sem_id] -= 1;
-                }
-            }
         }
     }
 
-    pub fn down(&self) {
-        let mut inner = self.inner.exclusive_access();
-        inner.count -= 1;
+    /// Take a unit, waiting for one if none is left. Returns false if a
+    /// signal going to kill the process comes first.
+    pub fn down(self: Arc<Self>) -> bool {
+        self.down_until(None).is_ok()
+    }
 
-        if inner.count < 0 {
-            inner.wait_queue.push_back(current_task().unwrap());
-            drop(inner);
-            block_current_and_run_next();
+    /// Take a unit only if one is left, returns whether it has been taken
+    pub fn try_down(&self) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.count > 0 {
+            inner.count -= 1;
+            true
         } else {
-            if let Some(task) = current_task() {
-                let task_inner = task.inner_exclusive_access();
-                if let Some(res) = task_inner.res.as_ref() {
-                    let tid = res.tid;
-                    let process = current_process();
-                    let mut process_inner = process.inner_exclusive_access();
-                    let sem_id = process_inner.semaphore_id;
-                    process_inner.semaphore_need_vector[tid][sem_id] -= 1;
-                    process_inner.semaphore_available_vector[sem_id] -= 1;
-                    process_inner.semaphore_allocation_vector[tid][sem_id] += 1;
+            false
+        }
+    }
+
+    /// Like `down`, but also give up once `expire_ms` has passed
+    pub fn down_until(self: Arc<Self>, expire_ms: Option<usize>) -> Result<(), SemaphoreError> {
+        let mut inner = self.inner.lock();
+        inner.count -= 1;
+        if inner.count >= 0 {
+            return Ok(());
+        }
+        inner.wait_queue.push_back(current_task().unwrap());
+        drop(inner);
+        let taken = block_current_until(expire_ms, move |task| {
+            let mut inner = self.inner.lock();
+            let position = inner
+                .wait_queue
+                .iter()
+                .position(|waiter| Arc::ptr_eq(waiter, task));
+            match position {
+                Some(index) => {
+                    // the unit counted as waited for is not going to be taken
+                    inner.wait_queue.remove(index);
+                    inner.count += 1;
+                    true
                 }
+                None => false,
+            }
+        });
+        if !taken {
+            if fatal_signal_pending() {
+                return Err(SemaphoreError::Interrupted);
             }
+            return Err(SemaphoreError::TimedOut);
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
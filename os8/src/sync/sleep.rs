@@ -0,0 +1,100 @@
+//! Lock for kernel data held across sleeps, such as waiting for the disk
+
+use super::SpinLock;
+use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+
+/// A lock whose waiters sleep instead of spinning, so its holder may sleep too.
+///
+/// A [`SpinLock`] must not be held by a sleeping task: the hart of a task
+/// spinning for it never gets back to the sleeper. Without a current task
+/// (during boot) the waiter spins.
+pub struct SleepLock<T> {
+    inner: SpinLock<SleepLockInner>,
+    data: UnsafeCell<T>,
+}
+
+struct SleepLockInner {
+    locked: bool,
+    wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+unsafe impl<T: Send> Send for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: SpinLock::new(SleepLockInner {
+                locked: false,
+                wait_queue: VecDeque::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Sleep until the lock is ours
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        loop {
+            let mut inner = self.inner.lock();
+            if !inner.locked {
+                inner.locked = true;
+                break;
+            }
+            if let Some(task) = current_task() {
+                inner.wait_queue.push_back(task);
+                drop(inner);
+                block_current_and_run_next();
+                // the lock has been handed over to us
+                break;
+            }
+            drop(inner);
+            spin_loop();
+        }
+        SleepLockGuard { lock: self }
+    }
+
+    /// Take the lock if nobody holds it
+    pub fn try_lock(&self) -> Option<SleepLockGuard<'_, T>> {
+        let mut inner = self.inner.lock();
+        if inner.locked {
+            None
+        } else {
+            inner.locked = true;
+            Some(SleepLockGuard { lock: self })
+        }
+    }
+}
+
+/// Exclusive access to the data of a [`SleepLock`], released when dropped
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut inner = self.lock.inner.lock();
+        // hand the lock over to the first waiter
+        if let Some(task) = inner.wait_queue.pop_front() {
+            add_task(task);
+        } else {
+            inner.locked = false;
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Ticket spinlock for data shared between harts
+
+use crate::task::hart_id;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `owner` of a lock nobody holds
+const NO_OWNER: usize = usize::MAX;
+
+/// A spinlock handing out tickets, harts get the lock in the order they ask for it.
+///
+/// Traps never come from the kernel itself: the interrupts are handled on
+/// traps from user space and polled by the idle loop, and neither holds a
+/// lock then. A hart holding a lock does not switch tasks either, so it is
+/// never waiting for a lock it holds. Taking a lock the current hart holds
+/// already is a bug and panics, drop the guard before calling anything that
+/// may take it again.
+pub struct SpinLock<T: ?Sized> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    /// Id of the hart holding the lock
+    owner: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            owner: AtomicUsize::new(NO_OWNER),
+            data: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Spin until it is our turn to access the inner data
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let hart_id = hart_id();
+        // only this hart writes its own id, so it is seen here if it holds the lock
+        if self.owner.load(Ordering::Relaxed) == hart_id {
+            panic!("SpinLock taken again by hart {} holding it", hart_id);
+        }
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+        self.owner.store(hart_id, Ordering::Relaxed);
+        SpinLockGuard { lock: self }
+    }
+
+    /// Take the lock only if nobody holds or waits for it
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let ticket = self.now_serving.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(ticket, ticket + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        self.owner.store(hart_id(), Ordering::Relaxed);
+        Some(SpinLockGuard { lock: self })
+    }
+}
+
+/// Exclusive access to the data of a [`SpinLock`], released when dropped
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
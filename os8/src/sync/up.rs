@@ -5,7 +5,8 @@ use core::cell::{RefCell, RefMut};
 /// Wrap a static data structure inside it so that we are
 /// able to access it without any `unsafe`.
 ///
-/// We should only use it in uniprocessor.
+/// We should only use it in uniprocessor, or for data that only one hart
+/// ever touches like the `Processor` of each hart.
 ///
 /// In order to get mutable reference of inner data, call
 /// `exclusive_access`.
@@ -0,0 +1,30 @@
+//! Error numbers returned negated by syscalls, as in Linux
+
+/// Operation not permitted
+pub const EPERM: isize = 1;
+/// No such process
+pub const ESRCH: isize = 3;
+/// Interrupted system call
+pub const EINTR: isize = 4;
+/// Try again
+pub const EAGAIN: isize = 11;
+/// Out of memory
+pub const ENOMEM: isize = 12;
+/// Bad address
+pub const EFAULT: isize = 14;
+/// Device or resource busy
+pub const EBUSY: isize = 16;
+/// Invalid argument
+pub const EINVAL: isize = 22;
+/// Not a terminal
+pub const ENOTTY: isize = 25;
+/// Illegal seek
+pub const ESPIPE: isize = 29;
+/// Resource deadlock would occur
+pub const EDEADLK: isize = 35;
+/// Function not implemented
+pub const ENOSYS: isize = 38;
+/// Connection timed out
+pub const ETIMEDOUT: isize = 110;
+/// Owner died
+pub const EOWNERDEAD: isize = 130;
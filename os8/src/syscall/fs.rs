@@ -1,10 +1,21 @@
 //! File and filesystem-related syscalls
 
+use super::errno::{EINVAL, ENOTTY, ESPIPE};
+use crate::drivers::{TtyMode, TTY};
+use crate::fs::link_file;
+use crate::fs::make_dir;
 use crate::fs::make_pipe;
 use crate::fs::open_file;
+use crate::fs::remove_dir;
+use crate::fs::resolve_dir;
+use crate::fs::sync_all;
+use crate::fs::unlink_file;
 use crate::fs::OpenFlags;
+use crate::fs::SeekFrom;
 use crate::fs::Stat;
+use crate::mm::copy_to_user;
 use crate::mm::translated_byte_buffer;
+use crate::mm::translated_ref;
 use crate::mm::translated_refmut;
 use crate::mm::translated_str;
 use crate::mm::UserBuffer;
@@ -46,11 +57,100 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     }
 }
 
+/// Offset `offset` bytes from the start of the file
+const SEEK_SET: usize = 0;
+/// Offset `offset` bytes from the current offset
+const SEEK_CUR: usize = 1;
+/// Offset `offset` bytes from the end of the file
+const SEEK_END: usize = 2;
+
+/// Move the offset of `fd`, returns the new offset
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(inner);
+    if !file.seekable() {
+        return -ESPIPE;
+    }
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return -EINVAL,
+    };
+    match file.seek(pos) {
+        Some(offset) => offset as isize,
+        None => -EINVAL,
+    }
+}
+
+/// Read from `offset` of `fd`, the offset of the file stays where it is
+pub fn sys_pread(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(inner);
+    if !file.readable() {
+        return -1;
+    }
+    if !file.seekable() {
+        return -ESPIPE;
+    }
+    file.read_at(
+        offset,
+        UserBuffer::new(translated_byte_buffer(token, buf, len)),
+    ) as isize
+}
+
+/// Write at `offset` of `fd`, the offset of the file stays where it is
+pub fn sys_pwrite(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(inner);
+    if !file.writable() {
+        return -1;
+    }
+    if !file.seekable() {
+        return -ESPIPE;
+    }
+    file.write_at(
+        offset,
+        UserBuffer::new(translated_byte_buffer(token, buf, len)),
+    ) as isize
+}
+
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(inode) = open_file(
+        cwd.as_str(),
+        path.as_str(),
+        OpenFlags::from_bits(flags).unwrap(),
+    ) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
@@ -82,6 +182,8 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pipe_write);
+    // the user page may have to be mapped through the process
+    drop(inner);
     *translated_refmut(token, pipe) = read_fd;
     *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
     0
@@ -101,14 +203,164 @@ pub fn sys_dup(fd: usize) -> isize {
     new_fd as isize
 }
 
-pub fn sys_fstat(_fd: usize, _st: *mut Stat) -> isize {
-    -1
+/// Get the mode of the terminal into the `u32` at `arg`
+const TCGETS: usize = 0x5401;
+/// Set the mode of the terminal to the `u32` at `arg`
+const TCSETS: usize = 0x5402;
+/// Get the foreground pid into the `isize` at `arg`, -1 for none
+const TIOCGPGRP: usize = 0x540f;
+/// Set the foreground pid to the `isize` at `arg`, -1 for none
+const TIOCSPGRP: usize = 0x5410;
+
+/// Control the terminal behind `fd`, the console is the only one
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let is_tty = match &inner.fd_table[fd] {
+        Some(file) => file.is_tty(),
+        None => return -1,
+    };
+    drop(inner);
+    if !is_tty {
+        return -ENOTTY;
+    }
+    match cmd {
+        TCGETS => *translated_refmut(token, arg as *mut u32) = TTY.mode().bits(),
+        TCSETS => {
+            let mode = *translated_ref(token, arg as *const u32);
+            TTY.set_mode(TtyMode::from_bits_truncate(mode));
+        }
+        TIOCGPGRP => {
+            *translated_refmut(token, arg as *mut isize) = match TTY.foreground() {
+                Some(pid) => pid as isize,
+                None => -1,
+            }
+        }
+        TIOCSPGRP => {
+            let pid = *translated_ref(token, arg as *const isize);
+            TTY.set_foreground(if pid < 0 { None } else { Some(pid as usize) });
+        }
+        _ => return -EINVAL,
+    }
+    0
+}
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    // the stat may have to be read from the disk
+    drop(inner);
+    let stat = file.stat();
+    if let Some(stat) = stat {
+        let src = unsafe {
+            core::slice::from_raw_parts(
+                &stat as *const Stat as *const u8,
+                core::mem::size_of::<Stat>(),
+            )
+        };
+        copy_to_user(token, st as *mut u8, src);
+        0
+    } else {
+        -1
+    }
+}
+
+/// Write all dirty cached blocks back to the disk
+pub fn sys_sync() -> isize {
+    sync_all();
+    0
+}
+
+/// Write the data of `fd` back to the disk. The block cache is shared by all
+/// files, so for a file on the disk this is a sync of the whole filesystem.
+pub fn sys_fsync(fd: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    drop(inner);
+    file.sync();
+    0
+}
+
+pub fn sys_linkat(old_path: *const u8, new_path: *const u8) -> isize {
+    let token = current_user_token();
+    let old_path = translated_str(token, old_path);
+    let new_path = translated_str(token, new_path);
+    let cwd = current_process().inner_exclusive_access().cwd.clone();
+    if link_file(cwd.as_str(), old_path.as_str(), new_path.as_str()).is_some() {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Remove a directory instead of a file in `sys_unlinkat`
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn sys_unlinkat(path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let cwd = current_process().inner_exclusive_access().cwd.clone();
+    let result = if flags & AT_REMOVEDIR != 0 {
+        remove_dir(cwd.as_str(), path.as_str())
+    } else {
+        unlink_file(cwd.as_str(), path.as_str())
+    };
+    if result.is_some() {
+        0
+    } else {
+        -1
+    }
 }
 
-pub fn sys_linkat(_old_name: *const u8, _new_name: *const u8) -> isize {
-    -1
+pub fn sys_mkdirat(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let cwd = current_process().inner_exclusive_access().cwd.clone();
+    if make_dir(cwd.as_str(), path.as_str()).is_some() {
+        0
+    } else {
+        -1
+    }
 }
 
-pub fn sys_unlinkat(_name: *const u8) -> isize {
-    -1
+pub fn sys_chdir(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let process = current_process();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(dir) = resolve_dir(cwd.as_str(), path.as_str()) {
+        process.inner_exclusive_access().cwd = dir;
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copy the current working directory into `buf` with a trailing '\0',
+/// returns the length copied or -1 if `buf` is too small
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let mut cwd = current_process().inner_exclusive_access().cwd.clone();
+    cwd.push('\0');
+    if cwd.len() > len {
+        return -1;
+    }
+    copy_to_user(token, buf, cwd.as_bytes());
+    cwd.len() as isize
 }
@@ -0,0 +1,56 @@
+//! Message passing syscalls
+
+use super::errno::{EAGAIN, ESRCH};
+use crate::config::MAIL_MAX_LEN;
+use crate::mm::{copy_to_user, translated_byte_buffer};
+use crate::task::{current_process, current_user_token, pid2process};
+use alloc::vec::Vec;
+
+/// Take the oldest message out of the mailbox of the current process and copy
+/// at most `len` bytes of it into `buf`, the rest of it is dropped. Returns
+/// the length copied, or -EAGAIN if the mailbox is empty. With `len` 0 it only
+/// tells whether there is a message and leaves the mailbox untouched.
+pub fn sys_mail_read(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.mailbox.is_empty() {
+        return -EAGAIN;
+    }
+    if len == 0 {
+        return 0;
+    }
+    let message = inner.mailbox.pop().unwrap();
+    // ---- release current PCB before touching user memory
+    drop(inner);
+    let len = len.min(message.len());
+    copy_to_user(token, buf, &message[..len]);
+    len as isize
+}
+
+/// Send the first `len` bytes of `buf`, at most `MAIL_MAX_LEN`, to the
+/// mailbox of process `pid`. Returns the length sent, -ESRCH if there is no
+/// such process or -EAGAIN if its mailbox is full. With `len` 0 it only
+/// tells whether a message could be sent.
+pub fn sys_mail_write(pid: usize, buf: *const u8, len: usize) -> isize {
+    let receiver = match pid2process(pid) {
+        Some(process) => process,
+        None => return -ESRCH,
+    };
+    if receiver.inner_exclusive_access().mailbox.is_full() {
+        return -EAGAIN;
+    }
+    if len == 0 {
+        return 0;
+    }
+    let token = current_user_token();
+    let message: Vec<u8> = translated_byte_buffer(token, buf, len.min(MAIL_MAX_LEN))
+        .into_iter()
+        .flat_map(|buffer| buffer.iter().copied())
+        .collect();
+    let mut receiver_inner = receiver.inner_exclusive_access();
+    match receiver_inner.mailbox.push(&message) {
+        Some(len) => len as isize,
+        None => -EAGAIN,
+    }
+}
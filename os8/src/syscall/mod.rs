@@ -10,18 +10,32 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 
+const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_MKDIRAT: usize = 34;
 const SYSCALL_UNLINKAT: usize = 35;
 const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD: usize = 67;
+const SYSCALL_PWRITE: usize = 68;
 const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
+const SYSCALL_FSYNC: usize = 82;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_FUTEX: usize = 98;
 const SYSCALL_SLEEP: usize = 101;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGRETURN: usize = 139;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_GETTID: usize = 178;
@@ -29,14 +43,21 @@ const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_SPAWN: usize = 400;
+const SYSCALL_MAIL_READ: usize = 401;
+const SYSCALL_MAIL_WRITE: usize = 402;
+const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_SET_PRIORITY: usize = 140;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_PROC_LIST: usize = 411;
+const SYSCALL_SET_SYSCALL_POLICY: usize = 412;
+const SYSCALL_SBRK: usize = 413;
 const SYSCALL_THREAD_CREATE: usize = 460;
 const SYSCALL_WAITTID: usize = 462;
 const SYSCALL_MUTEX_CREATE: usize = 463;
 const SYSCALL_MUTEX_LOCK: usize = 464;
+const SYSCALL_MUTEX_TRYLOCK: usize = 465;
 const SYSCALL_MUTEX_UNLOCK: usize = 466;
 const SYSCALL_SEMAPHORE_CREATE: usize = 467;
 const SYSCALL_SEMAPHORE_UP: usize = 468;
@@ -45,56 +66,117 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 470;
 const SYSCALL_CONDVAR_CREATE: usize = 471;
 const SYSCALL_CONDVAR_SIGNAL: usize = 472;
 const SYSCALL_CONDVAR_WAIT: usize = 473;
+const SYSCALL_MUTEX_TIMEDLOCK: usize = 474;
+const SYSCALL_SEMAPHORE_TRYDOWN: usize = 475;
+const SYSCALL_SEMAPHORE_TIMEDDOWN: usize = 476;
+const SYSCALL_CONDVAR_BROADCAST: usize = 477;
+const SYSCALL_CONDVAR_TIMEDWAIT: usize = 478;
+const SYSCALL_RWLOCK_CREATE: usize = 479;
+const SYSCALL_RWLOCK_READ: usize = 480;
+const SYSCALL_RWLOCK_WRITE: usize = 481;
+const SYSCALL_RWLOCK_UNLOCK: usize = 482;
+const SYSCALL_BARRIER_CREATE: usize = 483;
+const SYSCALL_BARRIER_WAIT: usize = 484;
+const SYSCALL_THREAD_EXIT: usize = 485;
+const SYSCALL_THREAD_DETACH: usize = 486;
 
+pub mod errno;
 mod fs;
+mod ipc;
 pub mod process;
 mod sync;
 mod thread;
 
 use crate::fs::Stat;
+use crate::task::{record_current_syscall, SignalAction};
 use fs::*;
+use ipc::*;
 use process::*;
 use sync::*;
 use thread::*;
 
 /// handle syscall exception with `syscall_id` and other arguments
 pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
+    record_current_syscall(syscall_id);
     match syscall_id {
+        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
         SYSCALL_DUP => sys_dup(args[0]),
+        SYSCALL_IOCTL => sys_ioctl(args[0], args[1], args[2]),
+        SYSCALL_MKDIRAT => sys_mkdirat(args[1] as *const u8),
         SYSCALL_LINKAT => sys_linkat(args[1] as *const u8, args[3] as *const u8),
-        SYSCALL_UNLINKAT => sys_unlinkat(args[1] as *const u8),
+        SYSCALL_UNLINKAT => sys_unlinkat(args[1] as *const u8, args[2] as u32),
+        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
         SYSCALL_OPEN => sys_open(args[1] as *const u8, args[2] as u32),
         SYSCALL_CLOSE => sys_close(args[0]),
         SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
+        SYSCALL_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_PREAD => sys_pread(args[0], args[1] as *const u8, args[2], args[3]),
+        SYSCALL_PWRITE => sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
         SYSCALL_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYSCALL_SYNC => sys_sync(),
+        SYSCALL_FSYNC => sys_fsync(args[0]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1], args[2] as u32, args[3] as isize),
         SYSCALL_SLEEP => sys_sleep(args[0]),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_KILL => sys_kill(args[0], args[1]),
+        SYSCALL_SIGACTION => sys_sigaction(
+            args[0],
+            args[1] as *const SignalAction,
+            args[2] as *mut SignalAction,
+        ),
+        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
+        SYSCALL_SIGRETURN => sys_sigreturn(),
         SYSCALL_GETPID => sys_getpid(),
         SYSCALL_GETTID => sys_gettid(),
         SYSCALL_FORK => sys_fork(),
         SYSCALL_EXEC => sys_exec(args[0] as *const u8, args[1] as *const usize),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_BRK => sys_brk(args[0]),
+        SYSCALL_SBRK => sys_sbrk(args[0] as isize),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MAIL_READ => sys_mail_read(args[0] as *mut u8, args[1]),
+        SYSCALL_MAIL_WRITE => sys_mail_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
-        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
-        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
-        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
+        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo, args[1]),
+        SYSCALL_PROC_LIST => sys_proc_list(args[0] as *mut ProcInfo, args[1]),
+        SYSCALL_SET_SYSCALL_POLICY => sys_set_syscall_policy(args[0]),
+        SYSCALL_SPAWN => sys_spawn(
+            args[0] as *const u8,
+            args[1] as *const usize,
+            args[2] as *const SpawnAction,
+            args[3],
+        ),
+        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1], args[2]),
         SYSCALL_WAITTID => sys_waittid(args[0]) as isize,
-        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] == 1),
+        SYSCALL_THREAD_EXIT => sys_thread_exit(args[0] as i32),
+        SYSCALL_THREAD_DETACH => sys_thread_detach(args[0]),
+        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] as u32),
         SYSCALL_MUTEX_LOCK => sys_mutex_lock(args[0]),
+        SYSCALL_MUTEX_TRYLOCK => sys_mutex_trylock(args[0]),
+        SYSCALL_MUTEX_TIMEDLOCK => sys_mutex_timedlock(args[0], args[1]),
         SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(args[0]),
         SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
         SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
         SYSCALL_ENABLE_DEADLOCK_DETECT => sys_enable_deadlock_detect(args[0]),
         SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
+        SYSCALL_SEMAPHORE_TRYDOWN => sys_semaphore_trydown(args[0]),
+        SYSCALL_SEMAPHORE_TIMEDDOWN => sys_semaphore_timeddown(args[0], args[1]),
         SYSCALL_CONDVAR_CREATE => sys_condvar_create(args[0]),
         SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
+        SYSCALL_CONDVAR_BROADCAST => sys_condvar_broadcast(args[0]),
         SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+        SYSCALL_CONDVAR_TIMEDWAIT => sys_condvar_timedwait(args[0], args[1], args[2]),
+        SYSCALL_RWLOCK_CREATE => sys_rwlock_create(),
+        SYSCALL_RWLOCK_READ => sys_rwlock_read(args[0]),
+        SYSCALL_RWLOCK_WRITE => sys_rwlock_write(args[0]),
+        SYSCALL_RWLOCK_UNLOCK => sys_rwlock_unlock(args[0]),
+        SYSCALL_BARRIER_CREATE => sys_barrier_create(args[0]),
+        SYSCALL_BARRIER_WAIT => sys_barrier_wait(args[0]),
+        _ => sys_unknown(syscall_id),
     }
 }
@@ -1,13 +1,19 @@
 //! Process management syscalls
 
-use crate::config::MAX_SYSCALL_NUM;
-use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str, PageTable, VirtAddr};
+use super::errno::{EINVAL, ENOSYS, EPERM, ESRCH};
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE, USER_SPACE_END};
+use crate::fs::{open_file, File, OpenFlags};
+use crate::mm::{
+    copy_from_user, copy_to_user, translated_ref, translated_refmut, translated_str,
+    try_copy_from_user, MapPermission, PageTable, VirtAddr,
+};
 use crate::task::{
-    current_process, current_task, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next, TaskStatus,
+    all_processes, current_process, current_raise_fault, current_task, current_trap_cx,
+    current_user_token, exit_current_and_run_next, pid2process, raise_signal,
+    suspend_current_and_run_next, BadSyscallPolicy, ProcessControlBlock, SignalAction, SignalFlags,
+    SignalFrame, TaskStatus, INITPROC, SIG_DFL, SIG_IGN,
 };
-use crate::timer::get_time_us;
+use crate::timer::{get_time_ms, get_time_us};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -19,6 +25,7 @@ pub struct TimeVal {
     pub usec: usize,
 }
 
+#[repr(C)]
 #[derive(Clone, Copy)]
 pub struct TaskInfo {
     pub status: TaskStatus,
@@ -26,6 +33,23 @@ pub struct TaskInfo {
     pub time: usize,
 }
 
+/// Length of the program name kept in `ProcInfo`, including the trailing '\0'
+pub const PROC_NAME_LEN: usize = 32;
+
+/// A live process as seen by `sys_proc_list`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProcInfo {
+    pub pid: usize,
+    /// Pid of the parent, 0 if there is none
+    pub ppid: usize,
+    pub status: TaskStatus,
+    pub thread_count: usize,
+    /// Cpu time used by all threads in ms
+    pub time: usize,
+    pub name: [u8; PROC_NAME_LEN],
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     // debug!("[kernel] Application exited with code {}", exit_code);
     exit_current_and_run_next(exit_code);
@@ -72,11 +96,12 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
             args = args.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    let process = current_process();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(app_inode) = open_file(cwd.as_str(), path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
-        let process = current_process();
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        process.exec(path.as_str(), all_data.as_slice(), args_vec);
         argc as isize
     } else {
         -1
@@ -105,14 +130,16 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ release child PCB
     });
     if let Some((idx, _)) = pair {
+        // the child is deallocated once its last thread has left the hart it exited on
         let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after removing from children list
-        assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child TCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        let token = inner.memory_set.token();
+        // ---- release current PCB before touching user memory
+        drop(inner);
+        *translated_refmut(token, exit_code_ptr) = exit_code;
         found_pid as isize
     } else {
         -2
@@ -135,24 +162,444 @@ pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
     0
 }
 
-pub fn sys_task_info(_ti: *mut TaskInfo) -> isize {
-    -1
+/// Copy a plain value into user space at `dst`
+fn write_to_user<T>(token: usize, dst: *mut T, value: &T) {
+    let src = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    copy_to_user(token, dst as *mut u8, src);
+}
+
+/// Get the syscall counters, status and time since first scheduled in ms
+/// of the current thread (`scope` 0) or of its process (`scope` 1)
+pub fn sys_task_info(ti: *mut TaskInfo, scope: usize) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let now = get_time_ms();
+    let info = match scope {
+        0 => {
+            let task_inner = task.inner_exclusive_access();
+            TaskInfo {
+                status: task_inner.task_status,
+                syscall_times: task_inner.syscall_times,
+                time: now - task_inner.first_run_time.unwrap_or(now),
+            }
+        }
+        1 => {
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            TaskInfo {
+                status: TaskStatus::Running,
+                syscall_times: inner.syscall_times,
+                time: now - inner.first_run_time.unwrap_or(now),
+            }
+        }
+        _ => return -1,
+    };
+    drop(task);
+    write_to_user(token, ti, &info);
+    0
+}
+
+/// Status of a process derived from its threads: running if any of them is,
+/// else ready if any of them is, else blocking
+fn process_status(process: &ProcessControlBlock) -> (TaskStatus, usize) {
+    let inner = process.inner_exclusive_access();
+    let mut status = TaskStatus::Blocking;
+    let mut thread_count = 0;
+    for task in inner.tasks.iter().flatten() {
+        let task_inner = task.inner_exclusive_access();
+        if task_inner.res.is_none() {
+            continue;
+        }
+        thread_count += 1;
+        match task_inner.task_status {
+            TaskStatus::Running => status = TaskStatus::Running,
+            TaskStatus::Ready if status != TaskStatus::Running => status = TaskStatus::Ready,
+            _ => {}
+        }
+    }
+    (status, thread_count)
+}
+
+/// Fill `buf` with at most `len` entries about the live processes in the order
+/// of pid, returns the number of live processes
+pub fn sys_proc_list(buf: *mut ProcInfo, len: usize) -> isize {
+    let token = current_user_token();
+    let processes = all_processes();
+    for (i, process) in processes.iter().take(len).enumerate() {
+        let (status, thread_count) = process_status(process);
+        let inner = process.inner_exclusive_access();
+        let ppid = inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .map_or(0, |parent| parent.getpid());
+        let mut name = [0u8; PROC_NAME_LEN];
+        let name_len = inner.name.len().min(PROC_NAME_LEN - 1);
+        name[..name_len].copy_from_slice(&inner.name.as_bytes()[..name_len]);
+        let info = ProcInfo {
+            pid: process.getpid(),
+            ppid,
+            status,
+            thread_count,
+            time: inner.cpu_time / 1000,
+            name,
+        };
+        drop(inner);
+        write_to_user(token, unsafe { buf.add(i) }, &info);
+    }
+    processes.len() as isize
+}
+
+/// Copy a plain value out of user space at `src`
+fn read_from_user<T>(token: usize, src: *const T) -> T {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let dst = unsafe {
+        core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>())
+    };
+    copy_from_user(token, src as *const u8, dst);
+    unsafe { value.assume_init() }
+}
+
+/// Send signal `signum` to process `pid`, `signum` 0 only checks that it exists.
+/// Return -EPERM for a signal initproc does not handle, it must never go away.
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    let process = match pid2process(pid) {
+        Some(process) => process,
+        None => return -ESRCH,
+    };
+    if signum == 0 {
+        return 0;
+    }
+    let signal = match SignalFlags::from_signum(signum) {
+        Some(signal) => signal,
+        None => return -EINVAL,
+    };
+    if Arc::ptr_eq(&process, &INITPROC)
+        && matches!(
+            process.inner_exclusive_access().signal_actions.table[signum].handler,
+            SIG_DFL | SIG_IGN
+        )
+    {
+        return -EPERM;
+    }
+    raise_signal(&process, signal);
+    0
+}
+
+/// Set the action of signal `signum` to `action` and save the old one in
+/// `old_action`, either of them may be null
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let signal = match SignalFlags::from_signum(signum) {
+        Some(signal) if !SignalFlags::unmaskable().contains(signal) => signal,
+        _ => return -EINVAL,
+    };
+    let token = current_user_token();
+    let process = current_process();
+    let old = process.inner_exclusive_access().signal_actions.table[signum];
+    if !action.is_null() {
+        let mut new = read_from_user(token, action);
+        new.mask = SignalFlags::from_bits_truncate(new.mask.bits()) - SignalFlags::unmaskable();
+        let mut inner = process.inner_exclusive_access();
+        inner.signal_actions.table[signum] = new;
+        // an ignored signal that is pending is dropped
+        if new.handler == SIG_IGN {
+            inner.signals.remove(signal);
+        }
+    }
+    if !old_action.is_null() {
+        write_to_user(token, old_action, &old);
+    }
+    0
 }
 
-pub fn sys_set_priority(_prio: isize) -> isize {
-    -1
+/// Replace the signal mask of the current process, returns the old one
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_mask = inner.signal_mask;
+    inner.signal_mask = SignalFlags::from_bits_truncate(mask) - SignalFlags::unmaskable();
+    old_mask.bits() as isize
 }
 
-pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    -1
+/// Return from a signal handler to where the signal came, the signal frame
+/// is on top of the user stack
+pub fn sys_sigreturn() -> isize {
+    let token = current_user_token();
+    let trap_cx = current_trap_cx();
+    let frame = read_from_user(token, trap_cx.x[2] as *const SignalFrame);
+    trap_cx.x = frame.x;
+    trap_cx.sepc = frame.sepc;
+    current_process().inner_exclusive_access().signal_mask =
+        SignalFlags::from_bits_truncate(frame.mask.bits()) - SignalFlags::unmaskable();
+    // the return value must not overwrite a0 of the interrupted context
+    trap_cx.x[10] as isize
 }
 
-pub fn sys_munmap(_start: usize, _len: usize) -> isize {
-    -1
+/// Set the priority of the current thread, which must be at least 2,
+/// returns the new priority. It may still run with a higher one lent by the
+/// waiters of the mutexes it holds.
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < 2 {
+        return -1;
+    }
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.base_priority = prio as usize;
+    task_inner.update_priority();
+    prio
 }
 
-//
-// ALERT: 注意在实现 SPAWN 时不需要复制父进程地址空间，SPAWN != FORK + EXEC
-pub fn sys_spawn(_path: *const u8) -> isize {
-    -1
+/// Choose what happens when the current process makes an unknown syscall:
+/// 0 to get -ENOSYS back, 1 to be killed
+pub fn sys_set_syscall_policy(policy: usize) -> isize {
+    let policy = match policy {
+        0 => BadSyscallPolicy::ReturnError,
+        1 => BadSyscallPolicy::Kill,
+        _ => return -1,
+    };
+    current_process()
+        .inner_exclusive_access()
+        .bad_syscall_policy = policy;
+    0
+}
+
+/// Handle a syscall the kernel does not implement as the policy of the
+/// current process says
+pub fn sys_unknown(syscall_id: usize) -> isize {
+    let process = current_process();
+    let policy = process.inner_exclusive_access().bad_syscall_policy;
+    let tid = current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid;
+    warn!(
+        "[kernel] unsupported syscall {} from pid {} tid {}",
+        syscall_id,
+        process.getpid(),
+        tid
+    );
+    match policy {
+        BadSyscallPolicy::ReturnError => -ENOSYS,
+        BadSyscallPolicy::Kill => {
+            println!(
+                "[kernel] Unsupported syscall {} in application, killed.",
+                syscall_id
+            );
+            // SIGSYS takes the whole process down on the way back to user
+            // space, a handler set for it cannot keep the process alive
+            let signum = SignalFlags::SIGSYS.first_signum().unwrap();
+            process.inner_exclusive_access().signal_actions.table[signum] = SignalAction::default();
+            drop(process);
+            current_raise_fault(SignalFlags::SIGSYS);
+            -ENOSYS
+        }
+    }
+}
+
+/// Map `[start, start + len)` with `port` as `X W R` bits, pages are
+/// allocated on first access. `start` must be page aligned.
+pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 || port & !0x7 != 0 || port & 0x7 == 0 {
+        return -1;
+    }
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return -1,
+    };
+    let permission = MapPermission::from_bits((port << 1) as u8).unwrap() | MapPermission::U;
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner
+        .memory_set
+        .mmap(VirtAddr::from(start), VirtAddr::from(end), permission)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Unmap `[start, start + len)`, every page of which must be mapped
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 {
+        return -1;
+    }
+    let end = match start.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => end,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner
+        .memory_set
+        .munmap(VirtAddr::from(start), VirtAddr::from(end))
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Move the program break to `addr`, returns the new one. The current one is
+/// returned if `addr` is 0 or the heap cannot be moved there.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr != 0 {
+        inner.memory_set.set_brk(addr);
+    }
+    inner.memory_set.brk() as isize
+}
+
+/// Move the program break by `increment` bytes, returns the old one or -1
+pub fn sys_sbrk(increment: isize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_brk = inner.memory_set.brk();
+    let new_brk = if increment < 0 {
+        old_brk.checked_sub(increment.unsigned_abs())
+    } else {
+        old_brk.checked_add(increment as usize)
+    };
+    match new_brk {
+        Some(new_brk) if inner.memory_set.set_brk(new_brk) => old_brk as isize,
+        _ => -1,
+    }
+}
+
+/// Close `fd` in the child of `sys_spawn`
+pub const SPAWN_CLOSE: usize = 0;
+/// Make `fd` a copy of `src_fd` in the child of `sys_spawn`
+pub const SPAWN_DUP2: usize = 1;
+/// Open `path` with `flags` as `fd` in the child of `sys_spawn`
+pub const SPAWN_OPEN: usize = 2;
+/// File actions may not create fds beyond this
+const SPAWN_MAX_FD: usize = 256;
+/// Number of file actions a single `sys_spawn` takes at most
+const SPAWN_MAX_ACTIONS: usize = 64;
+
+/// A file action applied to the fd table of the child before it starts,
+/// like `posix_spawn_file_actions_t`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SpawnAction {
+    pub kind: usize,
+    pub fd: usize,
+    pub src_fd: usize,
+    pub path: *const u8,
+    pub flags: u32,
+}
+
+type FdTable = Vec<Option<Arc<dyn File + Send + Sync>>>;
+
+/// Apply `action` to `fd_table`, returns false if it cannot be done
+fn apply_spawn_action(
+    fd_table: &mut FdTable,
+    cwd: &str,
+    token: usize,
+    action: &SpawnAction,
+) -> bool {
+    let fd = action.fd;
+    if fd >= SPAWN_MAX_FD {
+        return false;
+    }
+    let file = match action.kind {
+        SPAWN_CLOSE => {
+            // closing an fd which is not open does nothing
+            if let Some(file) = fd_table.get_mut(fd) {
+                file.take();
+            }
+            return true;
+        }
+        SPAWN_DUP2 => match fd_table.get(action.src_fd) {
+            Some(Some(file)) => Arc::clone(file),
+            _ => return false,
+        },
+        SPAWN_OPEN => {
+            let path = translated_str(token, action.path);
+            let flags = match OpenFlags::from_bits(action.flags) {
+                Some(flags) => flags,
+                None => return false,
+            };
+            match open_file(cwd, path.as_str(), flags) {
+                Some(inode) => inode,
+                None => return false,
+            }
+        }
+        _ => return false,
+    };
+    if fd >= fd_table.len() {
+        fd_table.resize(fd + 1, None);
+    }
+    fd_table[fd] = Some(file);
+    true
+}
+
+/// Create a child process running the elf at `path` with the null-terminated
+/// argv `args`, without copying the address space of the caller. The child
+/// inherits the fd table of the caller, after applying the `actions_len`
+/// file actions at `actions` in order, there are none if `actions` is null.
+/// Returns the pid of the child.
+pub fn sys_spawn(
+    path: *const u8,
+    mut args: *const usize,
+    actions: *const SpawnAction,
+    actions_len: usize,
+) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let mut args_vec: Vec<String> = Vec::new();
+    if !args.is_null() {
+        loop {
+            let arg_str_ptr = *translated_ref(token, args);
+            if arg_str_ptr == 0 {
+                break;
+            }
+            args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+            unsafe {
+                args = args.add(1);
+            }
+        }
+    }
+    let actions_len = if actions.is_null() { 0 } else { actions_len };
+    if actions_len > SPAWN_MAX_ACTIONS {
+        return -1;
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let cwd = inner.cwd.clone();
+    let mut fd_table = inner.fd_table.clone();
+    drop(inner);
+    for i in 0..actions_len {
+        let mut action = core::mem::MaybeUninit::<SpawnAction>::uninit();
+        let dst = unsafe {
+            core::slice::from_raw_parts_mut(
+                action.as_mut_ptr() as *mut u8,
+                core::mem::size_of::<SpawnAction>(),
+            )
+        };
+        if !try_copy_from_user(token, unsafe { actions.add(i) } as *const u8, dst) {
+            return -1;
+        }
+        let action = unsafe { action.assume_init() };
+        if !apply_spawn_action(&mut fd_table, cwd.as_str(), token, &action) {
+            return -1;
+        }
+    }
+    if let Some(app_inode) = open_file(cwd.as_str(), path.as_str(), OpenFlags::RDONLY) {
+        let all_data = app_inode.read_all();
+        let child = process.spawn(path.as_str(), all_data.as_slice(), args_vec, fd_table);
+        child.getpid() as isize
+    } else {
+        -1
+    }
 }
@@ -1,27 +1,170 @@
-use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
-use crate::task::{block_current_and_run_next, current_process, current_task};
-use crate::timer::{add_timer, get_time_ms};
+use super::errno::{EAGAIN, EBUSY, EDEADLK, EFAULT, EINTR, EINVAL, EOWNERDEAD, EPERM, ETIMEDOUT};
+use crate::mm::{try_translated_ref, try_translated_refmut};
+use crate::sync::{futex_wait, futex_wake, FutexError};
+use crate::sync::{Barrier, Condvar, Resource, RwLock, Semaphore, SemaphoreError};
+use crate::sync::{
+    CheckedMutex, Mutex, MutexBlocking, MutexError, MutexFlags, MutexKind, MutexSpin,
+};
+use crate::task::{current_process, current_task, current_user_token};
+use crate::timer::{get_time_ms, sleep_current_until};
 use alloc::sync::Arc;
+use core::sync::atomic::AtomicU32;
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
 
 pub fn sys_sleep(ms: usize) -> isize {
-    let expire_ms = get_time_ms() + ms;
-    let task = current_task().unwrap();
-    add_timer(expire_ms, task);
-    block_current_and_run_next();
+    sleep_current_until(get_time_ms() + ms);
     0
 }
 
-// LAB5 HINT: you might need to maintain data structures used for deadlock detection
-// during sys_mutex_* and sys_semaphore_* syscalls
-pub fn sys_mutex_create(blocking: bool) -> isize {
+/// Sleep on (FUTEX_WAIT) or wake (FUTEX_WAKE) the futex of the u32 at `addr`.
+///
+/// A wait only sleeps if the word still holds `val`, else returns -EAGAIN, and
+/// gives up with -ETIMEDOUT after `timeout` ms unless `timeout` is negative.
+/// A wake wakes at most `val` threads and returns how many it has woken.
+/// Both return -EFAULT if the word is not mapped for the user.
+pub fn sys_futex(addr: usize, op: usize, val: u32, timeout: isize) -> isize {
+    if addr % core::mem::align_of::<AtomicU32>() != 0 {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    match op {
+        FUTEX_WAIT => {
+            // resolve copy-on-write now, the word has to sit in the frame
+            // its writers and wakers are going to find
+            let word = match try_translated_refmut(token, addr as *mut AtomicU32) {
+                Some(word) => word,
+                None => return -EFAULT,
+            };
+            let expire_ms = if timeout < 0 {
+                None
+            } else {
+                Some(get_time_ms() + timeout as usize)
+            };
+            match futex_wait(word, val, expire_ms) {
+                Ok(()) => 0,
+                Err(FutexError::ValueChanged) => -EAGAIN,
+                Err(FutexError::TimedOut) => -ETIMEDOUT,
+                Err(FutexError::Interrupted) => -EINTR,
+            }
+        }
+        FUTEX_WAKE => {
+            let word = match try_translated_ref(token, addr as *const AtomicU32) {
+                Some(word) => word,
+                None => return -EFAULT,
+            };
+            futex_wake(word, val as usize) as isize
+        }
+        _ => -EINVAL,
+    }
+}
+
+/// Tid of the current thread
+fn current_tid() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid
+}
+
+/// Record that the current thread is going to wait for `res`, returns false
+/// and takes the request back if this may lead to a deadlock
+fn request_resource(res: Resource) -> bool {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.resource_tracker.request(tid, res);
+    if process_inner.enable_deadlock && !process_inner.resource_tracker.is_safe() {
+        process_inner.resource_tracker.cancel(tid, res);
+        return false;
+    }
+    true
+}
+
+/// Take back the request of the current thread for `res`, it has given up waiting
+fn cancel_request(res: Resource) {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.resource_tracker.cancel(tid, res);
+}
+
+/// Record that the current thread has taken `res`
+fn acquire_resource(res: Resource) {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.resource_tracker.acquire(tid, res);
+}
+
+/// Record that the current thread has given `res` back
+fn release_resource(res: Resource) {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.resource_tracker.release(tid, res);
+}
+
+/// Errno of the result of a mutex operation
+fn mutex_errno(result: Result<(), MutexError>) -> isize {
+    match result {
+        Ok(()) => 0,
+        Err(MutexError::Busy) => -EBUSY,
+        Err(MutexError::TimedOut) => -ETIMEDOUT,
+        Err(MutexError::NotOwner) => -EPERM,
+        Err(MutexError::Deadlock) => -EDEADLK,
+        Err(MutexError::OwnerDead) => -EOWNERDEAD,
+        Err(MutexError::Interrupted) => -EINTR,
+    }
+}
+
+/// Settle the request of the current thread for mutex `mutex_id`, which is
+/// held after the lock succeeded or found its last owner dead
+fn settle_mutex_request(mutex_id: usize, result: Result<(), MutexError>) {
+    match result {
+        Ok(()) | Err(MutexError::OwnerDead) => acquire_resource(Resource::Mutex(mutex_id)),
+        Err(_) => cancel_request(Resource::Mutex(mutex_id)),
+    }
+}
+
+/// Whether the current thread holds the mutex already, a relock only counts
+/// up or fails and never waits
+fn is_relock(mutex: &Arc<dyn Mutex>) -> bool {
+    mutex.depth().map_or(false, |depth| depth > 0)
+}
+
+/// Create a mutex of the kind in `flags`, see [`MutexFlags`]. Return -EINVAL
+/// for unknown flags or a mutex both error-checking and recursive.
+pub fn sys_mutex_create(flags: u32) -> isize {
+    let flags = match MutexFlags::from_bits(flags) {
+        Some(flags) if !flags.contains(MutexFlags::ERRORCHECK | MutexFlags::RECURSIVE) => flags,
+        _ => return -EINVAL,
+    };
     let process = current_process();
-    let mutex: Option<Arc<dyn Mutex>> = if !blocking {
-        Some(Arc::new(MutexSpin::new()))
+    let raw: Arc<dyn Mutex> = if !flags.contains(MutexFlags::BLOCKING) {
+        Arc::new(MutexSpin::new())
     } else {
-        Some(Arc::new(MutexBlocking::new()))
+        Arc::new(MutexBlocking::new())
+    };
+    let kind = if flags.contains(MutexFlags::ERRORCHECK) {
+        MutexKind::ErrorCheck
+    } else if flags.contains(MutexFlags::RECURSIVE) {
+        MutexKind::Recursive
+    } else {
+        MutexKind::Normal
+    };
+    let robust = flags.contains(MutexFlags::ROBUST);
+    let mutex: Option<Arc<dyn Mutex>> = if kind == MutexKind::Normal && !robust {
+        Some(raw)
+    } else {
+        Some(Arc::new(CheckedMutex::new(raw, kind, robust)))
     };
     let mut process_inner = process.inner_exclusive_access();
-    if let Some(id) = process_inner
+    let id = if let Some(id) = process_inner
         .mutex_list
         .iter()
         .enumerate()
@@ -29,71 +172,82 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
         .map(|(id, _)| id)
     {
         process_inner.mutex_list[id] = mutex;
-        process_inner.mutex_available_vector[id] = 1;
-        // 每个thread增加一个资源类别
-        process_inner
-            .mutex_allocation_vector
-            .iter_mut()
-            .for_each(|resource| resource[id] = 0);
-        process_inner
-            .mutex_need_vector
-            .iter_mut()
-            .for_each(|resource| resource[id] = 0);
-        process_inner.mutex_id = id;
-        id as isize
+        id
     } else {
         process_inner.mutex_list.push(mutex);
-        process_inner.mutex_available_vector.push(1);
-        process_inner
-            .mutex_allocation_vector
-            .iter_mut()
-            .for_each(|resource| resource.push(0));
-        process_inner
-            .mutex_need_vector
-            .iter_mut()
-            .for_each(|resource| resource.push(0));
-        process_inner.mutex_id = process_inner.mutex_list.len() - 1;
-        process_inner.mutex_id as isize
-    }
+        process_inner.mutex_list.len() - 1
+    };
+    process_inner.resource_tracker.add(Resource::Mutex(id), 1);
+    id as isize
 }
 
-// LAB5 HINT: Return -0xDEAD if deadlock is detected
+/// Return -0xDEAD if locking the mutex may lead to a deadlock, -EDEADLK for a
+/// relock of an error-checking mutex, and -EOWNERDEAD if the mutex has been
+/// taken from an exited owner
 pub fn sys_mutex_lock(mutex_id: usize) -> isize {
-    let mutex = {
-        let process = current_process();
-        let mut process_inner = process.inner_exclusive_access();
-
-        if let Some(task) = current_task() {
-            let task_inner = task.inner_exclusive_access();
-            if let Some(res) = task_inner.res.as_ref() {
-                let tid = res.tid;
-                process_inner.mutex_need_vector[tid][mutex_id] += 1;
-                process_inner.mutex_id = mutex_id;
-            } else {
-                // 没有res,need
-                return -1;
-            }
-        } else {
-            // 没有task
-            return -1;
-        }
-        if process_inner.enable_deadlock && process_inner.deadlock_detect() {
-            return -0xDEAD;
-        }
-        Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap())
-    };
-    mutex.lock();
-    return 0;
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    drop(process_inner);
+    let relock = is_relock(&mutex);
+    if !relock && !request_resource(Resource::Mutex(mutex_id)) {
+        return -0xDEAD;
+    }
+    let result = mutex.lock();
+    if !relock {
+        settle_mutex_request(mutex_id, result);
+    }
+    mutex_errno(result)
+}
+
+/// Return -EBUSY if the mutex is held
+pub fn sys_mutex_trylock(mutex_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    drop(process_inner);
+    let relock = is_relock(&mutex);
+    let result = mutex.try_lock();
+    if !relock && matches!(result, Ok(()) | Err(MutexError::OwnerDead)) {
+        acquire_resource(Resource::Mutex(mutex_id));
+    }
+    mutex_errno(result)
+}
+
+/// Like [`sys_mutex_lock`], but return -ETIMEDOUT if the mutex is still held
+/// when the time reaches `expire_ms`
+pub fn sys_mutex_timedlock(mutex_id: usize, expire_ms: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    drop(process_inner);
+    let relock = is_relock(&mutex);
+    if !relock && !request_resource(Resource::Mutex(mutex_id)) {
+        return -0xDEAD;
+    }
+    let result = mutex.lock_until(expire_ms);
+    if !relock {
+        settle_mutex_request(mutex_id, result);
+    }
+    mutex_errno(result)
 }
 
+/// Return -EPERM if the mutex is not locked, or if it checks its owner and it
+/// is not the current thread
 pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
-    let mutex = {
-        let process = current_process();
-        let process_inner = process.inner_exclusive_access();
-        Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap())
-    };
-    mutex.unlock();
-    0
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    drop(process_inner);
+    // a free mutex is not given back again
+    if !mutex.is_locked() {
+        return -EPERM;
+    }
+    // a recursive mutex is only given back by its last unlock
+    if mutex.depth().map_or(true, |depth| depth == 1) {
+        release_resource(Resource::Mutex(mutex_id));
+    }
+    mutex_errno(mutex.unlock())
 }
 
 pub fn sys_semaphore_create(res_count: usize) -> isize {
@@ -107,87 +261,78 @@ pub fn sys_semaphore_create(res_count: usize) -> isize {
         .map(|(id, _)| id)
     {
         process_inner.semaphore_list[id] = Some(Arc::new(Semaphore::new(res_count)));
-        process_inner.semaphore_available_vector[id] = res_count;
-        process_inner
-            .semaphore_list
-            .push(Some(Arc::new(Semaphore::new(res_count))));
-        process_inner
-            .semaphore_allocation_vector
-            .iter_mut()
-            .for_each(|resource| resource[id] = 0);
-        process_inner
-            .semaphore_need_vector
-            .iter_mut()
-            .for_each(|resource| resource[id] = 0);
-        process_inner.semaphore_available_vector[id] = 0;
-        process_inner.semaphore_id = id;
         id
     } else {
-        println!("push!");
         process_inner
             .semaphore_list
             .push(Some(Arc::new(Semaphore::new(res_count))));
-        process_inner
-            .semaphore_allocation_vector
-            .iter_mut()
-            .for_each(|resource| resource.push(0));
-        process_inner
-            .semaphore_need_vector
-            .iter_mut()
-            .for_each(|resource| resource.push(0));
-        process_inner.semaphore_available_vector.push(res_count);
-        process_inner.semaphore_id = process_inner.semaphore_list.len() - 1;
-        process_inner.semaphore_id
+        process_inner.semaphore_list.len() - 1
     };
-    // 每个thread增加一个资源类别
-    println!(
-        "avaliable:{:?}, allocation:{:?}, need:{:?}",
-        process_inner.semaphore_available_vector,
-        process_inner.semaphore_allocation_vector,
-        process_inner.semaphore_need_vector
-    );
-
+    process_inner
+        .resource_tracker
+        .add(Resource::Semaphore(id), res_count);
     id as isize
 }
 
 pub fn sys_semaphore_up(sem_id: usize) -> isize {
-    let sem = {
-        let process = current_process();
-        let process_inner = process.inner_exclusive_access();
-
-        Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap())
-    };
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    drop(process_inner);
+    release_resource(Resource::Semaphore(sem_id));
     sem.up();
     0
 }
 
-// LAB5 HINT: Return -0xDEAD if deadlock is detected
+/// Return -0xDEAD if waiting for the semaphore may lead to a deadlock
 pub fn sys_semaphore_down(sem_id: usize) -> isize {
-    let sem = {
-        let process = current_process();
-        let mut process_inner = process.inner_exclusive_access();
-        if let Some(task) = current_task() {
-            let task_inner = task.inner_exclusive_access();
-            if let Some(res) = task_inner.res.as_ref() {
-                let tid = res.tid;
-                // println!("before need_vector:{:?}",process_inner.semaphore_need_vector);
-                process_inner.semaphore_need_vector[tid][sem_id] += 1;
-                process_inner.semaphore_id = sem_id;
-                // println!("after need_vector:{:?}",process_inner.semaphore_need_vector);
-            } else {
-                // 没有res,need
-                return -1;
-            }
-        } else {
-            // 没有task
-            return -1;
-        }
-        if process_inner.enable_deadlock && process_inner.deadlock_detect() {
-            return -0xDEAD;
-        }
-        Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap())
-    };
-    sem.down();
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    drop(process_inner);
+    if !request_resource(Resource::Semaphore(sem_id)) {
+        return -0xDEAD;
+    }
+    if !sem.down() {
+        cancel_request(Resource::Semaphore(sem_id));
+        return -EINTR;
+    }
+    acquire_resource(Resource::Semaphore(sem_id));
+    0
+}
+
+/// Return -EAGAIN if no unit of the semaphore is left
+pub fn sys_semaphore_trydown(sem_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    drop(process_inner);
+    if !sem.try_down() {
+        return -EAGAIN;
+    }
+    acquire_resource(Resource::Semaphore(sem_id));
+    0
+}
+
+/// Like [`sys_semaphore_down`], but return -ETIMEDOUT if no unit has been
+/// left when the time reaches `expire_ms`, or -EINTR if a signal going to
+/// kill the process comes first
+pub fn sys_semaphore_timeddown(sem_id: usize, expire_ms: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    drop(process_inner);
+    if !request_resource(Resource::Semaphore(sem_id)) {
+        return -0xDEAD;
+    }
+    if let Err(error) = sem.down_until(Some(expire_ms)) {
+        cancel_request(Resource::Semaphore(sem_id));
+        return match error {
+            SemaphoreError::TimedOut => -ETIMEDOUT,
+            SemaphoreError::Interrupted => -EINTR,
+        };
+    }
+    acquire_resource(Resource::Semaphore(sem_id));
     0
 }
 
@@ -221,17 +366,167 @@ pub fn sys_condvar_signal(condvar_id: usize) -> isize {
     0
 }
 
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
+    drop(process_inner);
+    condvar.broadcast();
+    0
+}
+
+/// Return -EPERM if the mutex is not locked, is checked and not held by the
+/// current thread, or is recursive and held more than once
 pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
     let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
     let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
     drop(process_inner);
-    condvar.wait(mutex);
+    // a recursive mutex held more than once would stay locked while waiting
+    if !matches!(mutex.depth(), None | Some(1)) || !mutex.is_locked() {
+        return -EPERM;
+    }
+    // the mutex is given back while waiting and requested again on wakeup
+    release_resource(Resource::Mutex(mutex_id));
+    let tid = current_tid();
+    process
+        .inner_exclusive_access()
+        .resource_tracker
+        .request(tid, Resource::Mutex(mutex_id));
+    let result = condvar.wait(mutex);
+    acquire_resource(Resource::Mutex(mutex_id));
+    mutex_errno(result)
+}
+
+/// Like [`sys_condvar_wait`], but return -ETIMEDOUT if not signalled before
+/// the time reaches `expire_ms`, the mutex is locked again either way
+pub fn sys_condvar_timedwait(condvar_id: usize, mutex_id: usize, expire_ms: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
+    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    drop(process_inner);
+    // a recursive mutex held more than once would stay locked while waiting
+    if !matches!(mutex.depth(), None | Some(1)) || !mutex.is_locked() {
+        return -EPERM;
+    }
+    release_resource(Resource::Mutex(mutex_id));
+    let tid = current_tid();
+    process
+        .inner_exclusive_access()
+        .resource_tracker
+        .request(tid, Resource::Mutex(mutex_id));
+    let result = condvar.wait_until(mutex, expire_ms);
+    acquire_resource(Resource::Mutex(mutex_id));
+    mutex_errno(result)
+}
+
+pub fn sys_rwlock_create() -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = if let Some(id) = process_inner
+        .rwlock_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.rwlock_list[id] = Some(Arc::new(RwLock::new()));
+        id
+    } else {
+        process_inner
+            .rwlock_list
+            .push(Some(Arc::new(RwLock::new())));
+        process_inner.rwlock_list.len() - 1
+    };
+    id as isize
+}
+
+pub fn sys_rwlock_read(rwlock_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let rwlock = match process_inner.rwlock_list.get(rwlock_id) {
+        Some(Some(rwlock)) => Arc::clone(rwlock),
+        _ => return -1,
+    };
+    drop(process_inner);
+    if !rwlock.read() {
+        return -EINTR;
+    }
     0
 }
 
-// LAB5 YOUR JOB: Implement deadlock detection, but might not all in this syscall
+pub fn sys_rwlock_write(rwlock_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let rwlock = match process_inner.rwlock_list.get(rwlock_id) {
+        Some(Some(rwlock)) => Arc::clone(rwlock),
+        _ => return -1,
+    };
+    drop(process_inner);
+    if !rwlock.write() {
+        return -EINTR;
+    }
+    0
+}
+
+/// Return -EPERM if the current thread does not hold the rwlock
+pub fn sys_rwlock_unlock(rwlock_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let rwlock = match process_inner.rwlock_list.get(rwlock_id) {
+        Some(Some(rwlock)) => Arc::clone(rwlock),
+        _ => return -1,
+    };
+    drop(process_inner);
+    if !rwlock.unlock() {
+        return -EPERM;
+    }
+    0
+}
+
+/// Create a barrier for `count` threads, -EINVAL if `count` is 0
+pub fn sys_barrier_create(count: usize) -> isize {
+    if count == 0 {
+        return -EINVAL;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = if let Some(id) = process_inner
+        .barrier_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.barrier_list[id] = Some(Arc::new(Barrier::new(count)));
+        id
+    } else {
+        process_inner
+            .barrier_list
+            .push(Some(Arc::new(Barrier::new(count))));
+        process_inner.barrier_list.len() - 1
+    };
+    id as isize
+}
+
+/// Return 1 to the last thread arriving at the barrier and 0 to the others
+pub fn sys_barrier_wait(barrier_id: usize) -> isize {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let barrier = match process_inner.barrier_list.get(barrier_id) {
+        Some(Some(barrier)) => Arc::clone(barrier),
+        _ => return -1,
+    };
+    drop(process_inner);
+    match barrier.wait() {
+        Some(last) => last as isize,
+        None => -EINTR,
+    }
+}
+
+/// Turn deadlock detection of the current process on (1) or off (0)
 pub fn sys_enable_deadlock_detect(_enabled: usize) -> isize {
     let process = current_process();
     match _enabled {
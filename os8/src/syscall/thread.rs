@@ -1,22 +1,38 @@
+use super::errno::ENOMEM;
 use crate::{
+    config::{PAGE_SIZE, USER_STACK_SIZE},
     mm::kernel_token,
-    task::{add_task, current_task, TaskControlBlock},
+    task::{add_task, current_task, exit_current_and_run_next, TaskControlBlock},
     trap::{trap_handler, TrapContext},
 };
-use alloc::{sync::Arc, vec};
+use alloc::sync::Arc;
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
-    println!("new_task");
+/// Create a thread running `entry(arg)` on a user stack of `stack_size` bytes,
+/// rounded up to pages, or of `USER_STACK_SIZE` if it is 0. Return -ENOMEM if
+/// there is no room for the stack.
+pub fn sys_thread_create(entry: usize, arg: usize, stack_size: usize) -> isize {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
+    let ustack_size = match stack_size {
+        0 => USER_STACK_SIZE,
+        size => match size.checked_add(PAGE_SIZE - 1) {
+            Some(size) => size & !(PAGE_SIZE - 1),
+            None => return -ENOMEM,
+        },
+    };
+    let ustack_bottom = match process
+        .inner_exclusive_access()
+        .memory_set
+        .alloc_ustack(ustack_size)
+    {
+        Some(ustack_bottom) => ustack_bottom,
+        None => return -ENOMEM,
+    };
     // create a new thread
     let new_task = Arc::new(TaskControlBlock::new(
         Arc::clone(&process),
-        task.inner_exclusive_access()
-            .res
-            .as_ref()
-            .unwrap()
-            .ustack_base,
+        ustack_bottom,
+        ustack_size,
         true,
     ));
     let new_task_inner = new_task.inner_exclusive_access();
@@ -31,6 +47,8 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     (*new_task_trap_cx).x[10] = arg;
+    (*new_task_trap_cx).set_tp(new_task_res.tls_base());
+    drop(new_task_inner);
 
     let mut process_inner = process.inner_exclusive_access();
     // add new thread to current process
@@ -41,17 +59,6 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     tasks[new_task_tid] = Some(Arc::clone(&new_task));
     // add new task to scheduler
     add_task(Arc::clone(&new_task));
-
-    let mutex_len = process_inner.mutex_available_vector.len();
-    let sem_len = process_inner.semaphore_available_vector.len();
-    process_inner
-        .mutex_allocation_vector
-        .push(vec![0; mutex_len]);
-    process_inner.mutex_need_vector.push(vec![0; mutex_len]);
-    process_inner
-        .semaphore_allocation_vector
-        .push(vec![0; sem_len]);
-    process_inner.semaphore_need_vector.push(vec![0; sem_len]);
     new_task_tid as isize
 }
 
@@ -65,22 +72,59 @@ pub fn sys_gettid() -> isize {
         .tid as isize
 }
 
-/// thread does not exist, return -1
+/// Exit the current thread with `exit_code` for `sys_waittid`, the other
+/// threads keep running. The main thread exiting ends the whole process.
+pub fn sys_thread_exit(exit_code: i32) -> ! {
+    exit_current_and_run_next(exit_code);
+    panic!("Unreachable in sys_thread_exit!");
+}
+
+/// Let thread `tid` be reaped when it exits instead of by `sys_waittid`.
+/// Return -1 if the thread does not exist or is the current one.
+pub fn sys_thread_detach(tid: usize) -> isize {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let current_tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    if current_tid == tid {
+        return -1;
+    }
+    let mut process_inner = process.inner_exclusive_access();
+    let detached_task = match process_inner.tasks.get(tid) {
+        Some(Some(detached_task)) => Arc::clone(detached_task),
+        _ => return -1,
+    };
+    let mut detached_inner = detached_task.inner_exclusive_access();
+    if detached_inner.exit_code.is_some() {
+        // it has exited already, reap it as sys_waittid does
+        drop(detached_inner);
+        process_inner.tasks[tid] = None;
+    } else {
+        // it is reaped when it exits
+        detached_inner.detached = true;
+    }
+    0
+}
+
+/// thread does not exist or is detached, return -1
 /// thread has not exited yet, return -2
 /// otherwise, return thread's exit code
 pub fn sys_waittid(tid: usize) -> i32 {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
+    let current_tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
     // a thread cannot wait for itself
-    if task_inner.res.as_ref().unwrap().tid == tid {
+    if current_tid == tid {
         return -1;
     }
+    let mut process_inner = process.inner_exclusive_access();
     let mut exit_code: Option<i32> = None;
     let waited_task = process_inner.tasks[tid].as_ref();
     if let Some(waited_task) = waited_task {
-        if let Some(waited_exit_code) = waited_task.inner_exclusive_access().exit_code {
+        let waited_inner = waited_task.inner_exclusive_access();
+        if waited_inner.detached {
+            return -1;
+        }
+        if let Some(waited_exit_code) = waited_inner.exit_code {
             exit_code = Some(waited_exit_code);
         }
     } else {
@@ -1,7 +1,7 @@
 use super::ProcessControlBlock;
-use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_TLS_SIZE};
 use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use alloc::vec;
 use alloc::{
     sync::{Arc, Weak},
@@ -41,21 +41,20 @@ impl RecycleAllocator {
 }
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
-    static ref KSTACK_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
+    static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
+    static ref KSTACK_ALLOCATOR: SpinLock<RecycleAllocator> =
+        SpinLock::new(RecycleAllocator::new());
 }
 
 pub struct PidHandle(pub usize);
 
 pub fn pid_alloc() -> PidHandle {
-    PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
+    PidHandle(PID_ALLOCATOR.lock().alloc())
 }
 
 impl Drop for PidHandle {
     fn drop(&mut self) {
-        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+        PID_ALLOCATOR.lock().dealloc(self.0);
     }
 }
 
@@ -69,10 +68,10 @@ pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
 pub struct KernelStack(pub usize);
 
 pub fn kstack_alloc() -> KernelStack {
-    let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
+    let kstack_id = KSTACK_ALLOCATOR.lock().alloc();
     let (kstack_bottom, kstack_top) = kernel_stack_position(kstack_id);
     //println!("kstack_alloc  kstack_bottom: {:#x?}, kstack_top: {:#x?}", kstack_bottom, kstack_top);
-    KERNEL_SPACE.exclusive_access().insert_framed_area(
+    KERNEL_SPACE.lock().insert_framed_area(
         kstack_bottom.into(),
         kstack_top.into(),
         MapPermission::R | MapPermission::W,
@@ -87,7 +86,7 @@ impl Drop for KernelStack {
         // let kernel_stack_bottom_pa: PhysAddr = kernel_stack_bottom.into();
         // println!("kstack_drop  kstack_bottom: va: {:#x?}, pa: {:#x?}", kernel_stack_bottom_va, kernel_stack_bottom_pa);
         KERNEL_SPACE
-            .exclusive_access()
+            .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
     }
 }
@@ -113,7 +112,9 @@ impl KernelStack {
 
 pub struct TaskUserRes {
     pub tid: usize,
-    pub ustack_base: usize,
+    /// Bottom of the user stack, the main stack may have grown below it
+    pub ustack_bottom: usize,
+    pub ustack_size: usize,
     pub process: Weak<ProcessControlBlock>,
 }
 
@@ -121,14 +122,14 @@ fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT - tid * PAGE_SIZE
 }
 
-fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
-    ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
-}
-
 impl TaskUserRes {
+    /// The user stack of `ustack_size` bytes at `ustack_bottom` is the main
+    /// stack for the first thread, else it is taken from the stack allocator
+    /// of the process
     pub fn new(
         process: Arc<ProcessControlBlock>,
-        ustack_base: usize,
+        ustack_bottom: usize,
+        ustack_size: usize,
         alloc_user_res: bool,
     ) -> Self {
         let tid = {
@@ -148,7 +149,8 @@ impl TaskUserRes {
 
         let task_user_res = Self {
             tid,
-            ustack_base,
+            ustack_bottom,
+            ustack_size,
             process: Arc::downgrade(&process),
         };
         if alloc_user_res {
@@ -160,12 +162,26 @@ impl TaskUserRes {
     pub fn alloc_user_res(&self) {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // alloc user stack
-        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
-        let ustack_top = ustack_bottom + USER_STACK_SIZE;
+        // alloc user stack, the main stack is written by exec before the
+        // process runs while the pages of other stacks are mapped on demand
+        let ustack_top = self.ustack_top();
+        if self.tid == 0 {
+            process_inner.memory_set.insert_framed_area(
+                self.ustack_bottom.into(),
+                ustack_top.into(),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            );
+        } else {
+            process_inner.memory_set.mmap(
+                self.ustack_bottom.into(),
+                ustack_top.into(),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            );
+        }
+        // alloc tls
         process_inner.memory_set.insert_framed_area(
-            ustack_bottom.into(),
-            ustack_top.into(),
+            self.tls_base().into(),
+            (self.tls_base() + USER_TLS_SIZE).into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
         );
         // alloc trap_cx
@@ -183,10 +199,18 @@ impl TaskUserRes {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
         // dealloc ustack manually
-        let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(self.ustack_base, self.tid).into();
+        let ustack_bottom_va: VirtAddr = self.ustack_bottom.into();
         process_inner
             .memory_set
-            .remove_area_with_start_vpn(ustack_bottom_va.into());
+            .remove_area_containing(ustack_bottom_va.into());
+        if self.tid != 0 {
+            process_inner.memory_set.dealloc_ustack(self.ustack_bottom);
+        }
+        // dealloc tls manually
+        let tls_base_va: VirtAddr = self.tls_base().into();
+        process_inner
+            .memory_set
+            .remove_area_with_start_vpn(tls_base_va.into());
         // dealloc trap_cx manually
         let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
         process_inner
@@ -224,11 +248,12 @@ impl TaskUserRes {
             .ppn()
     }
 
-    pub fn ustack_base(&self) -> usize {
-        self.ustack_base
-    }
     pub fn ustack_top(&self) -> usize {
-        ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_SIZE
+        self.ustack_bottom + self.ustack_size
+    }
+    /// Start of the TLS area, the initial `tp` of the thread
+    pub fn tls_base(&self) -> usize {
+        self.ustack_top()
     }
 }
 
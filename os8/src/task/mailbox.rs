@@ -0,0 +1,42 @@
+//! A bounded mailbox of messages sent to a process
+
+use crate::config::{MAIL_CAPACITY, MAIL_MAX_LEN};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Messages waiting to be read by a process, oldest first
+pub struct Mailbox {
+    messages: VecDeque<Vec<u8>>,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.messages.len() >= MAIL_CAPACITY
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Append a message of at most `MAIL_MAX_LEN` bytes, the rest is cut off,
+    /// returns the length kept or `None` if the mailbox is full
+    pub fn push(&mut self, message: &[u8]) -> Option<usize> {
+        if self.is_full() {
+            return None;
+        }
+        let len = message.len().min(MAIL_MAX_LEN);
+        self.messages.push_back(message[..len].to_vec());
+        Some(len)
+    }
+
+    /// Take the oldest message out
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.messages.pop_front()
+    }
+}
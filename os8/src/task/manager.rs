@@ -4,43 +4,77 @@
 //! Other CPU process monitoring functions are in Processor.
 
 
-use super::TaskControlBlock;
-use crate::sync::UPSafeCell;
-use alloc::collections::VecDeque;
+use super::scheduler::{new_scheduler, Scheduler};
+use super::{ProcessControlBlock, TaskControlBlock, TaskControlBlockInner};
+use crate::config::SCHED_POLICY;
+use crate::sync::SpinLock;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    scheduler: Box<dyn Scheduler + Send + Sync>,
 }
 
-/// A simple FIFO scheduler.
+/// The ready tasks, scheduled by the policy in `SCHED_POLICY`.
 impl TaskManager {
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            scheduler: new_scheduler(SCHED_POLICY),
         }
     }
     /// Add process back to ready queue
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        self.scheduler.add(task);
     }
     /// Take a process out of the ready queue
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        self.scheduler.fetch()
+    }
+    /// Account a timer tick to the running task, returns whether to preempt it
+    pub fn tick(&mut self, task_inner: &mut TaskControlBlockInner) -> bool {
+        self.scheduler.tick(task_inner)
     }
 }
 
 lazy_static! {
     /// TASK_MANAGER instance through lazy_static!
-    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
-        unsafe { UPSafeCell::new(TaskManager::new()) };
+    pub static ref TASK_MANAGER: SpinLock<TaskManager> = SpinLock::new(TaskManager::new());
+    /// All live processes by pid
+    pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        SpinLock::new(BTreeMap::new());
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.exclusive_access().add(task);
+    TASK_MANAGER.lock().add(task);
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+    TASK_MANAGER.lock().fetch()
+}
+
+pub fn tick_task(task: &Arc<TaskControlBlock>) -> bool {
+    // the manager is locked before the task like in fetch
+    let mut task_manager = TASK_MANAGER.lock();
+    task_manager.tick(&mut task.inner_exclusive_access())
+}
+
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PCB.lock().get(&pid).map(Arc::clone)
+}
+
+pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
+    PID2PCB.lock().insert(pid, process);
+}
+
+/// Remove a process from the pid table, one never inserted is left alone
+pub fn remove_from_pid2process(pid: usize) {
+    PID2PCB.lock().remove(&pid);
+}
+
+/// Get all live processes in the order of pid
+pub fn all_processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.lock().values().map(Arc::clone).collect()
 }
@@ -4,7 +4,7 @@
 //! (such as syscall or clock interrupt).
 //! By suspending or exiting the current process, you can
 //! modify the process state, manage the process queue through TASK_MANAGER,
-//! and switch the control flow through PROCESSOR.
+//! and switch the control flow through the Processor of the current hart.
 //!
 //! Be careful when you see [`__switch`]. Control flow around this function
 //! might not be what you expect.
@@ -12,9 +12,12 @@
 mod context;
 mod id;
 pub mod kthread;
+mod mailbox;
 mod manager;
 mod process;
 mod processor;
+mod scheduler;
+mod signal;
 pub mod stackless_coroutine;
 mod switch;
 #[allow(clippy::module_inception)]
@@ -22,24 +25,32 @@ mod task;
 
 pub use crate::syscall::process::TaskInfo;
 use crate::{
+    config::MAX_SYSCALL_NUM,
     fs::{open_file, OpenFlags},
+    mm::try_copy_to_user,
+    sync::Resource,
     task::id::TaskUserRes,
 };
 use alloc::{sync::Arc, vec::Vec};
 pub use context::TaskContext;
+use core::sync::atomic::Ordering;
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
 pub use kthread::kernel_stackful_coroutine_test;
 use lazy_static::*;
-pub use manager::add_task;
-use manager::fetch_task;
-use process::ProcessControlBlock;
+pub use manager::{add_task, all_processes, insert_into_pid2process, pid2process};
+use manager::{fetch_task, remove_from_pid2process, tick_task};
+use process::ProcessControlBlockInner;
+pub use process::{BadSyscallPolicy, ProcessControlBlock};
 pub use processor::{
     current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
-    run_tasks, schedule, take_current_task
+    hart_id, run_tasks, schedule, take_current_task
 };
+pub use scheduler::stride_of;
+use signal::DefaultAction;
+pub use signal::{SignalAction, SignalFlags, SignalFrame, MAX_SIG, SIG_DFL, SIG_IGN};
 pub use stackless_coroutine::kernel_stackless_coroutine_test;
 use switch::__switch;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskControlBlockInner, TaskStatus};
 
 pub fn block_current_and_run_next() {
     let task = take_current_task().unwrap();
@@ -70,6 +81,223 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
+/// Count a syscall made by the current thread in it and its process
+pub fn record_current_syscall(syscall_id: usize) {
+    if syscall_id >= MAX_SYSCALL_NUM {
+        return;
+    }
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().syscall_times[syscall_id] += 1;
+    if let Some(process) = task.process.upgrade() {
+        process.inner_exclusive_access().syscall_times[syscall_id] += 1;
+    }
+}
+
+/// Account a timer tick to the current task and switch to the next task
+/// if the scheduler preempts it
+pub fn tick_current_and_run_next() {
+    let task = current_task().unwrap();
+    let preempt = tick_task(&task);
+    drop(task);
+    if preempt {
+        suspend_current_and_run_next();
+    }
+}
+
+/// Raise the signal of a fault of the current thread. It can be neither
+/// blocked nor ignored as the faulting instruction would just run again.
+pub fn current_raise_fault(signal: SignalFlags) {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let signum = signal.first_signum().unwrap();
+    if inner.signal_mask.contains(signal) || inner.signal_actions.table[signum].handler == SIG_IGN {
+        inner.signal_mask.remove(signal);
+        inner.signal_actions.table[signum] = SignalAction::default();
+    }
+    inner.signals.insert(signal);
+}
+
+/// Whether a pending signal is going to terminate the process once delivered
+fn is_killed(inner: &ProcessControlBlockInner) -> bool {
+    let deliverable = inner.signals & (!inner.signal_mask | SignalFlags::unmaskable());
+    (1..=MAX_SIG).any(|signum| {
+        let signal = SignalFlags::from_signum(signum).unwrap();
+        deliverable.contains(signal)
+            && inner.signal_actions.table[signum].handler == SIG_DFL
+            && matches!(DefaultAction::of(signal), DefaultAction::Terminate(_))
+    })
+}
+
+/// Whether the process of the current task is going to be killed by a signal,
+/// the task should not go to sleep then
+pub fn fatal_signal_pending() -> bool {
+    current_task()
+        .unwrap()
+        .process
+        .upgrade()
+        .map_or(false, |process| {
+            is_killed(&process.inner_exclusive_access())
+        })
+}
+
+/// Wake `task` up if it sleeps in a wait that can be cut short
+fn interrupt_wait(task: &Arc<TaskControlBlock>) {
+    let wait = task.inner_exclusive_access().wait.clone();
+    if let Some(wait) = wait {
+        if wait.cancel() {
+            add_task(Arc::clone(task));
+        }
+    }
+}
+
+/// Whether a thread of `process` other than `task` has not finished exiting
+fn has_other_threads(process: &ProcessControlBlock, task: &Arc<TaskControlBlock>) -> bool {
+    let inner = process.inner_exclusive_access();
+    inner.tasks.iter().flatten().any(|other| {
+        // an exited thread runs on its kernel stack until it is switched out
+        !Arc::ptr_eq(other, task)
+            && (other.inner_exclusive_access().task_status != TaskStatus::Exited
+                || other.on_cpu.load(Ordering::Acquire))
+    })
+}
+
+/// Make `signal` pending for `process`. If it is going to kill the process,
+/// the sleeping threads are woken up to exit on their way back to user space.
+pub fn raise_signal(process: &Arc<ProcessControlBlock>, signal: SignalFlags) {
+    let stop_signals =
+        SignalFlags::SIGSTOP | SignalFlags::SIGTSTP | SignalFlags::SIGTTIN | SignalFlags::SIGTTOU;
+    let mut inner = process.inner_exclusive_access();
+    // a continue cancels pending stops and the other way round
+    if signal == SignalFlags::SIGCONT {
+        inner.signals.remove(stop_signals);
+    } else if stop_signals.contains(signal) {
+        inner.signals.remove(SignalFlags::SIGCONT);
+    }
+    inner.signals.insert(signal);
+    if !is_killed(&inner) {
+        return;
+    }
+    let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+    drop(inner);
+    for task in tasks.iter() {
+        interrupt_wait(task);
+    }
+}
+
+/// Deliver the pending signals of the current process that are not blocked,
+/// called on the way back to user space. A handler is entered with a signal
+/// frame pushed onto the user stack, at most one at a time.
+pub fn handle_signals() {
+    loop {
+        let task = current_task().unwrap();
+        let process = task.process.upgrade().unwrap();
+        let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+        let mut inner = process.inner_exclusive_access();
+        let deliverable = inner.signals & (!inner.signal_mask | SignalFlags::unmaskable());
+        let signum = match deliverable.first_signum() {
+            Some(signum) => signum,
+            None => return,
+        };
+        let signal = SignalFlags::from_signum(signum).unwrap();
+        let action = inner.signal_actions.table[signum];
+        let default_action = match action.handler {
+            SIG_DFL => DefaultAction::of(signal),
+            SIG_IGN => DefaultAction::Ignore,
+            _ => {
+                // block the signal itself and those of the action during the handler
+                inner.signals.remove(signal);
+                let old_mask = inner.signal_mask;
+                inner.signal_mask |= action.mask | signal;
+                let token = inner.memory_set.token();
+                drop(inner);
+                drop(process);
+                let trap_cx = task.inner_exclusive_access().get_trap_cx();
+                let frame = SignalFrame {
+                    x: trap_cx.x,
+                    sepc: trap_cx.sepc,
+                    mask: old_mask,
+                    signum,
+                };
+                // a bogus sp wraps around out of user space
+                let frame_addr =
+                    trap_cx.x[2].wrapping_sub(core::mem::size_of::<SignalFrame>()) & !0xf;
+                let src = unsafe {
+                    core::slice::from_raw_parts(
+                        &frame as *const SignalFrame as *const u8,
+                        core::mem::size_of::<SignalFrame>(),
+                    )
+                };
+                if !try_copy_to_user(token, frame_addr as *mut u8, src) {
+                    // no room for the frame, e.g. the stack has overflowed
+                    // into its guard page: the process dies of a SIGSEGV it
+                    // cannot catch, as in Linux
+                    let process = task.process.upgrade().unwrap();
+                    let mut inner = process.inner_exclusive_access();
+                    let segv = SignalFlags::SIGSEGV;
+                    inner.signal_actions.table[segv.first_signum().unwrap()] =
+                        SignalAction::default();
+                    inner.signal_mask.remove(segv);
+                    inner.signals.insert(segv);
+                    continue;
+                }
+                trap_cx.x[1] = action.restorer;
+                trap_cx.x[2] = frame_addr;
+                trap_cx.x[10] = signum;
+                trap_cx.x[11] = frame_addr;
+                trap_cx.sepc = action.handler;
+                return;
+            }
+        };
+        match default_action {
+            DefaultAction::Ignore => inner.signals.remove(signal),
+            DefaultAction::Terminate(exit_code) => {
+                // the signal stays pending, so every thread exits on its way
+                // back to user space, woken up if it sleeps. The main thread
+                // ends the process once the others are gone.
+                let others: Vec<_> = inner
+                    .tasks
+                    .iter()
+                    .flatten()
+                    .filter(|other| !Arc::ptr_eq(other, &task))
+                    .cloned()
+                    .collect();
+                drop(inner);
+                for other in others.iter() {
+                    interrupt_wait(other);
+                }
+                drop(others);
+                if tid == 0 {
+                    // threads created meanwhile are waited for as well
+                    while has_other_threads(&process, &task) {
+                        suspend_current_and_run_next();
+                    }
+                }
+                drop(process);
+                drop(task);
+                exit_current_and_run_next(exit_code);
+                unreachable!();
+            }
+            DefaultAction::Stop => {
+                inner.signals.remove(signal);
+                drop(inner);
+                drop(process);
+                drop(task);
+                // stay off the cpu until the process is continued or killed
+                loop {
+                    let process = current_process();
+                    let inner = process.inner_exclusive_access();
+                    if inner.signals.contains(SignalFlags::SIGCONT) || is_killed(&inner) {
+                        break;
+                    }
+                    drop(inner);
+                    drop(process);
+                    suspend_current_and_run_next();
+                }
+            }
+        }
+    }
+}
+
 /// Exit current task, recycle process resources and switch to the next task
 pub fn exit_current_and_run_next(exit_code: i32) {
     // take from Processor
@@ -80,15 +308,50 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     let tid = task_inner.res.as_ref().unwrap().tid;
     // Record exit code
     task_inner.exit_code = Some(exit_code);
-    task_inner.res = None;
+    task_inner.task_status = TaskStatus::Exited;
+    let res = task_inner.res.take();
+    // read along with setting the exit code, a thread detached later is
+    // reaped by `sys_thread_detach` instead
+    let reap = task_inner.detached && tid != 0;
+    drop(task_inner);
 
     // here we do not remove the thread since we are still using the kstack
-    // it will be deallocated when sys_waittid is called
-    drop(task_inner);
+    // it will be deallocated when sys_waittid is called. A detached thread is
+    // taken out before its tid may be reused, the idle control flow keeps its
+    // kstack until it has switched away from it.
+    if reap {
+        let reaped = process.inner_exclusive_access().tasks[tid].take();
+        drop(reaped);
+    }
+    // robust mutexes still held are unlocked for the next locker
+    let mutexes: Vec<_> = process
+        .inner_exclusive_access()
+        .mutex_list
+        .iter()
+        .enumerate()
+        .filter_map(|(id, mutex)| mutex.clone().map(|mutex| (id, mutex)))
+        .collect();
+    for (id, mutex) in mutexes {
+        if mutex.owner_exited(&task) {
+            process
+                .inner_exclusive_access()
+                .resource_tracker
+                .release(tid, Resource::Mutex(id));
+        }
+    }
     drop(task);
+    // the user resources are given back to the process, which must not be
+    // locked while the thread is
+    drop(res);
     // debug!("task {} dropped", tid);
+    // the tid may be recycled by a new thread
+    process
+        .inner_exclusive_access()
+        .resource_tracker
+        .clear_thread(tid);
 
     if tid == 0 {
+        remove_from_pid2process(process.getpid());
         let mut process_inner = process.inner_exclusive_access();
         // mark this process as a zombie process
         process_inner.is_zombie = true;
@@ -97,15 +360,19 @@ pub fn exit_current_and_run_next(exit_code: i32) {
 
         // do not move to its parent but under initproc
         // debug!("reparent");
+        let children = core::mem::take(&mut process_inner.children);
+        // initproc may be waiting for us with its PCB locked, so ours is released
+        drop(process_inner);
 
         // ++++++ access initproc PCB exclusively
         {
             let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
+            for child in children.iter() {
                 child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
                 initproc_inner.children.push(child.clone());
             }
         }
+        let process_inner = process.inner_exclusive_access();
         let mut recycle_res = Vec::<TaskUserRes>::new();
 
         // debug!("deallocate user res");
@@ -145,9 +412,9 @@ lazy_static! {
     /// the name "initproc" may be changed to any other app name like "usertests",
     /// but we have user_shell, so we don't need to change it.
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
-        let inode = open_file("ch8b_initproc", OpenFlags::RDONLY).unwrap();
+        let inode = open_file("/", "ch8b_initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new("ch8b_initproc", v.as_slice())
     };
 }
 
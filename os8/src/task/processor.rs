@@ -3,15 +3,23 @@
 //! Here, the continuous operation of user apps in CPU is maintained,
 //! the current running state of CPU is recorded,
 //! and the replacement and transfer of control flow of different applications are executed.
+//!
+//! Each hart has its own [`Processor`], found by the hart id kept in `tp`.
 
 use super::__switch;
 use super::process::ProcessControlBlock;
 use super::{fetch_task, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
+use crate::config::MAX_HARTS;
+use crate::drivers::poll_irq;
 use crate::sync::UPSafeCell;
+use crate::timer::{check_timer, get_time_ms, get_time_us, set_next_trigger};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
+use riscv::register::sip;
 
 /// Processor management structure
 pub struct Processor {
@@ -40,8 +48,23 @@ impl Processor {
 }
 
 lazy_static! {
-    /// PROCESSOR instance through lazy_static!
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+    /// One Processor per hart, each only accessed by its own hart
+    pub static ref PROCESSORS: [UPSafeCell<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| unsafe { UPSafeCell::new(Processor::new()) });
+}
+
+/// Id of the hart we are running on, the kernel keeps it in `tp`
+pub fn hart_id() -> usize {
+    let hart_id;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) hart_id);
+    }
+    hart_id
+}
+
+/// The Processor of the current hart
+fn processor() -> &'static UPSafeCell<Processor> {
+    &PROCESSORS[hart_id()]
 }
 
 /// The main part of process execution and scheduling
@@ -50,36 +73,78 @@ lazy_static! {
 /// and switch the process through __switch
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        let mut processor = processor().exclusive_access();
         if let Some(task) = fetch_task() {
             // println!("task get!");
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            // a task woken up right after it went to sleep may still be
+            // on its way to another hart's idle control flow
+            while task.on_cpu.load(Ordering::Acquire) {
+                spin_loop();
+            }
+            task.on_cpu.store(true, Ordering::Relaxed);
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            let now = get_time_ms();
+            task_inner.first_run_time.get_or_insert(now);
             drop(task_inner);
             // release coming task TCB manually
+            if let Some(process) = task.process.upgrade() {
+                process.inner_exclusive_access().first_run_time.get_or_insert(now);
+            }
+            let running_task = Arc::clone(&task);
             processor.current = Some(task);
             // release processor manually
             drop(processor);
+            let start_us = get_time_us();
             unsafe {
+                // kernel stacks are remapped when recycled, forget the old ones
+                core::arch::asm!("sfence.vma");
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // back in idle control flow, the context of the task is saved
+            running_task.on_cpu.store(false, Ordering::Release);
+            // charge the time to the task just switched out
+            let cpu_time = get_time_us() - start_us;
+            running_task.inner_exclusive_access().cpu_time += cpu_time;
+            if let Some(process) = running_task.process.upgrade() {
+                process.inner_exclusive_access().cpu_time += cpu_time;
+            }
         } else {
-            println!("no tasks available in run_tasks");
+            // interrupts are off in the kernel, an idle hart sleeps until one
+            // is pending and then looks for the sleepers whose time is up and
+            // the finished disk requests by itself; a task queued by another
+            // hart meanwhile is picked up at the next tick at the latest
+            drop(processor);
+            unsafe {
+                riscv::asm::wfi();
+            }
+            let sip = sip::read();
+            if sip.stimer() {
+                set_next_trigger();
+            }
+            if sip.ssoft() {
+                // a TLB shootdown, nothing of a user space to flush here
+                unsafe {
+                    sip::clear_ssoft();
+                }
+            }
+            check_timer();
+            poll_irq();
         }
     }
 }
 
 /// Get current task through take, leaving a None in its place
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    processor().exclusive_access().take_current()
 }
 
 /// Get a copy of the current task
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    processor().exclusive_access().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -112,7 +177,7 @@ pub fn current_trap_cx_user_va() -> usize {
 
 /// Return to idle control flow for new scheduling
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = processor().exclusive_access();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
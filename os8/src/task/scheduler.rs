@@ -0,0 +1,189 @@
+//! Scheduling policies behind [`TaskManager`](super::manager::TaskManager)
+//!
+//! A [`Scheduler`] owns the ready tasks and decides which one runs next and
+//! when a running one is preempted. The policy in use is chosen by
+//! [`SCHED_POLICY`](crate::config::SCHED_POLICY).
+
+use super::{TaskControlBlock, TaskControlBlockInner};
+use crate::config::{SchedPolicy, BIG_STRIDE, MLFQ_BOOST_TICKS, MLFQ_LEVELS, RR_TIME_SLICE};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// The interface every scheduling policy implements
+pub trait Scheduler {
+    /// Put a ready task into the scheduler
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Take the next task to run out of the scheduler
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Account a timer tick to the running task,
+    /// returns whether it should be preempted
+    fn tick(&mut self, task_inner: &mut TaskControlBlockInner) -> bool;
+}
+
+/// Create the scheduler of the given policy
+pub fn new_scheduler(policy: SchedPolicy) -> alloc::boxed::Box<dyn Scheduler + Send + Sync> {
+    use alloc::boxed::Box;
+    match policy {
+        SchedPolicy::Fifo => Box::new(FifoScheduler::new()),
+        SchedPolicy::RoundRobin => Box::new(RoundRobinScheduler::new()),
+        SchedPolicy::Stride => Box::new(StrideScheduler::new()),
+        SchedPolicy::Mlfq => Box::new(MlfqScheduler::new()),
+    }
+}
+
+/// A simple FIFO scheduler without preemption
+pub struct FifoScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+    fn tick(&mut self, _task_inner: &mut TaskControlBlockInner) -> bool {
+        false
+    }
+}
+
+/// A round robin scheduler
+pub struct RoundRobinScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl RoundRobinScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let task = self.ready_queue.pop_front()?;
+        // a fresh time slice every time it is picked
+        task.inner_exclusive_access().ticks = 0;
+        Some(task)
+    }
+    fn tick(&mut self, task_inner: &mut TaskControlBlockInner) -> bool {
+        task_inner.ticks += 1;
+        task_inner.ticks >= RR_TIME_SLICE
+    }
+}
+
+/// A stride scheduler, the task with the smallest pass runs next
+/// and advances its pass by its stride
+pub struct StrideScheduler {
+    ready_queue: Vec<Arc<TaskControlBlock>>,
+    /// Pass of the latest picked task, tasks joining with a smaller pass
+    /// (new or long asleep) start from here so that they cannot monopolize the cpu
+    min_pass: u64,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: Vec::new(),
+            min_pass: 0,
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.pass = task_inner.pass.max(self.min_pass);
+        drop(task_inner);
+        self.ready_queue.push(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let (idx, _) = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, task)| task.inner_exclusive_access().pass)?;
+        let task = self.ready_queue.swap_remove(idx);
+        let mut task_inner = task.inner_exclusive_access();
+        self.min_pass = task_inner.pass;
+        task_inner.pass += task_inner.stride;
+        drop(task_inner);
+        Some(task)
+    }
+    fn tick(&mut self, _task_inner: &mut TaskControlBlockInner) -> bool {
+        true
+    }
+}
+
+/// A multi-level feedback queue scheduler: tasks start at the top level,
+/// sink a level each time they use up a time slice, which doubles per level,
+/// and are all lifted back to the top every `MLFQ_BOOST_TICKS` ticks
+pub struct MlfqScheduler {
+    queues: Vec<VecDeque<Arc<TaskControlBlock>>>,
+    ticks: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: (0..MLFQ_LEVELS).map(|_| VecDeque::new()).collect(),
+            ticks: 0,
+        }
+    }
+    fn time_slice(level: usize) -> usize {
+        1 << level
+    }
+    fn boost(&mut self) {
+        for level in 1..MLFQ_LEVELS {
+            while let Some(task) = self.queues[level].pop_front() {
+                task.inner_exclusive_access().level = 0;
+                self.queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let level = task.inner_exclusive_access().level;
+        self.queues[level].push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let task = self.queues.iter_mut().find_map(|queue| queue.pop_front())?;
+        task.inner_exclusive_access().ticks = 0;
+        Some(task)
+    }
+    fn tick(&mut self, task_inner: &mut TaskControlBlockInner) -> bool {
+        self.ticks += 1;
+        if self.ticks % MLFQ_BOOST_TICKS == 0 {
+            self.boost();
+            task_inner.level = 0;
+        }
+        task_inner.ticks += 1;
+        if task_inner.ticks >= Self::time_slice(task_inner.level) {
+            task_inner.level = (task_inner.level + 1).min(MLFQ_LEVELS - 1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Stride of a task with the given priority
+pub fn stride_of(priority: usize) -> u64 {
+    BIG_STRIDE / priority as u64
+}
@@ -0,0 +1,170 @@
+//! Signals of a process and the actions taken on them
+
+/// Largest signal number
+pub const MAX_SIG: usize = 31;
+/// Handler value of the default action of a signal
+pub const SIG_DFL: usize = 0;
+/// Handler value to ignore a signal
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    /// A set of signals, bit `n` stands for signal number `n`
+    pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+impl SignalFlags {
+    /// The set of the single signal `signum`, `None` if there is no such signal
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if (1..=MAX_SIG).contains(&signum) {
+            Self::from_bits(1 << signum)
+        } else {
+            None
+        }
+    }
+
+    /// The smallest signal number in the set
+    pub fn first_signum(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bits().trailing_zeros() as usize)
+        }
+    }
+
+    /// Signals that can be neither caught, blocked nor ignored
+    pub fn unmaskable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+}
+
+/// What happens to a process on a signal without a handler
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DefaultAction {
+    /// Terminate the process with the exit code
+    Terminate(i32),
+    Ignore,
+    /// Stop the process until it gets SIGCONT
+    Stop,
+}
+
+impl DefaultAction {
+    pub fn of(signal: SignalFlags) -> Self {
+        if signal == SignalFlags::SIGSEGV || signal == SignalFlags::SIGBUS {
+            // page fault exit code
+            Self::Terminate(-2)
+        } else if signal == SignalFlags::SIGILL {
+            // illegal instruction exit code
+            Self::Terminate(-3)
+        } else if signal == SignalFlags::SIGSYS {
+            // bad syscall exit code
+            Self::Terminate(-4)
+        } else if (SignalFlags::SIGCHLD
+            | SignalFlags::SIGCONT
+            | SignalFlags::SIGURG
+            | SignalFlags::SIGWINCH)
+            .contains(signal)
+        {
+            Self::Ignore
+        } else if (SignalFlags::SIGSTOP
+            | SignalFlags::SIGTSTP
+            | SignalFlags::SIGTTIN
+            | SignalFlags::SIGTTOU)
+            .contains(signal)
+        {
+            Self::Stop
+        } else {
+            Self::Terminate(-(signal.first_signum().unwrap() as i32))
+        }
+    }
+}
+
+/// The action taken on a signal, shared with user space
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SignalAction {
+    /// Address of the handler, or `SIG_DFL`/`SIG_IGN`
+    pub handler: usize,
+    /// Signals blocked while the handler runs, besides the signal itself
+    pub mask: SignalFlags,
+    /// Where the handler returns to, it should call `sys_sigreturn`
+    pub restorer: usize,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+            restorer: 0,
+        }
+    }
+}
+
+/// Actions of all signals of a process
+#[derive(Clone)]
+pub struct SignalActions {
+    pub table: [SignalAction; MAX_SIG + 1],
+}
+
+impl Default for SignalActions {
+    fn default() -> Self {
+        Self {
+            table: [SignalAction::default(); MAX_SIG + 1],
+        }
+    }
+}
+
+impl SignalActions {
+    /// Handlers do not survive exec, ignored signals stay ignored
+    pub fn reset_handlers(&mut self) {
+        for action in self.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+    }
+}
+
+/// What the user stack holds while a handler runs, `sys_sigreturn`
+/// restores the interrupted context from it
+#[repr(C)]
+pub struct SignalFrame {
+    /// General-Purpose Register x0-31 when the signal came
+    pub x: [usize; 32],
+    pub sepc: usize,
+    /// Signal mask to restore
+    pub mask: SignalFlags,
+    pub signum: usize,
+}
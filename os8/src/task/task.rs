@@ -1,11 +1,18 @@
 //! Types related to task management & Functions for completely changing TCB
 
 use super::id::TaskUserRes;
-use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use super::{kstack_alloc, stride_of, KernelStack, ProcessControlBlock, TaskContext};
+use crate::config::{DEFAULT_PRIORITY, MAX_SYSCALL_NUM};
+use crate::timer::WaitCancel;
 use crate::trap::TrapContext;
-use crate::{mm::PhysPageNum, sync::UPSafeCell};
+use crate::{
+    mm::{FrameTracker, PhysPageNum},
+    sync::{MutexBlocking, SpinLock, SpinLockGuard},
+};
+use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
-use core::cell::RefMut;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicBool;
 
 /// Task control block structure
 ///
@@ -15,14 +22,17 @@ pub struct TaskControlBlock {
     pub process: Weak<ProcessControlBlock>,
     /// Kernel stack corresponding to TID
     pub kernel_stack: KernelStack,
+    /// Set while a hart runs on the kernel stack of this task, until its
+    /// context is saved by `__switch` no other hart may switch to it
+    pub on_cpu: AtomicBool,
     // mutable
-    inner: UPSafeCell<TaskControlBlockInner>,
+    inner: SpinLock<TaskControlBlockInner>,
 }
 
 /// Structure containing more process content
 ///
 /// Store the contents that will change during operation
-/// and are wrapped by UPSafeCell to provide mutual exclusion
+/// and are wrapped by SpinLock to provide mutual exclusion
 pub struct TaskControlBlockInner {
     /// The physical page number of the frame where the trap context is placed
     pub trap_cx_ppn: PhysPageNum,
@@ -34,6 +44,36 @@ pub struct TaskControlBlockInner {
     pub exit_code: Option<i32>,
     /// Tid and ustack will be deallocated when this goes None
     pub res: Option<TaskUserRes>,
+    /// Scheduling priority set by the thread itself, at least 2
+    pub base_priority: usize,
+    /// Priority the task is scheduled with, `base_priority` raised by the
+    /// waiters of the blocking mutexes it holds
+    pub priority: usize,
+    /// Stride scheduling: the pass advances by `stride` each time the task is picked
+    pub stride: u64,
+    pub pass: u64,
+    /// Timer ticks used in the current time slice
+    pub ticks: usize,
+    /// Level in the multi-level feedback queue
+    pub level: usize,
+    /// Number of calls of each syscall made by this thread
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// Time in ms when this thread was first scheduled
+    pub first_run_time: Option<usize>,
+    /// Cpu time used by this thread in us
+    pub cpu_time: usize,
+    /// Frames of the user buffers of the running syscall, which must not be
+    /// swapped out while the kernel uses them
+    pub pinned_frames: Vec<Arc<FrameTracker>>,
+    /// Highest priority lent by the waiters of each blocking mutex held,
+    /// keyed by the address of the mutex
+    pub donations: BTreeMap<usize, usize>,
+    /// The blocking mutex this task waits for
+    pub blocked_on: Option<Arc<MutexBlocking>>,
+    /// Cuts the wait the task sleeps in short, for a signal killing the process
+    pub wait: Option<Arc<WaitCancel>>,
+    /// Nobody waits for a detached thread, it is reaped without `sys_waittid`
+    pub detached: bool,
 }
 
 /// Simple access to its internal fields
@@ -51,15 +91,31 @@ impl TaskControlBlockInner {
     fn get_status(&self) -> TaskStatus {
         self.task_status
     }
+
+    /// Schedule the task with the highest of its own and the lent priorities
+    pub fn update_priority(&mut self) {
+        self.priority = self
+            .donations
+            .values()
+            .copied()
+            .fold(self.base_priority, usize::max);
+        self.stride = stride_of(self.priority);
+    }
 }
 
 impl TaskControlBlock {
     pub fn new(
         process: Arc<ProcessControlBlock>,
-        ustack_base: usize,
+        ustack_bottom: usize,
+        ustack_size: usize,
         alloc_user_res: bool,
     ) -> Self {
-        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res);
+        let res = TaskUserRes::new(
+            Arc::clone(&process),
+            ustack_bottom,
+            ustack_size,
+            alloc_user_res,
+        );
         let trap_cx_ppn = res.trap_cx_ppn();
         let kernel_stack = kstack_alloc();
         let kstack_top = kernel_stack.get_top();
@@ -68,21 +124,34 @@ impl TaskControlBlock {
         Self {
             process: Arc::downgrade(&process),
             kernel_stack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
+            on_cpu: AtomicBool::new(false),
+            inner: SpinLock::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                base_priority: DEFAULT_PRIORITY,
+                priority: DEFAULT_PRIORITY,
+                stride: stride_of(DEFAULT_PRIORITY),
+                pass: 0,
+                ticks: 0,
+                level: 0,
+                syscall_times: [0; MAX_SYSCALL_NUM],
+                first_run_time: None,
+                cpu_time: 0,
+                pinned_frames: Vec::new(),
+                donations: BTreeMap::new(),
+                blocked_on: None,
+                wait: None,
+                detached: false,
+            }),
         }
     }
 
-    /// Get the mutex to get the RefMut TaskControlBlockInner
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
-        let inner = self.inner.exclusive_access();
+    /// Lock the mutex to get the TaskControlBlockInner
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskControlBlockInner> {
+        let inner = self.inner.lock();
         // if self.process.upgrade().unwrap().pid.0 > 1 {
         //     if let Some(res) = inner.res.as_ref() {
         //         println!("t{}i", res.tid);
@@ -118,16 +187,29 @@ impl TaskControlBlock {
         Self {
             process,
             kernel_stack: KernelStack(kstack_top),
+            on_cpu: AtomicBool::new(false),
             //kstack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    res: None,
-                    trap_cx_ppn: context_ppn,
-                    task_cx: context,
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
+            inner: SpinLock::new(TaskControlBlockInner {
+                res: None,
+                trap_cx_ppn: context_ppn,
+                task_cx: context,
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                base_priority: DEFAULT_PRIORITY,
+                priority: DEFAULT_PRIORITY,
+                stride: stride_of(DEFAULT_PRIORITY),
+                pass: 0,
+                ticks: 0,
+                level: 0,
+                syscall_times: [0; MAX_SYSCALL_NUM],
+                first_run_time: None,
+                cpu_time: 0,
+                pinned_frames: Vec::new(),
+                donations: BTreeMap::new(),
+                blocked_on: None,
+                wait: None,
+                detached: false,
+            }),
         }
     }
 }
@@ -138,5 +220,6 @@ pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
+    Exited,
     Blocking,
 }
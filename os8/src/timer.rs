@@ -2,11 +2,15 @@
 
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
-use crate::sync::UPSafeCell;
-use crate::task::{add_task, TaskControlBlock};
+use crate::sync::SpinLock;
+use crate::task::{
+    add_task, block_current_and_run_next, current_task, fatal_signal_pending, TaskControlBlock,
+};
+use alloc::boxed::Box;
 use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
 use core::cmp::Ordering;
+use core::sync::atomic::{self, AtomicBool};
 use lazy_static::*;
 use riscv::register::time;
 
@@ -34,9 +38,35 @@ pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
 
+/// The way to cut a wait on a queue short, shared by the timer of the wait
+/// and the signals waking up the threads of a process being killed
+pub struct WaitCancel {
+    /// Takes the task off its wait queue, false if it has been woken up from
+    /// there already. It is gone once the wait is over.
+    cancel: SpinLock<Option<Box<dyn FnOnce() -> bool + Send>>>,
+}
+
+impl WaitCancel {
+    /// Take the task off its wait queue, returns whether the caller is the
+    /// one to wake it up
+    pub fn cancel(&self) -> bool {
+        // held while cancelling, the task cannot be back on the queue for
+        // another wait before the cancel is done
+        let mut cancel = self.cancel.lock();
+        cancel.take().map_or(false, |cancel| cancel())
+    }
+
+    /// The wait is over, a later cancel does nothing
+    fn disarm(&self) {
+        self.cancel.lock().take();
+    }
+}
+
 pub struct TimerCondVar {
     pub expire_ms: usize,
     pub task: Arc<TaskControlBlock>,
+    /// Cuts the wait of the task short once the time is up
+    pub cancel: Arc<WaitCancel>,
 }
 
 impl PartialEq for TimerCondVar {
@@ -60,22 +90,69 @@ impl Ord for TimerCondVar {
 }
 
 lazy_static! {
-    static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> =
-        unsafe { UPSafeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+    static ref TIMERS: SpinLock<BinaryHeap<TimerCondVar>> =
+        SpinLock::new(BinaryHeap::<TimerCondVar>::new());
+}
+
+/// Block the current task, which has just joined a wait queue, until it is
+/// woken up from there, `expire_ms` has passed or a signal is going to kill
+/// the process. The last two call `cancel` to take the task off the queue,
+/// which returns false if the task has been woken up already. It may be
+/// called with the timers locked.
+///
+/// Returns false if the wait has been cut short.
+pub fn block_current_until<F>(expire_ms: Option<usize>, cancel: F) -> bool
+where
+    F: FnOnce(&Arc<TaskControlBlock>) -> bool + Send + 'static,
+{
+    let task = current_task().unwrap();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancel = {
+        let task = Arc::clone(&task);
+        let cancelled = Arc::clone(&cancelled);
+        move || {
+            let done = cancel(&task);
+            cancelled.store(done, atomic::Ordering::Relaxed);
+            done
+        }
+    };
+    let wait = Arc::new(WaitCancel {
+        cancel: SpinLock::new(Some(Box::new(cancel))),
+    });
+    task.inner_exclusive_access().wait = Some(Arc::clone(&wait));
+    if let Some(expire_ms) = expire_ms {
+        TIMERS.lock().push(TimerCondVar {
+            expire_ms,
+            task: Arc::clone(&task),
+            cancel: Arc::clone(&wait),
+        });
+    }
+    // a killer looks for the wait after raising its signal, so one raised
+    // before the wait has been published is found here
+    if !(fatal_signal_pending() && wait.cancel()) {
+        block_current_and_run_next();
+    }
+    // the timer stays in the heap until it is due, disarmed it does nothing
+    wait.disarm();
+    task.inner_exclusive_access().wait = None;
+    !cancelled.load(atomic::Ordering::Relaxed)
 }
 
-pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
-    let mut timers = TIMERS.exclusive_access();
-    timers.push(TimerCondVar { expire_ms, task });
+/// Sleep until `expire_ms`, or until a signal is going to kill the process
+pub fn sleep_current_until(expire_ms: usize) {
+    block_current_until(Some(expire_ms), |_| true);
 }
 
 pub fn check_timer() {
     let current_ms = get_time_ms();
-    let mut timers = TIMERS.exclusive_access();
+    let mut timers = TIMERS.lock();
     while let Some(timer) = timers.peek() {
         if timer.expire_ms <= current_ms {
-            add_task(Arc::clone(&timer.task));
-            timers.pop();
+            let timer = timers.pop().unwrap();
+            // a task woken up from its wait queue is running or queued already
+            if timer.cancel.cancel() {
+                add_task(timer.task);
+            }
         } else {
             break;
         }
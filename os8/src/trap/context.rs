@@ -17,12 +17,18 @@ pub struct TrapContext {
     pub kernel_sp: usize,
     /// Virtual address of trap handler entry point in kernel
     pub trap_handler: usize,
+    /// Kernel `tp` holding the id of the hart, set each time we return to user
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
+    /// Set the user `tp`, pointing to the thread-local storage
+    pub fn set_tp(&mut self, tp: usize) {
+        self.x[4] = tp;
+    }
     pub fn app_init_context(
         entry: usize,
         sp: usize,
@@ -40,6 +46,7 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
         };
         cx.set_sp(sp);
         cx
@@ -15,16 +15,19 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::drivers::irq_handler;
+use crate::fs::flush_dirty_blocks;
+use crate::mm::{enter_user, leave_user, unpin_user_pages, MapPermission, VirtAddr};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_trap_cx_user_va, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next,
+    current_process, current_raise_fault, current_trap_cx, current_trap_cx_user_va,
+    current_user_token, handle_signals, hart_id, tick_current_and_run_next, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sie, sip, stval, stvec,
 };
 
 core::arch::global_asm!(include_str!("trap.S"));
@@ -51,9 +54,24 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+/// External interrupts come from the devices through the PLIC
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// Software interrupts are IPIs sent by other harts
+pub fn enable_software_interrupt() {
+    unsafe {
+        sie::set_ssoft();
+    }
+}
+
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    leave_user();
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
@@ -63,10 +81,31 @@ pub fn trap_handler() -> ! {
             cx.sepc += 4;
             // get system call return value
             let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13]]);
+            // the kernel is done with the user buffers of the syscall
+            unpin_user_pages();
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        // lazily mapped pages are mapped on first access, swapped out ones are read back
+        Trap::Exception(Exception::StorePageFault)
+            if handle_page_fault(stval, MapPermission::W) => {}
+        Trap::Exception(Exception::LoadPageFault)
+            if handle_page_fault(stval, MapPermission::R) => {}
+        Trap::Exception(Exception::InstructionPageFault)
+            if handle_page_fault(stval, MapPermission::X) => {}
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+            if is_stack_guard(stval) =>
+        {
+            println!(
+                "[kernel] Stack overflow in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
+                stval,
+                current_trap_cx().sepc,
+            );
+            current_raise_fault(SignalFlags::SIGSEGV);
+        }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
@@ -79,18 +118,26 @@ pub fn trap_handler() -> ! {
                 stval,
                 current_trap_cx().sepc,
             );
-            // page fault exit code
-            exit_current_and_run_next(-2);
+            current_raise_fault(SignalFlags::SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             println!("[kernel] IllegalInstruction in application, core dumped.");
-            // illegal instruction exit code
-            exit_current_and_run_next(-3);
+            current_raise_fault(SignalFlags::SIGILL);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
-            suspend_current_and_run_next();
+            flush_dirty_blocks();
+            tick_current_and_run_next();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            irq_handler();
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // a TLB shootdown, the TLB has been flushed on the way in
+            unsafe {
+                sip::clear_ssoft();
+            }
         }
         _ => {
             panic!(
@@ -103,11 +150,31 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// Try to resolve a page fault at `addr` in the current process
+fn handle_page_fault(addr: usize, access: MapPermission) -> bool {
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .handle_page_fault(VirtAddr::from(addr), access)
+}
+
+/// Whether `addr` is in the guard page below a user stack of the current process
+fn is_stack_guard(addr: usize) -> bool {
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .is_stack_guard(VirtAddr::from(addr))
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
+    handle_signals();
     set_user_trap_entry();
     let trap_cx_ptr = current_trap_cx_user_va();
     let user_satp = current_user_token();
+    // the thread may come back to the kernel on another hart
+    current_trap_cx().kernel_tp = hart_id();
+    enter_user(user_satp);
     extern "C" {
         fn __alltraps();
         fn __restore();
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{brk, munmap, sbrk};
+
+/*
+理想结果：程序断点可以增长和收缩，增长出的内存可读写，
+堆被 munmap 掉一部分后断点仍可移动，
+堆分配可以远超 16 KiB，输出 Test sbrk OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let origin = sbrk(0);
+    assert!(origin > 0);
+    assert_eq!(brk(0), origin);
+    let start = sbrk(8192) as usize;
+    assert_eq!(start as isize, origin);
+    for addr in start..start + 8192 {
+        unsafe {
+            *(addr as *mut u8) = addr as u8;
+        }
+    }
+    for addr in start..start + 8192 {
+        assert_eq!(unsafe { *(addr as *const u8) }, addr as u8);
+    }
+    assert_eq!(sbrk(-4096), origin + 8192);
+    assert_eq!(brk(0), origin + 4096);
+    assert_eq!(brk(origin as usize), origin);
+    // the break cannot go below where the heap starts
+    assert_eq!(brk(1), origin);
+    assert_eq!(sbrk(isize::MIN), -1);
+
+    // the break still moves after parts of the heap are unmapped
+    let start = sbrk(3 * 4096) as usize;
+    unsafe {
+        *(start as *mut u8) = 1;
+    }
+    assert_eq!(munmap(start + 4096, 4096), 0);
+    assert_eq!(munmap(start + 8192, 4096), 0);
+    assert_eq!(sbrk(4096), origin + 3 * 4096);
+    unsafe {
+        *((start + 3 * 4096) as *mut u8) = 2;
+        assert_eq!(*(start as *const u8), 1);
+    }
+    assert_eq!(brk(start + 4096), origin + 4096);
+    assert_eq!(brk(origin as usize), origin);
+
+    let v: Vec<usize> = (0..0x10_0000).collect();
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(i, *x);
+    }
+    let mut s = String::new();
+    for _ in 0..10000 {
+        s.push_str("sbrk ");
+    }
+    assert_eq!(s.len(), 50000);
+    drop(v);
+    println!("Test sbrk OK!");
+    0
+}
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{spawn_with, syscall6, waitpid, SpawnAction, SYSCALL_SPAWN};
+
+/// 测试 spawn 的文件操作：关闭没有打开的 fd 什么也不做；文件操作数组为空指针时忽略其长度，
+/// 以兼容只传路径的 spawn；文件操作过多时返回 -1。输出 Test spawn actions OK! 就算正确。
+
+const PATH: &str = "ch5_getpid\0";
+
+fn wait_ok(pid: isize) {
+    assert!(pid > 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let args = [PATH.as_ptr(), core::ptr::null()];
+    wait_ok(spawn_with(PATH, &args, &[SpawnAction::close(100)]));
+    wait_ok(syscall6(
+        SYSCALL_SPAWN,
+        [PATH.as_ptr() as usize, 0, 0, usize::MAX, 0, 0],
+    ));
+    let actions = [SpawnAction::close(100); 65];
+    assert_eq!(spawn_with(PATH, &args, &actions), -1);
+    println!("Test spawn actions OK!");
+    0
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+use user_lib::{chdir, close, fstat, getcwd, mkdir, open, read, rmdir, unlink, write, OpenFlags, Stat, StatMode};
+
+/// 测试 mkdir/rmdir/chdir/getcwd，输出　Test dir OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, world!";
+    assert_eq!(mkdir("dir0\0"), 0);
+    assert_eq!(mkdir("dir0\0"), -1);
+    assert_eq!(mkdir("dir0/dir1\0"), 0);
+    // 在子目录中创建文件
+    let fd = open("dir0/dir1/fname\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+    // 切换工作目录后用相对路径访问
+    assert_eq!(chdir("dir0/./dir1\0"), 0);
+    let mut buf = [0u8; 100];
+    let len = getcwd(&mut buf);
+    assert_eq!(core::str::from_utf8(&buf[..len as usize]).unwrap(), "/dir0/dir1\0");
+    let fd = open("fname\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let read_len = read(fd as usize, &mut buf) as usize;
+    assert_eq!(test_str, core::str::from_utf8(&buf[..read_len]).unwrap());
+    close(fd as usize);
+    let fd = open("..\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let stat = Stat::new();
+    fstat(fd as usize, &stat);
+    assert_eq!(stat.mode, StatMode::DIR);
+    assert_eq!(stat.nlink, 3);
+    close(fd as usize);
+    assert_eq!(chdir("fname\0"), -1);
+    assert_eq!(chdir("../..\0"), 0);
+    let len = getcwd(&mut buf);
+    assert_eq!(core::str::from_utf8(&buf[..len as usize]).unwrap(), "/\0");
+    // 非空目录不能删除
+    assert_eq!(rmdir("dir0\0"), -1);
+    assert_eq!(unlink("dir0/dir1\0"), -1);
+    assert_eq!(unlink("/dir0/dir1/fname\0"), 0);
+    assert_eq!(rmdir("dir0/dir1\0"), 0);
+    assert_eq!(rmdir("dir0\0"), 0);
+    assert_eq!(chdir("dir0\0"), -1);
+    println!("Test dir OK!");
+    0
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fsync, open, read, sync, write, OpenFlags};
+
+/// 测试 fsync 与 sync 的接口：写回成功返回 0，对无效的 fd 返回 -1，写回后文件内容不变；
+/// 数据是否真正落盘由 easy-fs-fuse 的 efs_test 直接读磁盘镜像检查。
+/// 输出 Test fsync OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, fsync!";
+    let fname = "fsync_file\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, test_str.as_bytes());
+    assert_eq!(fsync(fd), 0);
+    close(fd);
+    assert_eq!(fsync(fd), -1);
+    assert_eq!(sync(), 0);
+
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut buffer = [0u8; 100];
+    let read_len = read(fd, &mut buffer) as usize;
+    close(fd);
+
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+    println!("Test fsync OK!");
+    0
+}
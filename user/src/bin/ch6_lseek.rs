@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, pipe, pread, pwrite, read, write, OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+/// 测试 lseek、pread、pwrite 与 O_APPEND：pread/pwrite 不移动文件偏移，
+/// 追加写总在文件末尾，对管道 lseek 返回 -29 (ESPIPE)。
+/// 输出 Test lseek OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fname = "lseek_file\0";
+    let fd = open(fname, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"Hello, world!"), 13);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 13);
+    assert_eq!(lseek(fd, -6, SEEK_END), 7);
+    let mut buffer = [0u8; 100];
+    assert_eq!(read(fd, &mut buffer), 6);
+    assert_eq!(&buffer[..6], b"world!");
+    assert_eq!(lseek(fd, 7, SEEK_SET), 7);
+    assert_eq!(write(fd, b"rCore"), 5);
+    assert_eq!(lseek(fd, -1, SEEK_SET), -22);
+    assert_eq!(lseek(fd, -20, SEEK_CUR), -22);
+
+    // the offset stays at 12
+    assert_eq!(pread(fd, &mut buffer, 0), 13);
+    assert_eq!(&buffer[..13], b"Hello, rCore!");
+    assert_eq!(pwrite(fd, b"h", 0), 1);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+    close(fd);
+
+    let fd = open(fname, OpenFlags::WRONLY | OpenFlags::APPEND);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    lseek(fd, 0, SEEK_SET);
+    assert_eq!(write(fd, b"!!"), 2);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 15);
+    close(fd);
+
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let len = read(fd, &mut buffer) as usize;
+    assert_eq!(&buffer[..len], b"hello, rCore!!!");
+    close(fd);
+
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -29);
+    assert_eq!(pwrite(pipe_fd[1], b"x", 0), -29);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    assert_eq!(lseek(1, 0, SEEK_CUR), -29);
+    println!("Test lseek OK!");
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, getpid, mail_read, mail_write, waitpid, EAGAIN, ESRCH};
+
+const MAIL_CAPACITY: usize = 16;
+const MAIL_MAX_LEN: usize = 256;
+
+/// 测试邮箱的收发、容量与长度限制，输出 Test mail OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    let mut buf = [0u8; 300];
+    // 空邮箱
+    assert_eq!(mail_read(&mut buf), -EAGAIN);
+    assert_eq!(mail_read(&mut buf[..0]), -EAGAIN);
+    // 给自己发信
+    assert_eq!(mail_write(pid, b"hello"), 5);
+    assert_eq!(mail_read(&mut buf[..0]), 0);
+    assert_eq!(mail_read(&mut buf), 5);
+    assert_eq!(&buf[..5], b"hello");
+    // 超长的信会被截断，读缓冲区不够时余下部分被丢弃
+    assert_eq!(mail_write(pid, &[b'a'; 300]), MAIL_MAX_LEN as isize);
+    assert_eq!(mail_read(&mut buf[..10]), 10);
+    assert_eq!(mail_read(&mut buf), -EAGAIN);
+    // 邮箱满
+    for i in 0..MAIL_CAPACITY {
+        assert_eq!(mail_write(pid, &[i as u8]), 1);
+    }
+    assert_eq!(mail_write(pid, b"x"), -EAGAIN);
+    assert_eq!(mail_write(pid, &[]), -EAGAIN);
+    for i in 0..MAIL_CAPACITY {
+        assert_eq!(mail_read(&mut buf), 1);
+        assert_eq!(buf[0], i as u8);
+    }
+    // 不存在的进程
+    assert_eq!(mail_write(usize::MAX, b"x"), -ESRCH);
+    // 子进程给父进程发信
+    let child = fork();
+    if child == 0 {
+        assert_eq!(mail_write(pid, b"from child"), 10);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+    assert_eq!(mail_read(&mut buf), 10);
+    assert_eq!(&buf[..10], b"from child");
+    println!("Test mail OK!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, set_syscall_policy, syscall, thread_create, waitpid, waittid, ENOSYS};
+
+const SYSCALL_UNKNOWN: usize = 999;
+
+fn bad_thread() -> ! {
+    syscall(SYSCALL_UNKNOWN, [0, 0, 0]);
+    println!("Should not reach here!");
+    exit(0)
+}
+
+/// 测试未实现的系统调用：默认返回 -ENOSYS，设置后整个进程被杀死（即使出错的不是主线程），输出 Test bad syscall OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(syscall(SYSCALL_UNKNOWN, [0, 0, 0]), -ENOSYS);
+    assert_eq!(set_syscall_policy(true), 0);
+    let pid = fork();
+    if pid == 0 {
+        // 策略随 fork 继承
+        syscall(SYSCALL_UNKNOWN, [0, 0, 0]);
+        println!("Should not reach here!");
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -4);
+    let pid = fork();
+    if pid == 0 {
+        let tid = thread_create(bad_thread as usize, 0);
+        waittid(tid as usize);
+        println!("Should not reach here!");
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -4);
+    assert_eq!(set_syscall_policy(false), 0);
+    assert_eq!(syscall(SYSCALL_UNKNOWN, [0, 0, 0]), -ENOSYS);
+    println!("Test bad syscall OK!");
+    0
+}
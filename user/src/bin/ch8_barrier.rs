@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{barrier_create, barrier_wait, exit, thread_create, waittid};
+
+/// 测试屏障：THREADS 个线程分段求和后逐轮两两归约，每轮之间用同一个屏障同步，
+/// 每轮恰有一个线程最后到达。输出 Test barrier OK! 就算正确。
+
+const THREADS: usize = 8;
+const PER_THREAD: usize = 1000;
+const BARRIER_ID: usize = 0;
+
+static mut SUMS: [usize; THREADS] = [0; THREADS];
+static LAST_ARRIVALS: AtomicUsize = AtomicUsize::new(0);
+
+fn arrive() {
+    if barrier_wait(BARRIER_ID) == 1 {
+        LAST_ARRIVALS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+unsafe fn reduce(id: usize) -> ! {
+    let start = id * PER_THREAD;
+    SUMS[id] = (start..start + PER_THREAD).sum();
+    let mut step = 1;
+    while step < THREADS {
+        arrive();
+        if id % (2 * step) == 0 {
+            SUMS[id] += SUMS[id + step];
+        }
+        step *= 2;
+    }
+    arrive();
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(barrier_create(THREADS) as usize, BARRIER_ID);
+    let mut tids = [0; THREADS];
+    for (id, tid) in tids.iter_mut().enumerate() {
+        *tid = thread_create(reduce as usize, id);
+    }
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid as usize), 0);
+    }
+    let n = THREADS * PER_THREAD;
+    assert_eq!(unsafe { SUMS[0] }, n * (n - 1) / 2);
+    // log2(THREADS) rounds of reduction and the final one
+    assert_eq!(LAST_ARRIVALS.load(Ordering::SeqCst), 4);
+    println!("Test barrier OK!");
+    0
+}
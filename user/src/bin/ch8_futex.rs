@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use user_lib::sync::{Condvar, Mutex};
+use user_lib::{exit, futex_wait, futex_wake, get_time, sleep_blocking, thread_create, waittid};
+
+/// 测试 futex：值不符时不睡眠，超时返回 -ETIMEDOUT，没有等待者时唤醒数为 0；
+/// 基于 futex 的条件变量能唤醒等待的线程。输出 Test futex OK! 就算正确。
+
+const EAGAIN: isize = 11;
+const ETIMEDOUT: isize = 110;
+const WORKERS: usize = 4;
+
+static WORD: AtomicU32 = AtomicU32::new(0);
+static MUTEX: Mutex = Mutex::new();
+static CONDVAR: Condvar = Condvar::new();
+static mut READY: bool = false;
+static mut DONE: usize = 0;
+
+unsafe fn worker() -> ! {
+    MUTEX.lock();
+    while !READY {
+        CONDVAR.wait(&MUTEX);
+    }
+    DONE += 1;
+    MUTEX.unlock();
+    exit(0)
+}
+
+unsafe fn waker() -> ! {
+    sleep_blocking(10);
+    WORD.store(1, Ordering::SeqCst);
+    futex_wake(&WORD, 1);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(futex_wait(&WORD, 1, -1), -EAGAIN);
+    let start = get_time();
+    assert_eq!(futex_wait(&WORD, 0, 50), -ETIMEDOUT);
+    assert!(get_time() - start >= 50);
+    assert_eq!(futex_wake(&WORD, 1), 0);
+
+    let tid = thread_create(waker as usize, 0);
+    while WORD.load(Ordering::SeqCst) == 0 {
+        futex_wait(&WORD, 0, -1);
+    }
+    assert_eq!(waittid(tid as usize), 0);
+
+    let mut tids = [0; WORKERS];
+    for tid in tids.iter_mut() {
+        *tid = thread_create(worker as usize, 0);
+    }
+    sleep_blocking(10);
+    MUTEX.lock();
+    unsafe {
+        READY = true;
+    }
+    CONDVAR.broadcast();
+    MUTEX.unlock();
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid as usize), 0);
+    }
+    assert_eq!(unsafe { DONE }, WORKERS);
+    println!("Test futex OK!");
+    0
+}
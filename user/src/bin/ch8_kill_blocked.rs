@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::AtomicU32;
+use user_lib::{exit, fork, futex_wait, kill, semaphore_create, semaphore_down};
+use user_lib::{sleep_blocking, thread_create, waitpid, SIGKILL, SIGTERM};
+
+/// 测试杀死阻塞中的进程：在信号量、futex 或 sleep 上睡眠的线程会被致命信号唤醒，
+/// 整个进程以信号的退出码结束；非主线程出错时主线程同样随之退出。
+/// 输出 Test kill blocked OK! 就算正确。
+
+static WORD: AtomicU32 = AtomicU32::new(0);
+static mut SEM_ID: usize = 0;
+
+fn sem_waiter() -> ! {
+    semaphore_down(unsafe { SEM_ID });
+    println!("Should not reach here!");
+    exit(0)
+}
+
+fn faulter() -> ! {
+    unsafe {
+        core::ptr::null_mut::<u8>().write_volatile(0);
+    }
+    exit(0)
+}
+
+/// Run `child` in a new process, send it `signum` after a while if it is
+/// not 0, and return its exit code
+fn run(child: fn(), signum: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        child();
+        println!("Should not reach here!");
+        exit(0);
+    }
+    if signum != 0 {
+        sleep_blocking(50);
+        assert_eq!(kill(pid as usize, signum), 0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+fn wait_semaphore() {
+    unsafe {
+        SEM_ID = semaphore_create(0) as usize;
+    }
+    thread_create(sem_waiter as usize, 0);
+    semaphore_down(unsafe { SEM_ID });
+}
+
+fn wait_futex() {
+    thread_create(faulter as usize, 0);
+    futex_wait(&WORD, 0, -1);
+}
+
+fn sleep_long() {
+    sleep_blocking(1_000_000);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(run(wait_semaphore, SIGTERM), -(SIGTERM as i32));
+    assert_eq!(run(wait_futex, 0), -2);
+    assert_eq!(run(sleep_long, SIGKILL), -(SIGKILL as i32));
+    println!("Test kill blocked OK!");
+    0
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{condvar_create, condvar_wait, exit, thread_create, waittid};
+use user_lib::{mutex_create_with, mutex_lock, mutex_trylock, mutex_unlock, MutexFlags};
+use user_lib::{EDEADLK, EOWNERDEAD, EPERM};
+
+/// 测试检查持有者的互斥锁：检错锁重复上锁返回 -EDEADLK，非持有者解锁返回 -EPERM；
+/// 递归锁解锁同样次数后才释放，多次持有时不能用于条件变量等待；健壮锁的持有者
+/// 退出后自动解锁，下一个上锁者得到 -EOWNERDEAD 并持有锁，非健壮锁则保持锁定。
+/// 输出 Test mutex kinds OK! 就算正确。
+
+const EBUSY: isize = 16;
+const EINVAL: isize = 22;
+
+static mut MUTEX_ID: usize = 0;
+
+unsafe fn unlocker() -> ! {
+    exit(mutex_unlock(MUTEX_ID) as i32)
+}
+
+unsafe fn try_locker() -> ! {
+    let ret = mutex_trylock(MUTEX_ID);
+    if ret == 0 {
+        mutex_unlock(MUTEX_ID);
+    }
+    exit(ret as i32)
+}
+
+unsafe fn dying_owner() -> ! {
+    assert_eq!(mutex_lock(MUTEX_ID), 0);
+    exit(0)
+}
+
+unsafe fn run(f: unsafe fn() -> !) -> isize {
+    let tid = thread_create(f as usize, 0);
+    waittid(tid as usize)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(
+        mutex_create_with(MutexFlags::ERRORCHECK | MutexFlags::RECURSIVE),
+        -EINVAL
+    );
+    let condvar_id = condvar_create() as usize;
+    unsafe {
+        for blocking in [MutexFlags::empty(), MutexFlags::BLOCKING] {
+            // errorcheck
+            MUTEX_ID = mutex_create_with(blocking | MutexFlags::ERRORCHECK) as usize;
+            assert_eq!(mutex_lock(MUTEX_ID), 0);
+            assert_eq!(mutex_lock(MUTEX_ID), -EDEADLK);
+            assert_eq!(mutex_trylock(MUTEX_ID), -EDEADLK);
+            assert_eq!(run(unlocker), -EPERM);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+            assert_eq!(mutex_unlock(MUTEX_ID), -EPERM);
+
+            // recursive
+            MUTEX_ID = mutex_create_with(blocking | MutexFlags::RECURSIVE) as usize;
+            assert_eq!(mutex_lock(MUTEX_ID), 0);
+            assert_eq!(mutex_lock(MUTEX_ID), 0);
+            assert_eq!(mutex_trylock(MUTEX_ID), 0);
+            assert_eq!(run(unlocker), -EPERM);
+            assert_eq!(condvar_wait(condvar_id, MUTEX_ID), -EPERM);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+            assert_eq!(run(try_locker), -EBUSY);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+            assert_eq!(run(try_locker), 0);
+
+            // robust, the lock is held by the next locker after the owner died
+            MUTEX_ID = mutex_create_with(blocking | MutexFlags::ROBUST) as usize;
+            assert_eq!(run(dying_owner), 0);
+            assert_eq!(mutex_lock(MUTEX_ID), -EOWNERDEAD);
+            assert_eq!(run(try_locker), -EBUSY);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+            assert_eq!(mutex_lock(MUTEX_ID), 0);
+            assert_eq!(mutex_unlock(MUTEX_ID), 0);
+
+            // any other one stays locked after the owner died
+            MUTEX_ID = mutex_create_with(blocking | MutexFlags::ERRORCHECK) as usize;
+            assert_eq!(run(dying_owner), 0);
+            assert_eq!(mutex_trylock(MUTEX_ID), -EBUSY);
+            assert_eq!(mutex_unlock(MUTEX_ID), -EPERM);
+        }
+    }
+    println!("Test mutex kinds OK!");
+    0
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{exit, get_time, set_priority, thread_create, waittid, yield_, EPERM};
+use user_lib::{mutex_blocking_create, mutex_lock, mutex_unlock};
+
+/// 测试阻塞互斥锁的优先级继承：低优先级线程持有锁时，高优先级线程等待该锁，
+/// 同时有多个中优先级线程占用 CPU。持有者借到高优先级后能先于中优先级线程完成，
+/// 高优先级线程在任何中优先级线程结束前拿到锁，重复释放锁返回 -EPERM。
+/// 输出 Test priority inherit OK! 就算正确。
+
+const MUTEX_ID: usize = 0;
+const MEDIUMS: usize = 8;
+const LOW_WORK: usize = 1_000_000;
+const MEDIUM_WORK: usize = 10 * LOW_WORK;
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static MEDIUMS_DONE: AtomicUsize = AtomicUsize::new(0);
+static DONE_BEFORE_HIGH: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn work(n: usize) -> usize {
+    let mut t = 2usize;
+    for _ in 0..n {
+        t = t * t % 10007;
+    }
+    t
+}
+
+fn low() -> ! {
+    set_priority(2);
+    mutex_lock(MUTEX_ID);
+    LOCKED.store(true, Ordering::SeqCst);
+    let t = work(LOW_WORK);
+    mutex_unlock(MUTEX_ID);
+    exit(t as i32)
+}
+
+fn medium() -> ! {
+    set_priority(16);
+    let t = work(MEDIUM_WORK);
+    MEDIUMS_DONE.fetch_add(1, Ordering::SeqCst);
+    exit(t as i32)
+}
+
+fn high() -> ! {
+    set_priority(64);
+    mutex_lock(MUTEX_ID);
+    DONE_BEFORE_HIGH.store(MEDIUMS_DONE.load(Ordering::SeqCst), Ordering::SeqCst);
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_ID);
+    let low = thread_create(low as usize, 0);
+    while !LOCKED.load(Ordering::SeqCst) {
+        yield_();
+    }
+    let mut mediums = [0; MEDIUMS];
+    for tid in mediums.iter_mut() {
+        *tid = thread_create(medium as usize, 0);
+    }
+    let high = thread_create(high as usize, 0);
+    assert_eq!(waittid(high as usize), 0);
+    println!("high got the lock after {}ms", get_time() - start);
+    waittid(low as usize);
+    for tid in mediums.iter() {
+        waittid(*tid as usize);
+    }
+    println!("mediums done after {}ms", get_time() - start);
+    assert_eq!(DONE_BEFORE_HIGH.load(Ordering::SeqCst), 0);
+    // a free mutex cannot be unlocked again
+    assert_eq!(mutex_unlock(MUTEX_ID), -EPERM);
+    println!("Test priority inherit OK!");
+    0
+}
@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, sleep_blocking, thread_create, waittid, EPERM};
+use user_lib::{rwlock_create, rwlock_read, rwlock_unlock, rwlock_write};
+
+/// 测试读写锁：写者修改两个值期间读者看不到不一致的状态，读者可以同时持有锁，
+/// 有写者等待时新来的读者排在其后，未持有锁的线程不能释放锁。
+/// 输出 Test rwlock OK! 就算正确。
+
+const READERS: usize = 4;
+const WRITERS: usize = 2;
+const ROUNDS: usize = 20;
+const RWLOCK_ID: usize = 0;
+
+static mut A: usize = 0;
+static mut B: usize = 0;
+
+unsafe fn writer() -> ! {
+    for _ in 0..ROUNDS {
+        rwlock_write(RWLOCK_ID);
+        let a = &mut A as *mut usize;
+        a.write_volatile(a.read_volatile() + 1);
+        sleep_blocking(0);
+        let b = &mut B as *mut usize;
+        b.write_volatile(b.read_volatile() + 1);
+        rwlock_unlock(RWLOCK_ID);
+    }
+    exit(0)
+}
+
+unsafe fn reader() -> ! {
+    for _ in 0..ROUNDS {
+        rwlock_read(RWLOCK_ID);
+        let a = (&A as *const usize).read_volatile();
+        sleep_blocking(1);
+        assert_eq!((&B as *const usize).read_volatile(), a);
+        rwlock_unlock(RWLOCK_ID);
+    }
+    exit(0)
+}
+
+fn shared_reader() -> ! {
+    rwlock_read(RWLOCK_ID);
+    rwlock_unlock(RWLOCK_ID);
+    exit(0)
+}
+
+unsafe fn late_reader() -> ! {
+    rwlock_read(RWLOCK_ID);
+    // the writer queued up before this reader has been first
+    assert_eq!((&A as *const usize).read_volatile(), 1);
+    rwlock_unlock(RWLOCK_ID);
+    exit(0)
+}
+
+fn stray_unlocker() -> ! {
+    // the lock is held for writing by the main thread
+    assert_eq!(rwlock_unlock(RWLOCK_ID), -EPERM);
+    exit(0)
+}
+
+unsafe fn late_writer() -> ! {
+    rwlock_write(RWLOCK_ID);
+    A = 1;
+    rwlock_unlock(RWLOCK_ID);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(rwlock_create() as usize, RWLOCK_ID);
+    let mut tids = [0; READERS + WRITERS];
+    for (i, tid) in tids.iter_mut().enumerate() {
+        let entry = if i < READERS {
+            reader as usize
+        } else {
+            writer as usize
+        };
+        *tid = thread_create(entry, 0);
+    }
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid as usize), 0);
+    }
+    assert_eq!(unsafe { (A, B) }, (WRITERS * ROUNDS, WRITERS * ROUNDS));
+
+    unsafe {
+        A = 0;
+    }
+    rwlock_read(RWLOCK_ID);
+    // another reader gets the lock while it is read
+    let tid = thread_create(shared_reader as usize, 0);
+    assert_eq!(waittid(tid as usize), 0);
+    let writer = thread_create(late_writer as usize, 0);
+    sleep_blocking(10);
+    let reader = thread_create(late_reader as usize, 0);
+    sleep_blocking(10);
+    assert_eq!(unsafe { A }, 0);
+    rwlock_unlock(RWLOCK_ID);
+    assert_eq!(waittid(writer as usize), 0);
+    assert_eq!(waittid(reader as usize), 0);
+
+    // only a thread holding the lock may release it
+    assert_eq!(rwlock_unlock(RWLOCK_ID), -EPERM);
+    rwlock_write(RWLOCK_ID);
+    let tid = thread_create(stray_unlocker as usize, 0);
+    assert_eq!(waittid(tid as usize), 0);
+    assert_eq!(rwlock_unlock(RWLOCK_ID), 0);
+    assert_eq!(rwlock_unlock(RWLOCK_ID + 1), -1);
+    println!("Test rwlock OK!");
+    0
+}
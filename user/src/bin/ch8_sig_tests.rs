@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, getpid, kill, sigaction, sigprocmask, sleep, waitpid, SignalAction, SignalFlags,
+    EPERM, SIGKILL, SIGSEGV, SIGSTOP, SIGTERM, SIGUSR1, SIGUSR2,
+};
+
+/// 测试信号的发送、处理、屏蔽与缺省动作，输出 Test signal OK! 就算正确。
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handler(signum: usize) {
+    HANDLED.fetch_add(signum, Ordering::SeqCst);
+}
+
+extern "C" fn segv_handler(_signum: usize) {
+    exit(42);
+}
+
+fn handle(signum: usize, handler: usize) {
+    let action = SignalAction {
+        handler,
+        ..SignalAction::default()
+    };
+    assert_eq!(sigaction(signum, Some(&action), None), 0);
+}
+
+fn wait_child(pid: isize) -> i32 {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    // 处理函数在 kill 返回之前运行，且不破坏被打断时的寄存器
+    handle(SIGUSR1, handler as usize);
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), SIGUSR1);
+    // 屏蔽期间信号保持未决，解除屏蔽后送达
+    handle(SIGUSR2, handler as usize);
+    sigprocmask(SignalFlags::SIGUSR2);
+    assert_eq!(kill(pid, SIGUSR2), 0);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), SIGUSR1);
+    assert_eq!(sigprocmask(SignalFlags::empty()), SignalFlags::SIGUSR2);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), SIGUSR1 + SIGUSR2);
+    // SIGKILL 与 SIGSTOP 不能被捕获
+    assert!(sigaction(SIGKILL, Some(&SignalAction::default()), None) < 0);
+    assert!(sigaction(SIGSTOP, Some(&SignalAction::default()), None) < 0);
+    // 杀死死循环的子进程
+    let child = fork();
+    if child == 0 {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+    sleep(10);
+    assert_eq!(kill(child as usize, SIGKILL), 0);
+    assert_eq!(wait_child(child), -(SIGKILL as i32));
+    // 访存错误成为 SIGSEGV，可被捕获
+    let child = fork();
+    if child == 0 {
+        handle(SIGSEGV, segv_handler as usize);
+        unsafe { (0x0 as *mut u8).write_volatile(0) };
+        exit(0);
+    }
+    assert_eq!(wait_child(child), 42);
+    // 未捕获时按缺省动作结束进程
+    let child = fork();
+    if child == 0 {
+        unsafe { (0x0 as *mut u8).write_volatile(0) };
+        exit(0);
+    }
+    assert_eq!(wait_child(child), -2);
+    // initproc（pid 0）不接受它没有处理的信号
+    assert_eq!(kill(0, SIGKILL), -EPERM);
+    assert_eq!(kill(0, SIGTERM), -EPERM);
+    println!("Test signal OK!");
+    0
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, munmap, waitpid};
+
+/// 测试换页：映射并写满比 128 MiB 内存更大的区域，页被换出到交换盘后仍能读回；
+/// fork 出的子进程与父进程共享换出的页，子进程的修改对父进程不可见。
+/// 输出 Test swap OK! 就算正确。
+
+const START: usize = 0x1000_0000;
+const LEN: usize = 160 * 1024 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+fn page(i: usize) -> *mut usize {
+    (START + i * PAGE_SIZE) as *mut usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pages = LEN / PAGE_SIZE;
+    assert_eq!(mmap(START, LEN, 3), 0);
+    for i in 0..pages {
+        unsafe {
+            page(i).write_volatile(i);
+        }
+    }
+    for i in 0..pages {
+        assert_eq!(unsafe { page(i).read_volatile() }, i);
+    }
+    let pid = fork();
+    if pid == 0 {
+        for i in 0..pages {
+            assert_eq!(unsafe { page(i).read_volatile() }, i);
+            if i % 2 == 0 {
+                unsafe {
+                    page(i).write_volatile(!i);
+                }
+            }
+        }
+        for i in (0..pages).step_by(2) {
+            assert_eq!(unsafe { page(i).read_volatile() }, !i);
+        }
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for i in 0..pages {
+        assert_eq!(unsafe { page(i).read_volatile() }, i);
+    }
+    assert_eq!(munmap(START, LEN), 0);
+    println!("Test swap OK!");
+    0
+}
@@ -0,0 +1,113 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{condvar_broadcast, condvar_create, condvar_timedwait, condvar_wait};
+use user_lib::{exit, get_time, sleep_blocking, thread_create, waittid};
+use user_lib::{mutex_blocking_create, mutex_lock, mutex_timedlock, mutex_trylock, mutex_unlock};
+use user_lib::{semaphore_create, semaphore_timeddown, semaphore_trydown, semaphore_up};
+
+/// 测试 try/限时版本的互斥锁、信号量和条件变量操作，以及条件变量的 broadcast：
+/// 拿不到资源时 try 版本立即返回，限时版本超时后返回 -ETIMEDOUT 并离开等待队列，
+/// 在期限前释放的资源能被限时等待者拿到。输出 Test sync timeout OK! 就算正确。
+
+const EAGAIN: isize = 11;
+const EBUSY: isize = 16;
+const ETIMEDOUT: isize = 110;
+const MUTEX_ID: usize = 0;
+const SEM_ID: usize = 0;
+const CONDVAR_ID: usize = 0;
+const WAITERS: usize = 3;
+
+static mut READY: bool = false;
+
+fn holder() -> ! {
+    mutex_lock(MUTEX_ID);
+    sleep_blocking(20);
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+fn poster() -> ! {
+    sleep_blocking(20);
+    semaphore_up(SEM_ID);
+    exit(0)
+}
+
+unsafe fn waiter() -> ! {
+    mutex_lock(MUTEX_ID);
+    while !READY {
+        condvar_wait(CONDVAR_ID, MUTEX_ID);
+    }
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_ID);
+    assert_eq!(semaphore_create(0) as usize, SEM_ID);
+    assert_eq!(condvar_create() as usize, CONDVAR_ID);
+
+    // mutex
+    assert_eq!(mutex_trylock(MUTEX_ID), 0);
+    assert_eq!(mutex_trylock(MUTEX_ID), -EBUSY);
+    let start = get_time();
+    assert_eq!(mutex_timedlock(MUTEX_ID, (start + 50) as usize), -ETIMEDOUT);
+    assert!(get_time() >= start + 50);
+    mutex_unlock(MUTEX_ID);
+    let tid = thread_create(holder as usize, 0);
+    sleep_blocking(5);
+    let start = get_time();
+    assert_eq!(mutex_timedlock(MUTEX_ID, (start + 1000) as usize), 0);
+    assert!(get_time() < start + 1000);
+    mutex_unlock(MUTEX_ID);
+    assert_eq!(waittid(tid as usize), 0);
+
+    // semaphore, the unit given up by a timed out waiter is left for others
+    assert_eq!(semaphore_trydown(SEM_ID), -EAGAIN);
+    let start = get_time();
+    assert_eq!(
+        semaphore_timeddown(SEM_ID, (start + 50) as usize),
+        -ETIMEDOUT
+    );
+    assert!(get_time() >= start + 50);
+    semaphore_up(SEM_ID);
+    assert_eq!(semaphore_trydown(SEM_ID), 0);
+    assert_eq!(semaphore_trydown(SEM_ID), -EAGAIN);
+    let tid = thread_create(poster as usize, 0);
+    let start = get_time();
+    assert_eq!(semaphore_timeddown(SEM_ID, (start + 1000) as usize), 0);
+    assert!(get_time() < start + 1000);
+    assert_eq!(waittid(tid as usize), 0);
+
+    // condvar
+    mutex_lock(MUTEX_ID);
+    let start = get_time();
+    assert_eq!(
+        condvar_timedwait(CONDVAR_ID, MUTEX_ID, (start + 50) as usize),
+        -ETIMEDOUT
+    );
+    assert!(get_time() >= start + 50);
+    // the mutex is held again after the timeout
+    assert_eq!(mutex_trylock(MUTEX_ID), -EBUSY);
+    mutex_unlock(MUTEX_ID);
+    let mut tids = [0; WAITERS];
+    for tid in tids.iter_mut() {
+        *tid = thread_create(waiter as usize, 0);
+    }
+    sleep_blocking(10);
+    mutex_lock(MUTEX_ID);
+    unsafe {
+        READY = true;
+    }
+    condvar_broadcast(CONDVAR_ID);
+    mutex_unlock(MUTEX_ID);
+    for tid in tids.iter() {
+        assert_eq!(waittid(*tid as usize), 0);
+    }
+    println!("Test sync timeout OK!");
+    0
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::thread::{self, tls_base, TLS_SIZE};
+use user_lib::{gettid, sleep_blocking, yield_};
+use user_lib::{thread_create, thread_detach, thread_exit, waittid};
+
+/// 测试线程退出码、分离和 TLS：waittid 取得 thread_exit 的退出码；分离的线程不能再被等待；
+/// 每个线程有自己清零的 TLS 区域，`thread::spawn` 的 join 取回闭包的返回值。
+/// 输出 Test thread spawn OK! 就算正确。
+
+const THREADS: usize = 5;
+
+fn exiter(code: usize) -> ! {
+    thread_exit(code as i32)
+}
+
+fn sleeper() -> ! {
+    sleep_blocking(10);
+    thread_exit(0)
+}
+
+/// 把 tid 写进自己的 TLS，让出 CPU 后读回
+fn tls_check() -> usize {
+    let base = tls_base();
+    let slot = base as *mut usize;
+    unsafe {
+        assert!((0..TLS_SIZE / 8).all(|i| *slot.add(i) == 0));
+        *slot = gettid() as usize;
+        for _ in 0..10 {
+            yield_();
+        }
+        assert_eq!(*slot, gettid() as usize);
+    }
+    base
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // exit codes
+    let tid = thread_create(exiter as usize, 42);
+    assert_eq!(waittid(tid as usize), 42);
+
+    // a detached thread cannot be waited for
+    let tid = thread_create(sleeper as usize, 0);
+    assert_eq!(thread_detach(tid as usize), 0);
+    assert_eq!(waittid(tid as usize), -1);
+    assert_eq!(thread_detach(gettid() as usize), -1);
+    sleep_blocking(20);
+
+    // tls and spawn
+    assert_ne!(tls_base(), 0);
+    let handles: Vec<_> = (0..THREADS)
+        .map(|i| thread::spawn(move || (i * i, tls_check())))
+        .collect();
+    let mut bases: Vec<_> = handles
+        .into_iter()
+        .enumerate()
+        .map(|(i, handle)| {
+            let (square, base) = handle.join().unwrap();
+            assert_eq!(square, i * i);
+            base
+        })
+        .collect();
+    bases.push(tls_base());
+    bases.sort();
+    bases.dedup();
+    assert_eq!(bases.len(), THREADS + 1);
+    let handle = thread::spawn(|| -> usize { thread_exit(7) });
+    assert_eq!(handle.join(), Err(7));
+    thread::spawn(|| sleep_blocking(10)).detach();
+    println!("Test thread spawn OK!");
+    0
+}
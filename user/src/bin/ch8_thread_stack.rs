@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::thread;
+use user_lib::{exit, fork, thread_create_with_stack, waitpid, waittid, ENOMEM};
+use user_lib::{sigaction, SignalAction, SIGSEGV};
+
+/// 测试可配置大小的线程栈：指定大小的栈能容纳超过默认大小的递归，主线程的栈按需增长，
+/// 栈放不下时返回 -ENOMEM；栈溢出碰到保护页时进程因段错误退出（退出码 -2），
+/// 不会写坏相邻线程的栈；即使装有 SIGSEGV 处理函数，栈上放不下信号帧时进程同样
+/// 因段错误退出。输出 Test thread stack OK! 就算正确。
+
+const KIB: usize = 1024;
+const FRAME_SIZE: usize = KIB;
+
+/// 每层至少占用 `FRAME_SIZE` 字节栈，返回值依赖每一层，不会被优化成循环
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; FRAME_SIZE];
+    unsafe {
+        core::ptr::write_volatile(&mut frame[depth % FRAME_SIZE], depth as u8);
+    }
+    if depth == 0 {
+        return 0;
+    }
+    recurse(depth - 1) + unsafe { core::ptr::read_volatile(&frame[depth % FRAME_SIZE]) } as usize
+}
+
+fn expected(depth: usize) -> usize {
+    (1..=depth).map(|i| i % 256).sum()
+}
+
+fn deep(depth: usize) -> ! {
+    exit((recurse(depth) == expected(depth)) as i32)
+}
+
+extern "C" fn segv_handler(_signum: usize) {
+    println!("Should not reach here!");
+    exit(1)
+}
+
+/// Run a thread overflowing its stack in a new process, which is expected to
+/// die of the fault, and return the exit code of the process
+fn overflow(handled: bool) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        if handled {
+            let action = SignalAction {
+                handler: segv_handler as usize,
+                ..SignalAction::default()
+            };
+            assert_eq!(sigaction(SIGSEGV, Some(&action), None), 0);
+        }
+        let tid = thread_create_with_stack(deep as usize, 64, 16 * KIB);
+        waittid(tid as usize);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 256 KiB stack for 128 KiB of frames, far beyond the default 8 KiB
+    let tid = thread_create_with_stack(deep as usize, 128, 256 * KIB);
+    assert!(tid > 0);
+    assert_eq!(waittid(tid as usize), 1);
+    let handle = thread::spawn_with_stack(64 * KIB, || recurse(32));
+    assert_eq!(handle.join(), Ok(expected(32)));
+    // the last page-aligned size has no room for the guard page and TLS area
+    for size in [usize::MAX, usize::MAX - 4 * KIB + 1] {
+        assert_eq!(thread_create_with_stack(deep as usize, 0, size), -ENOMEM);
+    }
+
+    // the main stack grows on demand
+    assert_eq!(recurse(512), expected(512));
+
+    // a thread overflowing its stack runs into the guard page, a handler
+    // cannot run without room for its signal frame
+    assert_eq!(overflow(false), -2);
+    assert_eq!(overflow(true), -2);
+    println!("Test thread stack OK!");
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, getpid, ioctl, pipe, tcgetattr, tcgetpgrp, tcsetattr, tcsetpgrp, TtyMode, STDIN, STDOUT,
+    TCGETS,
+};
+
+/// 测试终端控制：模式与前台进程可以设置并读回，退出前恢复原来的模式，
+/// 对管道 ioctl 返回 -25 (ENOTTY)。输出 Test tty OK! 就算正确。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 终端模式是全局的，从 shell 启动时不一定是开机时的模式
+    let mut old_mode = TtyMode::empty();
+    assert_eq!(tcgetattr(STDIN, &mut old_mode), 0);
+    assert_eq!(tcsetattr(STDIN, TtyMode::ISIG), 0);
+    let mut mode = TtyMode::empty();
+    assert_eq!(tcgetattr(STDIN, &mut mode), 0);
+    assert_eq!(mode, TtyMode::ISIG);
+
+    let canonical = mode | TtyMode::ICANON | TtyMode::ECHO;
+    assert_eq!(tcsetattr(STDIN, canonical), 0);
+    let mut new_mode = TtyMode::empty();
+    assert_eq!(tcgetattr(STDOUT, &mut new_mode), 0);
+    assert_eq!(new_mode, canonical);
+    assert_eq!(tcsetattr(STDIN, old_mode), 0);
+
+    let old_pid = tcgetpgrp(STDIN);
+    let pid = getpid();
+    assert_eq!(tcsetpgrp(STDIN, pid), 0);
+    assert_eq!(tcgetpgrp(STDIN), pid);
+    assert_eq!(tcsetpgrp(STDIN, old_pid), 0);
+
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let mut bits = 0u32;
+    assert_eq!(
+        ioctl(pipe_fd[0], TCGETS, &mut bits as *mut u32 as usize),
+        -25
+    );
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("Test tty OK!");
+    0
+}
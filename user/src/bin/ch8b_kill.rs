@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{kill, SIGTERM};
+
+/// 用法：ch8b_kill pid [signum]，向进程 pid 发送信号，缺省为 SIGTERM。
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 || argc > 3 {
+        println!("usage: ch8b_kill pid [signum]");
+        return -1;
+    }
+    let pid = match argv[1].parse::<usize>() {
+        Ok(pid) => pid,
+        Err(_) => {
+            println!("ch8b_kill: bad pid {}", argv[1]);
+            return -1;
+        }
+    };
+    let signum = match argv.get(2).map(|arg| arg.parse::<usize>()) {
+        None => SIGTERM,
+        Some(Ok(signum)) => signum,
+        Some(Err(_)) => {
+            println!("ch8b_kill: bad signal {}", argv[2]);
+            return -1;
+        }
+    };
+    if kill(pid, signum) < 0 {
+        println!("ch8b_kill: cannot send signal {} to {}", signum, pid);
+        return -1;
+    }
+    0
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{proc_list, ProcInfo, TaskStatus};
+
+const MAX_PROC: usize = 32;
+
+/// 列出所有存活进程的 pid、父进程、状态、线程数与 CPU 时间，类似 ps。
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut procs = [ProcInfo::new(); MAX_PROC];
+    let total = proc_list(&mut procs);
+    if total < 0 {
+        println!("ps: proc_list failed");
+        return -1;
+    }
+    println!("  PID  PPID STATE    THREADS  TIME(ms) NAME");
+    for info in procs.iter().take(total as usize) {
+        let state = match info.status {
+            TaskStatus::Running => "running",
+            TaskStatus::Ready => "ready",
+            TaskStatus::Blocking => "blocking",
+            TaskStatus::Exited => "exited",
+            TaskStatus::UnInit => "uninit",
+        };
+        println!(
+            "{:>5} {:>5} {:<8} {:>7} {:>9} {}",
+            info.pid,
+            info.ppid,
+            state,
+            info.thread_count,
+            info.time,
+            info.name()
+        );
+    }
+    if total as usize > MAX_PROC {
+        println!("... {} more", total as usize - MAX_PROC);
+    }
+    0
+}
@@ -8,15 +8,14 @@ extern crate alloc;
 extern crate user_lib;
 
 const LF: u8 = 0x0au8;
-const CR: u8 = 0x0du8;
-const DL: u8 = 0x7fu8;
-const BS: u8 = 0x08u8;
 const LINE_START: &str = ">> ";
 
 use alloc::string::String;
 use alloc::vec::Vec;
-use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{
+    close, flush, pipe, read, spawn_with, tcgetattr, tcsetattr, tcsetpgrp, waitpid, OpenFlags,
+    SpawnAction, TtyMode, STDIN,
+};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,140 +73,139 @@ impl ProcessArguments {
     }
 }
 
+/// Read a line edited by the terminal, without the newline.
+/// Returns `None` at the end of input (^D on an empty line).
+fn read_line() -> Option<String> {
+    let mut line: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 128];
+    loop {
+        let len = read(STDIN, &mut buffer);
+        if len <= 0 {
+            return if line.is_empty() {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&line).into_owned())
+            };
+        }
+        line.extend_from_slice(&buffer[..len as usize]);
+        if line.last() == Some(&LF) {
+            line.pop();
+            return Some(String::from_utf8_lossy(&line).into_owned());
+        }
+    }
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
-    let mut line: String = String::new();
-    print!("{}", LINE_START);
+    // the terminal edits and echoes the lines for us, the children get the
+    // mode the shell has been started with
+    let mut child_mode = TtyMode::empty();
+    tcgetattr(STDIN, &mut child_mode);
+    let shell_mode = TtyMode::ISIG | TtyMode::ICANON | TtyMode::ECHO;
+    tcsetattr(STDIN, shell_mode);
     loop {
-        let c = getchar();
-        match c {
-            LF | CR => {
+        print!("{}", LINE_START);
+        flush();
+        let line = match read_line() {
+            Some(line) => line,
+            None => {
                 println!("");
-                if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
-                    let process_arguments_list: Vec<_> = splited
-                        .iter()
-                        .map(|&cmd| ProcessArguments::new(cmd))
-                        .collect();
-                    let mut valid = true;
-                    for (i, process_args) in process_arguments_list.iter().enumerate() {
-                        if i == 0 {
-                            if !process_args.output.is_empty() {
-                                valid = false;
-                            }
-                        } else if i == process_arguments_list.len() - 1 {
-                            if !process_args.input.is_empty() {
-                                valid = false;
-                            }
-                        } else if !process_args.output.is_empty() || !process_args.input.is_empty()
-                        {
-                            valid = false;
-                        }
-                    }
-                    if process_arguments_list.len() == 1 {
-                        valid = true;
+                continue;
+            }
+        };
+        if !line.is_empty() {
+            let splited: Vec<_> = line.as_str().split('|').collect();
+            let process_arguments_list: Vec<_> = splited
+                .iter()
+                .map(|&cmd| ProcessArguments::new(cmd))
+                .collect();
+            let mut valid = true;
+            for (i, process_args) in process_arguments_list.iter().enumerate() {
+                if i == 0 {
+                    if !process_args.output.is_empty() {
+                        valid = false;
                     }
-                    if !valid {
-                        println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
-                    } else {
-                        // create pipes
-                        let mut pipes_fd: Vec<[usize; 2]> = Vec::new();
-                        if !process_arguments_list.is_empty() {
-                            for _ in 0..process_arguments_list.len() - 1 {
-                                let mut pipe_fd = [0usize; 2];
-                                pipe(&mut pipe_fd);
-                                pipes_fd.push(pipe_fd);
-                            }
-                        }
-                        let mut children: Vec<_> = Vec::new();
-                        for (i, process_argument) in process_arguments_list.iter().enumerate() {
-                            let pid = fork();
-                            if pid == 0 {
-                                let input = &process_argument.input;
-                                let output = &process_argument.output;
-                                let args_copy = &process_argument.args_copy;
-                                let args_addr = &process_argument.args_addr;
-                                // redirect input
-                                if !input.is_empty() {
-                                    let input_fd = open(input.as_str(), OpenFlags::RDONLY);
-                                    if input_fd == -1 {
-                                        println!("Error when opening file {}", input);
-                                        return -4;
-                                    }
-                                    let input_fd = input_fd as usize;
-                                    close(0);
-                                    assert_eq!(dup(input_fd), 0);
-                                    close(input_fd);
-                                }
-                                // redirect output
-                                if !output.is_empty() {
-                                    let output_fd = open(
-                                        output.as_str(),
-                                        OpenFlags::CREATE | OpenFlags::WRONLY,
-                                    );
-                                    if output_fd == -1 {
-                                        println!("Error when opening file {}", output);
-                                        return -4;
-                                    }
-                                    let output_fd = output_fd as usize;
-                                    close(1);
-                                    assert_eq!(dup(output_fd), 1);
-                                    close(output_fd);
-                                }
-                                // receive input from the previous process
-                                if i > 0 {
-                                    close(0);
-                                    let read_end = pipes_fd.get(i - 1).unwrap()[0];
-                                    assert_eq!(dup(read_end), 0);
-                                }
-                                // send output to the next process
-                                if i < process_arguments_list.len() - 1 {
-                                    close(1);
-                                    let write_end = pipes_fd.get(i).unwrap()[1];
-                                    assert_eq!(dup(write_end), 1);
-                                }
-                                // close all pipe ends inherited from the parent process
-                                for pipe_fd in pipes_fd.iter() {
-                                    close(pipe_fd[0]);
-                                    close(pipe_fd[1]);
-                                }
-                                // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
-                                    println!("Error when executing!");
-                                    return -4;
-                                }
-                                unreachable!();
-                            } else {
-                                children.push(pid);
-                            }
-                        }
-                        for pipe_fd in pipes_fd.iter() {
-                            close(pipe_fd[0]);
-                            close(pipe_fd[1]);
-                        }
-                        let mut exit_code: i32 = 0;
-                        for pid in children.into_iter() {
-                            let exit_pid = waitpid(pid as usize, &mut exit_code);
-                            assert_eq!(pid, exit_pid);
-                            //println!("Shell: Process {} exited with code {}", pid, exit_code);
-                        }
+                } else if i == process_arguments_list.len() - 1 {
+                    if !process_args.input.is_empty() {
+                        valid = false;
                     }
-                    line.clear();
+                } else if !process_args.output.is_empty() || !process_args.input.is_empty() {
+                    valid = false;
                 }
-                print!("{}", LINE_START);
             }
-            BS | DL => {
-                if !line.is_empty() {
-                    print!("{}", BS as char);
-                    print!(" ");
-                    print!("{}", BS as char);
-                    line.pop();
-                }
+            if process_arguments_list.len() == 1 {
+                valid = true;
             }
-            _ => {
-                print!("{}", c as char);
-                line.push(c as char);
+            if !valid {
+                println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
+            } else {
+                // create pipes
+                let mut pipes_fd: Vec<[usize; 2]> = Vec::new();
+                if !process_arguments_list.is_empty() {
+                    for _ in 0..process_arguments_list.len() - 1 {
+                        let mut pipe_fd = [0usize; 2];
+                        pipe(&mut pipe_fd);
+                        pipes_fd.push(pipe_fd);
+                    }
+                }
+                tcsetattr(STDIN, child_mode);
+                let mut children: Vec<_> = Vec::new();
+                for (i, process_argument) in process_arguments_list.iter().enumerate() {
+                    let input = &process_argument.input;
+                    let output = &process_argument.output;
+                    let args_copy = &process_argument.args_copy;
+                    let args_addr = &process_argument.args_addr;
+                    let mut actions: Vec<SpawnAction> = Vec::new();
+                    // redirect input
+                    if !input.is_empty() {
+                        actions.push(SpawnAction::open(input.as_str(), OpenFlags::RDONLY, 0));
+                    }
+                    // redirect output
+                    if !output.is_empty() {
+                        actions.push(SpawnAction::open(
+                            output.as_str(),
+                            OpenFlags::CREATE | OpenFlags::WRONLY,
+                            1,
+                        ));
+                    }
+                    // receive input from the previous process
+                    if i > 0 {
+                        actions.push(SpawnAction::dup2(pipes_fd[i - 1][0], 0));
+                    }
+                    // send output to the next process
+                    if i < process_arguments_list.len() - 1 {
+                        actions.push(SpawnAction::dup2(pipes_fd[i][1], 1));
+                    }
+                    // close all pipe ends inherited from the shell
+                    for pipe_fd in pipes_fd.iter() {
+                        actions.push(SpawnAction::close(pipe_fd[0]));
+                        actions.push(SpawnAction::close(pipe_fd[1]));
+                    }
+                    // create the new application without forking the shell
+                    let pid = spawn_with(args_copy[0].as_str(), args_addr.as_slice(), &actions);
+                    if pid == -1 {
+                        println!("Error when executing!");
+                        continue;
+                    }
+                    children.push(pid);
+                }
+                for pipe_fd in pipes_fd.iter() {
+                    close(pipe_fd[0]);
+                    close(pipe_fd[1]);
+                }
+                // ^C goes to the last process of the pipeline
+                if let Some(&pid) = children.last() {
+                    tcsetpgrp(STDIN, pid);
+                }
+                let mut exit_code: i32 = 0;
+                for pid in children.into_iter() {
+                    let exit_pid = waitpid(pid as usize, &mut exit_code);
+                    assert_eq!(pid, exit_pid);
+                    //println!("Shell: Process {} exited with code {}", pid, exit_code);
+                }
+                tcsetpgrp(STDIN, -1);
+                tcsetattr(STDIN, shell_mode);
             }
         }
     }
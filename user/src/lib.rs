@@ -6,7 +6,9 @@
 #[macro_use]
 pub mod console;
 mod lang_items;
+pub mod sync;
 mod syscall;
+pub mod thread;
 
 extern crate alloc;
 extern crate core;
@@ -16,14 +18,45 @@ extern crate bitflags;
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
 pub use console::{flush, STDIN, STDOUT};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::AtomicU32;
 pub use syscall::*;
 
-const USER_HEAP_SIZE: usize = 16384;
+/// The heap grows by this many bytes at least
+const USER_HEAP_GROW_SIZE: usize = 16384;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// A heap starting empty and growing through sbrk when it runs out of memory
+struct GrowingHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        loop {
+            if let Ok(ptr) = heap.alloc(layout) {
+                return ptr.as_ptr();
+            }
+            // room for a free block of the size of the layout at its alignment
+            let size = 2 * layout
+                .size()
+                .max(layout.align())
+                .max(USER_HEAP_GROW_SIZE)
+                .next_power_of_two();
+            let start = sbrk(size as isize);
+            if start < 0 {
+                return core::ptr::null_mut();
+            }
+            heap.add_to_heap(start as usize, start as usize + size);
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowingHeap = GrowingHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -48,10 +81,6 @@ fn clear_bss() {
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     clear_bss();
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
@@ -82,9 +111,14 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const APPEND = 1 << 11;
     }
 }
 
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct TimeVal {
@@ -104,6 +138,7 @@ pub enum TaskStatus {
     Ready,
     Running,
     Exited,
+    Blocking,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -114,6 +149,56 @@ pub struct SyscallInfo {
 
 const MAX_SYSCALL_NUM: usize = 500;
 
+const SPAWN_CLOSE: usize = 0;
+const SPAWN_DUP2: usize = 1;
+const SPAWN_OPEN: usize = 2;
+
+/// A file action applied in the child of `spawn_with` before it starts,
+/// like `posix_spawn_file_actions_t`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SpawnAction {
+    kind: usize,
+    fd: usize,
+    src_fd: usize,
+    path: *const u8,
+    flags: u32,
+}
+
+impl SpawnAction {
+    /// Close `fd`, nothing happens if it is not open
+    pub fn close(fd: usize) -> Self {
+        Self {
+            kind: SPAWN_CLOSE,
+            fd,
+            src_fd: 0,
+            path: core::ptr::null(),
+            flags: 0,
+        }
+    }
+    /// Make `fd` a copy of `src_fd`
+    pub fn dup2(src_fd: usize, fd: usize) -> Self {
+        Self {
+            kind: SPAWN_DUP2,
+            fd,
+            src_fd,
+            path: core::ptr::null(),
+            flags: 0,
+        }
+    }
+    /// Open `path` with `flags` as `fd`, `path` has to end with '\0'
+    pub fn open(path: &str, flags: OpenFlags, fd: usize) -> Self {
+        Self {
+            kind: SPAWN_OPEN,
+            fd,
+            src_fd: 0,
+            path: path.as_ptr(),
+            flags: flags.bits,
+        }
+    }
+}
+
+#[repr(C)]
 #[derive(Debug)]
 pub struct TaskInfo {
     pub status: TaskStatus,
@@ -131,6 +216,43 @@ impl TaskInfo {
     }
 }
 
+pub const PROC_NAME_LEN: usize = 32;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ProcInfo {
+    pub pid: usize,
+    /// Pid of the parent, 0 if there is none
+    pub ppid: usize,
+    pub status: TaskStatus,
+    pub thread_count: usize,
+    /// CPU time taken by all the threads, in ms
+    pub time: usize,
+    pub name: [u8; PROC_NAME_LEN],
+}
+
+impl ProcInfo {
+    pub fn new() -> Self {
+        ProcInfo {
+            pid: 0,
+            ppid: 0,
+            status: TaskStatus::UnInit,
+            thread_count: 0,
+            time: 0,
+            name: [0; PROC_NAME_LEN],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(PROC_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Stat {
@@ -174,7 +296,149 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Local modes of the terminal
+    pub struct TtyMode: u32 {
+        /// ^C sends SIGINT to the foreground process
+        const ISIG = 0o1;
+        /// Input is read line by line, after editing
+        const ICANON = 0o2;
+        /// Input is echoed back
+        const ECHO = 0o10;
+    }
+}
+
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+
+bitflags! {
+    /// The kind of a mutex, ERRORCHECK and RECURSIVE cannot go together
+    pub struct MutexFlags: u32 {
+        /// Waiters sleep instead of yielding and trying again
+        const BLOCKING = 1 << 0;
+        /// A relock by the owner returns -EDEADLK, only the owner may unlock
+        const ERRORCHECK = 1 << 1;
+        /// The owner may lock it again and has to unlock it as many times,
+        /// only the owner may unlock
+        const RECURSIVE = 1 << 2;
+        /// Unlocked when the owner exits holding it, the next locker gets
+        /// -EOWNERDEAD
+        const ROBUST = 1 << 3;
+    }
+}
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGABRT: usize = 6;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+
+/// How a signal is handled, `handler` is the address of the handler or
+/// `SIG_DFL`/`SIG_IGN`. The signals in `mask` and the signal itself are
+/// blocked while the handler runs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: SignalFlags,
+    /// Where the handler returns to, filled in by `sigaction`
+    pub restorer: usize,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+            restorer: 0,
+        }
+    }
+}
+
+// a returning handler leaves the signal frame pushed by the kernel on the top
+// of the stack, sigreturn is called with that sp
+core::arch::global_asm!(
+    ".globl __sigreturn_trampoline",
+    "__sigreturn_trampoline:",
+    "li a7, 139",
+    "ecall",
+);
+
+extern "C" {
+    fn __sigreturn_trampoline();
+}
+
+/// Operation not permitted
+pub const EPERM: isize = 1;
+/// No such process
+pub const ESRCH: isize = 3;
+/// Try again
+pub const EAGAIN: isize = 11;
+/// Out of memory
+pub const ENOMEM: isize = 12;
+/// Resource deadlock would occur
+pub const EDEADLK: isize = 35;
+/// Function not implemented
+pub const ENOSYS: isize = 38;
+/// Owner died
+pub const EOWNERDEAD: isize = 130;
+
 const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: usize = 0x200;
 
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_openat(AT_FDCWD as usize, path, flags.bits, OpenFlags::RDWR.bits)
@@ -207,6 +471,75 @@ pub fn fstat(fd: usize, st: &Stat) -> isize {
     sys_fstat(fd, st)
 }
 
+/// Write all cached data of the file system back to the disk
+pub fn sync() -> isize {
+    sys_sync()
+}
+
+/// Write the cached data of `fd` back to the disk
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread(fd, buf, offset)
+}
+
+pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
+
+pub fn tcgetattr(fd: usize, mode: &mut TtyMode) -> isize {
+    let mut bits = 0u32;
+    let ret = sys_ioctl(fd, TCGETS, &mut bits as *mut u32 as usize);
+    *mode = TtyMode::from_bits_truncate(bits);
+    ret
+}
+
+pub fn tcsetattr(fd: usize, mode: TtyMode) -> isize {
+    sys_ioctl(fd, TCSETS, &mode.bits() as *const u32 as usize)
+}
+
+/// Pid of the foreground process of the terminal, -1 for none
+pub fn tcgetpgrp(fd: usize) -> isize {
+    let mut pid = -1isize;
+    let ret = sys_ioctl(fd, TIOCGPGRP, &mut pid as *mut isize as usize);
+    if ret < 0 {
+        ret
+    } else {
+        pid
+    }
+}
+
+/// Make `pid` get the ^C of the terminal, -1 for no process
+pub fn tcsetpgrp(fd: usize, pid: isize) -> isize {
+    sys_ioctl(fd, TIOCSPGRP, &pid as *const isize as usize)
+}
+
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    sys_pwrite(fd, buf, offset)
+}
+
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdirat(AT_FDCWD as usize, path, 0)
+}
+
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD as usize, path, AT_REMOVEDIR)
+}
+
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
+
 pub fn mail_read(buf: &mut [u8]) -> isize {
     sys_mail_read(buf)
 }
@@ -292,8 +625,23 @@ pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+/// Move the program break to `addr`, returns the new one
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+/// Move the program break by `increment` bytes, returns the old one or -1
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+
 pub fn spawn(path: &str) -> isize {
-    sys_spawn(path)
+    sys_spawn(path, &[path.as_ptr(), core::ptr::null()], &[])
+}
+/// Create a child process running `path` with the null-terminated `args`,
+/// `actions` are applied to its fd table in order before it starts
+pub fn spawn_with(path: &str, args: &[*const u8], actions: &[SpawnAction]) -> isize {
+    sys_spawn(path, args, actions)
 }
 
 pub fn dup(fd: usize) -> isize {
@@ -304,15 +652,68 @@ pub fn pipe(pipe_fd: &mut [usize]) -> isize {
 }
 
 pub fn task_info(info: &TaskInfo) -> isize {
-    sys_task_info(info)
+    sys_task_info(info, 0)
+}
+pub fn process_info(info: &TaskInfo) -> isize {
+    sys_task_info(info, 1)
+}
+pub fn proc_list(buf: &mut [ProcInfo]) -> isize {
+    sys_proc_list(buf)
+}
+pub fn kill(pid: usize, signum: usize) -> isize {
+    sys_kill(pid, signum)
+}
+/// Set how signal `signum` is handled, the old way is returned in `old_action`
+pub fn sigaction(
+    signum: usize,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    let action = action.map(|action| SignalAction {
+        restorer: __sigreturn_trampoline as usize,
+        ..*action
+    });
+    sys_sigaction(
+        signum,
+        action
+            .as_ref()
+            .map_or(core::ptr::null(), |action| action as *const _),
+        old_action.map_or(core::ptr::null_mut(), |action| action as *mut _),
+    )
+}
+/// Set the signal mask, returns the old one
+pub fn sigprocmask(mask: SignalFlags) -> SignalFlags {
+    SignalFlags::from_bits_truncate(sys_sigprocmask(mask.bits()) as u32)
+}
+/// What an unknown syscall does: return -ENOSYS if `kill` is false, else
+/// terminate the process with exit code -4
+pub fn set_syscall_policy(kill: bool) -> isize {
+    sys_set_syscall_policy(kill as usize)
 }
 
 pub fn thread_create(entry: usize, arg: usize) -> isize {
-    sys_thread_create(entry, arg)
+    sys_thread_create(entry, arg, 0)
+}
+/// Create a thread on a user stack of `stack_size` bytes rounded up to pages,
+/// or of the default size if it is 0. Returns -ENOMEM if there is no room for
+/// the stack.
+pub fn thread_create_with_stack(entry: usize, arg: usize, stack_size: usize) -> isize {
+    sys_thread_create(entry, arg, stack_size)
 }
 pub fn gettid() -> isize {
     sys_gettid()
 }
+/// Exit the current thread with `exit_code` for `waittid`, the main thread
+/// exiting ends the whole process
+pub fn thread_exit(exit_code: i32) -> ! {
+    console::flush();
+    sys_thread_exit(exit_code);
+}
+/// Detach thread `tid`, which is reaped when it exits and cannot be waited
+/// for by `waittid` any more
+pub fn thread_detach(tid: usize) -> isize {
+    sys_thread_detach(tid)
+}
 pub fn waittid(tid: usize) -> isize {
     loop {
         match sys_waittid(tid) {
@@ -325,16 +726,31 @@ pub fn waittid(tid: usize) -> isize {
 }
 
 pub fn mutex_create() -> isize {
-    sys_mutex_create(false)
+    sys_mutex_create(MutexFlags::empty().bits)
 }
 pub fn mutex_blocking_create() -> isize {
-    sys_mutex_create(true)
+    sys_mutex_create(MutexFlags::BLOCKING.bits)
+}
+/// Create a mutex of the kind in `flags`, -EINVAL for invalid `flags`
+pub fn mutex_create_with(flags: MutexFlags) -> isize {
+    sys_mutex_create(flags.bits)
 }
 pub fn mutex_lock(mutex_id: usize) -> isize {
     sys_mutex_lock(mutex_id)
 }
-pub fn mutex_unlock(mutex_id: usize) {
-    sys_mutex_unlock(mutex_id);
+/// Return -EBUSY instead of waiting if the mutex is held
+pub fn mutex_trylock(mutex_id: usize) -> isize {
+    sys_mutex_trylock(mutex_id)
+}
+/// Return -ETIMEDOUT if the mutex is still held when `get_time()` reaches
+/// `expire_ms`
+pub fn mutex_timedlock(mutex_id: usize, expire_ms: usize) -> isize {
+    sys_mutex_timedlock(mutex_id, expire_ms)
+}
+/// Return -EPERM if the mutex is not locked, or if it checks its owner and
+/// the current thread does not hold it
+pub fn mutex_unlock(mutex_id: usize) -> isize {
+    sys_mutex_unlock(mutex_id)
 }
 pub fn semaphore_create(res_count: usize) -> isize {
     sys_semaphore_create(res_count)
@@ -348,12 +764,65 @@ pub fn enable_deadlock_detect(enabled: bool) -> isize {
 pub fn semaphore_down(sem_id: usize) -> isize {
     sys_semaphore_down(sem_id)
 }
+/// Return -EAGAIN instead of waiting if no unit of the semaphore is left
+pub fn semaphore_trydown(sem_id: usize) -> isize {
+    sys_semaphore_trydown(sem_id)
+}
+/// Return -ETIMEDOUT if no unit has been left when `get_time()` reaches
+/// `expire_ms`
+pub fn semaphore_timeddown(sem_id: usize, expire_ms: usize) -> isize {
+    sys_semaphore_timeddown(sem_id, expire_ms)
+}
 pub fn condvar_create() -> isize {
     sys_condvar_create(0)
 }
 pub fn condvar_signal(condvar_id: usize) {
     sys_condvar_signal(condvar_id);
 }
-pub fn condvar_wait(condvar_id: usize, mutex_id: usize) {
-    sys_condvar_wait(condvar_id, mutex_id);
+/// Wake up all the threads waiting on the condvar
+pub fn condvar_broadcast(condvar_id: usize) {
+    sys_condvar_broadcast(condvar_id);
+}
+pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    sys_condvar_wait(condvar_id, mutex_id)
+}
+/// Return -ETIMEDOUT if not signalled when `get_time()` reaches `expire_ms`,
+/// the mutex is locked again either way
+pub fn condvar_timedwait(condvar_id: usize, mutex_id: usize, expire_ms: usize) -> isize {
+    sys_condvar_timedwait(condvar_id, mutex_id, expire_ms)
+}
+/// Create a rwlock preferring writers: once a writer waits, new readers
+/// queue up behind it. Returns the id of the rwlock.
+pub fn rwlock_create() -> isize {
+    sys_rwlock_create()
+}
+pub fn rwlock_read(rwlock_id: usize) {
+    sys_rwlock_read(rwlock_id);
+}
+pub fn rwlock_write(rwlock_id: usize) {
+    sys_rwlock_write(rwlock_id);
+}
+/// Release the rwlock held for either reading or writing, -EPERM if the
+/// current thread does not hold it
+pub fn rwlock_unlock(rwlock_id: usize) -> isize {
+    sys_rwlock_unlock(rwlock_id)
+}
+/// Create a barrier for `count` threads, returns the id of the barrier
+pub fn barrier_create(count: usize) -> isize {
+    sys_barrier_create(count)
+}
+/// Wait for the other threads to arrive at the barrier, returns 1 to the
+/// last one arriving and 0 to the others
+pub fn barrier_wait(barrier_id: usize) -> isize {
+    sys_barrier_wait(barrier_id)
+}
+/// Sleep if `word` still holds `val`, until woken up or for at most `timeout`
+/// ms, forever if it is negative
+pub fn futex_wait(word: &AtomicU32, val: u32, timeout: isize) -> isize {
+    sys_futex(word, FUTEX_WAIT, val, timeout)
+}
+/// Wake up at most `count` threads sleeping on `word`, returns how many have
+/// been woken up
+pub fn futex_wake(word: &AtomicU32, count: u32) -> isize {
+    sys_futex(word, FUTEX_WAKE, count, 0)
 }
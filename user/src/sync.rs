@@ -0,0 +1,90 @@
+//! Locks in user space on top of futexes, uncontended they only take atomic
+//! instructions and never enter the kernel
+
+use super::{futex_wait, futex_wake};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and threads may be waiting
+const CONTENDED: u32 = 2;
+
+/// A mutex calling the futex only when a thread has to wait or be woken up
+pub struct Mutex {
+    state: AtomicU32,
+}
+
+impl Mutex {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+
+    /// Take the lock if it is free, returns false if it is held
+    pub fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        // marked as contended before sleeping, so that the unlock wakes us up
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED, -1);
+        }
+    }
+
+    pub fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+/// A condvar used with a [`Mutex`]
+pub struct Condvar {
+    /// Bumped by every signal and broadcast, a waiter finds a wakeup it has
+    /// missed before sleeping by it
+    seq: AtomicU32,
+    /// With no waiters signal and broadcast do not enter the kernel
+    waiters: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    /// Sleep with `mutex` unlocked and lock it again when woken up, the
+    /// wakeup may be spurious
+    pub fn wait(&self, mutex: &Mutex) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let seq = self.seq.load(Ordering::SeqCst);
+        mutex.unlock();
+        futex_wait(&self.seq, seq, -1);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        mutex.lock();
+    }
+
+    pub fn signal(&self) {
+        self.notify(1);
+    }
+
+    pub fn broadcast(&self) {
+        self.notify(u32::MAX);
+    }
+
+    fn notify(&self, count: u32) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            futex_wake(&self.seq, count);
+        }
+    }
+}
@@ -1,17 +1,31 @@
-use crate::TaskInfo;
+use crate::{ProcInfo, SignalAction, SpawnAction, TaskInfo};
 
 use super::{Stat, TimeVal};
+use core::sync::atomic::AtomicU32;
 
+pub const SYSCALL_GETCWD: usize = 17;
+pub const SYSCALL_MKDIRAT: usize = 34;
+pub const SYSCALL_CHDIR: usize = 49;
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
+pub const SYSCALL_LSEEK: usize = 62;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
+pub const SYSCALL_PREAD: usize = 67;
+pub const SYSCALL_PWRITE: usize = 68;
 pub const SYSCALL_UNLINKAT: usize = 35;
 pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_FSTAT: usize = 80;
+pub const SYSCALL_SYNC: usize = 81;
+pub const SYSCALL_FSYNC: usize = 82;
 pub const SYSCALL_EXIT: usize = 93;
+pub const SYSCALL_FUTEX: usize = 98;
 pub const SYSCALL_SLEEP: usize = 101;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_KILL: usize = 129;
+pub const SYSCALL_SIGACTION: usize = 134;
+pub const SYSCALL_SIGPROCMASK: usize = 135;
+pub const SYSCALL_SIGRETURN: usize = 139;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
 pub const SYSCALL_GETPID: usize = 172;
 pub const SYSCALL_GETTID: usize = 178;
@@ -19,18 +33,24 @@ pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
+pub const SYSCALL_BRK: usize = 214;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
+pub const SYSCALL_IOCTL: usize = 29;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_PROC_LIST: usize = 411;
+pub const SYSCALL_SET_SYSCALL_POLICY: usize = 412;
+pub const SYSCALL_SBRK: usize = 413;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
 pub const SYSCALL_MUTEX_LOCK: usize = 464;
+pub const SYSCALL_MUTEX_TRYLOCK: usize = 465;
 pub const SYSCALL_MUTEX_UNLOCK: usize = 466;
 pub const SYSCALL_SEMAPHORE_CREATE: usize = 467;
 pub const SYSCALL_SEMAPHORE_UP: usize = 468;
@@ -39,6 +59,19 @@ pub const SYSCALL_SEMAPHORE_DOWN: usize = 470;
 pub const SYSCALL_CONDVAR_CREATE: usize = 471;
 pub const SYSCALL_CONDVAR_SIGNAL: usize = 472;
 pub const SYSCALL_CONDVAR_WAIT: usize = 473;
+pub const SYSCALL_MUTEX_TIMEDLOCK: usize = 474;
+pub const SYSCALL_SEMAPHORE_TRYDOWN: usize = 475;
+pub const SYSCALL_SEMAPHORE_TIMEDDOWN: usize = 476;
+pub const SYSCALL_CONDVAR_BROADCAST: usize = 477;
+pub const SYSCALL_CONDVAR_TIMEDWAIT: usize = 478;
+pub const SYSCALL_RWLOCK_CREATE: usize = 479;
+pub const SYSCALL_RWLOCK_READ: usize = 480;
+pub const SYSCALL_RWLOCK_WRITE: usize = 481;
+pub const SYSCALL_RWLOCK_UNLOCK: usize = 482;
+pub const SYSCALL_BARRIER_CREATE: usize = 483;
+pub const SYSCALL_BARRIER_WAIT: usize = 484;
+pub const SYSCALL_THREAD_EXIT: usize = 485;
+pub const SYSCALL_THREAD_DETACH: usize = 486;
 
 pub fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -99,6 +132,24 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    syscall(SYSCALL_LSEEK, [fd, offset as usize, whence])
+}
+
+pub fn sys_pread(fd: usize, buffer: &mut [u8], offset: usize) -> isize {
+    syscall6(
+        SYSCALL_PREAD,
+        [fd, buffer.as_mut_ptr() as usize, buffer.len(), offset, 0, 0],
+    )
+}
+
+pub fn sys_pwrite(fd: usize, buffer: &[u8], offset: usize) -> isize {
+    syscall6(
+        SYSCALL_PWRITE,
+        [fd, buffer.as_ptr() as usize, buffer.len(), offset, 0, 0],
+    )
+}
+
 pub fn sys_linkat(
     old_dirfd: usize,
     old_path: &str,
@@ -123,10 +174,33 @@ pub fn sys_unlinkat(dirfd: usize, path: &str, flags: usize) -> isize {
     syscall(SYSCALL_UNLINKAT, [dirfd, path.as_ptr() as usize, flags])
 }
 
+pub fn sys_mkdirat(dirfd: usize, path: &str, mode: u32) -> isize {
+    syscall(
+        SYSCALL_MKDIRAT,
+        [dirfd, path.as_ptr() as usize, mode as usize],
+    )
+}
+
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETCWD, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
 pub fn sys_fstat(fd: usize, st: &Stat) -> isize {
     syscall(SYSCALL_FSTAT, [fd, st as *const _ as usize, 0])
 }
 
+pub fn sys_sync() -> isize {
+    syscall(SYSCALL_SYNC, [0, 0, 0])
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    syscall(SYSCALL_FSYNC, [fd, 0, 0])
+}
+
 pub fn sys_mail_read(buffer: &mut [u8]) -> isize {
     syscall(
         SYSCALL_MAIL_READ,
@@ -146,6 +220,20 @@ pub fn sys_exit(exit_code: i32) -> ! {
     panic!("sys_exit never returns!");
 }
 
+pub fn sys_futex(addr: &AtomicU32, op: usize, val: u32, timeout: isize) -> isize {
+    syscall6(
+        SYSCALL_FUTEX,
+        [
+            addr as *const _ as usize,
+            op,
+            val as usize,
+            timeout as usize,
+            0,
+            0,
+        ],
+    )
+}
+
 pub fn sys_sleep(sleep_ms: usize) -> isize {
     syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
 }
@@ -189,24 +277,73 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
-pub fn sys_spawn(path: &str) -> isize {
-    syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+    syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
+
+pub fn sys_spawn(path: &str, args: &[*const u8], actions: &[SpawnAction]) -> isize {
+    syscall6(
+        SYSCALL_SPAWN,
+        [
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            actions.as_ptr() as usize,
+            actions.len(),
+            0,
+            0,
+        ],
+    )
 }
 
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, cmd, arg])
+}
+
 pub fn sys_pipe(pipe: &mut [usize]) -> isize {
     syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
 }
 
-pub fn sys_task_info(info: &TaskInfo) -> isize {
-    syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
+pub fn sys_task_info(info: &TaskInfo, scope: usize) -> isize {
+    syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, scope, 0])
+}
+
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    syscall(SYSCALL_KILL, [pid, signum, 0])
 }
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
-    syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    syscall(
+        SYSCALL_SIGACTION,
+        [signum, action as usize, old_action as usize],
+    )
+}
+
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    syscall(SYSCALL_SIGPROCMASK, [mask as usize, 0, 0])
+}
+
+pub fn sys_set_syscall_policy(policy: usize) -> isize {
+    syscall(SYSCALL_SET_SYSCALL_POLICY, [policy, 0, 0])
+}
+
+pub fn sys_proc_list(buf: &mut [ProcInfo]) -> isize {
+    syscall(SYSCALL_PROC_LIST, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_thread_create(entry: usize, arg: usize, stack_size: usize) -> isize {
+    syscall(SYSCALL_THREAD_CREATE, [entry, arg, stack_size])
 }
 
 pub fn sys_gettid() -> isize {
@@ -217,14 +354,31 @@ pub fn sys_waittid(tid: usize) -> isize {
     syscall(SYSCALL_WAITTID, [tid, 0, 0])
 }
 
-pub fn sys_mutex_create(blocking: bool) -> isize {
-    syscall(SYSCALL_MUTEX_CREATE, [blocking as usize, 0, 0])
+pub fn sys_thread_exit(exit_code: i32) -> ! {
+    syscall(SYSCALL_THREAD_EXIT, [exit_code as usize, 0, 0]);
+    panic!("sys_thread_exit never returns!");
+}
+
+pub fn sys_thread_detach(tid: usize) -> isize {
+    syscall(SYSCALL_THREAD_DETACH, [tid, 0, 0])
+}
+
+pub fn sys_mutex_create(flags: u32) -> isize {
+    syscall(SYSCALL_MUTEX_CREATE, [flags as usize, 0, 0])
 }
 
 pub fn sys_mutex_lock(id: usize) -> isize {
     syscall(SYSCALL_MUTEX_LOCK, [id, 0, 0])
 }
 
+pub fn sys_mutex_trylock(id: usize) -> isize {
+    syscall(SYSCALL_MUTEX_TRYLOCK, [id, 0, 0])
+}
+
+pub fn sys_mutex_timedlock(id: usize, expire_ms: usize) -> isize {
+    syscall(SYSCALL_MUTEX_TIMEDLOCK, [id, expire_ms, 0])
+}
+
 pub fn sys_mutex_unlock(id: usize) -> isize {
     syscall(SYSCALL_MUTEX_UNLOCK, [id, 0, 0])
 }
@@ -245,6 +399,14 @@ pub fn sys_semaphore_down(sem_id: usize) -> isize {
     syscall(SYSCALL_SEMAPHORE_DOWN, [sem_id, 0, 0])
 }
 
+pub fn sys_semaphore_trydown(sem_id: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_TRYDOWN, [sem_id, 0, 0])
+}
+
+pub fn sys_semaphore_timeddown(sem_id: usize, expire_ms: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_TIMEDDOWN, [sem_id, expire_ms, 0])
+}
+
 pub fn sys_condvar_create(_arg: usize) -> isize {
     syscall(SYSCALL_CONDVAR_CREATE, [_arg, 0, 0])
 }
@@ -253,6 +415,38 @@ pub fn sys_condvar_signal(condvar_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_SIGNAL, [condvar_id, 0, 0])
 }
 
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_BROADCAST, [condvar_id, 0, 0])
+}
+
 pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
 }
+
+pub fn sys_condvar_timedwait(condvar_id: usize, mutex_id: usize, expire_ms: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_TIMEDWAIT, [condvar_id, mutex_id, expire_ms])
+}
+
+pub fn sys_rwlock_create() -> isize {
+    syscall(SYSCALL_RWLOCK_CREATE, [0, 0, 0])
+}
+
+pub fn sys_rwlock_read(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_READ, [rwlock_id, 0, 0])
+}
+
+pub fn sys_rwlock_write(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_WRITE, [rwlock_id, 0, 0])
+}
+
+pub fn sys_rwlock_unlock(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_UNLOCK, [rwlock_id, 0, 0])
+}
+
+pub fn sys_barrier_create(count: usize) -> isize {
+    syscall(SYSCALL_BARRIER_CREATE, [count, 0, 0])
+}
+
+pub fn sys_barrier_wait(barrier_id: usize) -> isize {
+    syscall(SYSCALL_BARRIER_WAIT, [barrier_id, 0, 0])
+}
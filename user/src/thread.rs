@@ -0,0 +1,114 @@
+//! Threads in the way of pthread: spawned with a closure whose result is
+//! taken back by a join, and a TLS area of each thread
+
+use super::{thread_create_with_stack, thread_detach, thread_exit, waittid};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+
+/// Size of the TLS area of each thread, the same as in the kernel
+pub const TLS_SIZE: usize = 4096;
+
+/// Start of the TLS area of the current thread, the value of `tp`. The area
+/// is zeroed when the thread is created.
+pub fn tls_base() -> usize {
+    let tp;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) tp);
+    }
+    tp
+}
+
+/// The result of a thread, written by the thread before it exits and taken
+/// by the joiner after that
+struct Packet<T> {
+    result: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// Handle of a thread, which is detached if the handle is dropped unjoined
+pub struct JoinHandle<T> {
+    tid: usize,
+    packet: Arc<Packet<T>>,
+    /// Neither joined nor detached yet
+    joinable: bool,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn tid(&self) -> usize {
+        self.tid
+    }
+
+    /// Wait for the thread to exit and take the result of its closure, or its
+    /// exit code if the closure has not returned, e.g. on a panic or an early exit
+    pub fn join(mut self) -> Result<T, i32> {
+        self.joinable = false;
+        let exit_code = waittid(self.tid);
+        match unsafe { (*self.packet.result.get()).take() } {
+            Some(result) => Ok(result),
+            None => Err(exit_code as i32),
+        }
+    }
+
+    /// Stop caring about the thread, the kernel reaps it when it exits
+    pub fn detach(mut self) {
+        self.joinable = false;
+        thread_detach(self.tid);
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if self.joinable {
+            thread_detach(self.tid);
+        }
+    }
+}
+
+/// Run `f` in a new thread, panics if the thread cannot be created
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_stack(0, f)
+}
+
+/// Like [`spawn`], the new thread runs on a user stack of `stack_size` bytes,
+/// or of the default size if it is 0
+pub fn spawn_with_stack<F, T>(stack_size: usize, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet {
+        result: UnsafeCell::new(None),
+    });
+    let their_packet = Arc::clone(&packet);
+    let main: Box<dyn FnOnce()> = Box::new(move || {
+        let result = f();
+        unsafe {
+            *their_packet.result.get() = Some(result);
+        }
+    });
+    // the closure is a fat pointer, boxed once more it fits in the argument
+    // of the new thread
+    let main = Box::into_raw(Box::new(main));
+    let tid = thread_create_with_stack(thread_start as usize, main as usize, stack_size);
+    if tid < 0 {
+        drop(unsafe { Box::from_raw(main) });
+        panic!("failed to create a thread: {}", tid);
+    }
+    JoinHandle {
+        tid: tid as usize,
+        packet,
+        joinable: true,
+    }
+}
+
+fn thread_start(main: *mut Box<dyn FnOnce()>) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    main();
+    thread_exit(0)
+}